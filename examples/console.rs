@@ -4,7 +4,7 @@ use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tcp_console as console;
-use tcp_console::{Subscription, SubscriptionError};
+use tcp_console::{Subscription, SubscriptionError, TypedSubscription};
 use tokio::{signal, time};
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
@@ -18,6 +18,7 @@ async fn main() -> anyhow::Result<()> {
     let mut console = console::Builder::new()
         .bind_address((Ipv4Addr::LOCALHOST, port))
         .welcome("Welcome to TCP console!")
+        .prompt("console> ")
         .subscribe(Services::Logger, Logger)?
         .subscribe(Services::Exec, Exec)?
         .subscribe(
@@ -28,6 +29,7 @@ async fn main() -> anyhow::Result<()> {
             },
         )?
         .accept_only_localhost()
+        .with_help()
         .build()?;
 
     console.spawn().await?;
@@ -41,7 +43,7 @@ async fn main() -> anyhow::Result<()> {
     //      [Console] will emit a warning,
     tokio::spawn(async move {
         let mut client = console::Client::new(
-            (Ipv4Addr::LOCALHOST, port)
+            (Ipv4Addr::LOCALHOST, port), None
         )
         .await
         .expect("Failed to create client");
@@ -78,10 +80,7 @@ async fn main() -> anyhow::Result<()> {
 
     signal::ctrl_c().await?;
 
-    console.stop();
-
-    // Let the console to actually stop.
-    time::sleep(Duration::from_millis(100)).await;
+    console.shutdown(Duration::from_secs(5)).await?;
 
     Ok(())
 }
@@ -94,18 +93,23 @@ enum Services {
     Unknown,
 }
 
+console::service_id!(Services {
+    Logger => "logger",
+    Exec => "exec",
+    Status => "status",
+    Unknown => "unknown",
+});
+
 struct Logger;
 
+/// Typed messages are decoded by the console, the handler never sees the wire format.
 #[async_trait]
-impl Subscription for Logger {
-    async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
-        let message =
-            bcs::from_bytes::<String>(message.as_ref()).expect("Must deserialize message");
-        debug!("[Logger] request to process a strongly typed message: `{message}`");
-        Ok(None)
-    }
+impl TypedSubscription for Logger {
+    type Request = String;
+    type Response = ();
 
-    async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+    async fn handle(&self, message: String) -> Result<Option<()>, SubscriptionError> {
+        debug!("[Logger] request to process a strongly typed message: `{message}`");
         Ok(None)
     }
 }
@@ -113,15 +117,12 @@ impl Subscription for Logger {
 struct Exec;
 
 #[async_trait]
-impl Subscription for Exec {
-    async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
-        let message =
-            bcs::from_bytes::<String>(message.as_ref()).expect("Must deserialize message");
-        debug!("[Exec] request to process a strongly typed message: `{message}`");
-        Ok(None)
-    }
+impl TypedSubscription for Exec {
+    type Request = String;
+    type Response = ();
 
-    async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+    async fn handle(&self, message: String) -> Result<Option<()>, SubscriptionError> {
+        debug!("[Exec] request to process a strongly typed message: `{message}`");
         Ok(None)
     }
 }
@@ -136,6 +137,10 @@ struct Status {
 
 #[async_trait]
 impl Subscription for Status {
+    fn description(&self) -> Option<&str> {
+        Some("`status` reports connections and health")
+    }
+
     async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
         debug!("[Status] request to process a strongly typed message");
 
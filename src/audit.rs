@@ -0,0 +1,141 @@
+//! Record of every message dispatched to subscriptions, see [Builder::audit](crate::Builder::audit).
+
+use crate::context::Context;
+use crate::history::format_utc;
+use crate::middleware::{Incoming, Middleware};
+use crate::subscription::SubscriptionError;
+use crate::targets::DISPATCH;
+use async_trait::async_trait;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// A message about to be dispatched to subscriptions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub at: SystemTime,
+    pub peer: SocketAddr,
+    /// Identity of the session, as in [Context::identity].
+    pub identity: String,
+    /// Service of a typed message, `None` for free-form text, which is offered to several.
+    pub service: Option<String>,
+    /// Message as redacted by [AuditSink::redact].
+    pub payload: String,
+}
+
+impl Display for AuditRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}: {}",
+            format_utc(self.at),
+            self.peer,
+            self.identity,
+            self.service.as_deref().unwrap_or("text"),
+            self.payload
+        )
+    }
+}
+
+/// Destination of the [AuditRecord]s of a console, see [Builder::audit](crate::Builder::audit).
+pub trait AuditSink: Send + Sync {
+    /// Stores `record`, called before the message is dispatched, so it must be quick.
+    fn record(&self, record: &AuditRecord);
+
+    /// What of `message` is recorded. By default typed payloads, which may carry secrets
+    /// and are not readable anyway, are reduced to their size, and texts are kept whole.
+    fn redact(&self, message: &Incoming) -> String {
+        match message {
+            Incoming::Typed { payload, .. } => format!("<{} bytes>", payload.len()),
+            Incoming::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// [AuditSink] appending records to a file, one line each.
+pub struct FileAudit {
+    file: Mutex<File>,
+}
+
+impl FileAudit {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileAudit {
+    fn record(&self, record: &AuditRecord) {
+        // Payloads are single lines, so that every record takes exactly one.
+        let line = record.to_string().replace(['\r', '\n'], " ");
+        let mut file = self.file.lock().expect("Audit file lock is never poisoned");
+        // A single write, so that records of concurrent consoles do not interleave.
+        if let Err(err) = file.write_all(format!("{line}\n").as_bytes()) {
+            warn!(target: DISPATCH, "Failed to append to the audit log: {err}");
+        }
+    }
+}
+
+/// [Middleware] handing every message to an [AuditSink].
+pub(crate) struct Audit<S>(pub(crate) S);
+
+#[async_trait]
+impl<S: AuditSink> Middleware for Audit<S> {
+    async fn before(&self, context: &Context, message: &Incoming) -> Result<(), SubscriptionError> {
+        let service = match message {
+            Incoming::Typed { service, .. } => Some(service.clone()),
+            Incoming::Text(_) => None,
+        };
+        self.0.record(&AuditRecord {
+            at: SystemTime::now(),
+            peer: context.peer(),
+            identity: context.identity().to_owned(),
+            service,
+            payload: self.0.redact(message),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditRecord, AuditSink, FileAudit};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn file_audit_appends_one_line_per_record() {
+        let path = std::env::temp_dir().join(format!("tcp-console-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let record = AuditRecord {
+            at: UNIX_EPOCH + Duration::from_secs(86_400),
+            peer: SocketAddr::from((Ipv4Addr::LOCALHOST, 4000)),
+            identity: "127.0.0.1".to_owned(),
+            service: None,
+            payload: "restart\nnow".to_owned(),
+        };
+        FileAudit::open(&path).unwrap().record(&record);
+        let record = AuditRecord {
+            service: Some("exec".to_owned()),
+            payload: "<3 bytes>".to_owned(),
+            ..record
+        };
+        FileAudit::open(&path).unwrap().record(&record);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "1970-01-02 00:00:00 UTC 127.0.0.1:4000 127.0.0.1 text: restart now\n\
+             1970-01-02 00:00:00 UTC 127.0.0.1:4000 127.0.0.1 exec: <3 bytes>\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! Shared-secret authentication handshake, see [Builder::require_token](crate::Builder::require_token).
+//!
+//! After the welcome message, or the acknowledgement of a programmatic client, a session must send `AUTH <token>` as a text line.
+//! The console answers [AUTH_OK] and starts dispatching, or [AUTH_FAILED] and closes the session.
+
+use crate::ip_net::IpNet;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Command carrying the token.
+pub(crate) const AUTH_COMMAND: &str = "AUTH";
+/// Answer to a valid token.
+pub(crate) const AUTH_OK: &str = "Authenticated";
+/// Answer to an invalid token, the session is closed afterwards.
+pub(crate) const AUTH_FAILED: &str = "Authentication failed";
+/// Answer to any command sent before authenticating.
+pub(crate) const AUTH_REQUIRED: &str = "Authentication required";
+
+/// Extracts the token from an `AUTH <token>` line.
+pub(crate) fn parse_auth(line: &str) -> Option<&str> {
+    let (command, token) = line.split_once(' ')?;
+    (command == AUTH_COMMAND).then(|| token.trim())
+}
+
+/// Compares tokens in time independent of where they differ.
+pub(crate) fn tokens_match(expected: &str, actual: &str) -> bool {
+    let (expected, actual) = (expected.as_bytes(), actual.as_bytes());
+    if expected.len() != actual.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(actual)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Who a session is, handed to the [Builder::authorizer](crate::Builder::authorizer).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AuthContext {
+    pub peer: SocketAddr,
+    /// Identity commands are recorded under, the peer IP address.
+    pub identity: String,
+    /// Whether the session presented the token required by
+    /// [Builder::require_token](crate::Builder::require_token).
+    pub authenticated: bool,
+    /// End of the elevated access granted via the `elevate <reason>` command,
+    /// see [Builder::elevation](crate::Builder::elevation).
+    pub elevated_until: Option<Instant>,
+}
+
+impl AuthContext {
+    pub(crate) fn new(peer: SocketAddr) -> Self {
+        Self {
+            peer,
+            identity: peer.ip().to_string(),
+            authenticated: false,
+            elevated_until: None,
+        }
+    }
+
+    /// Whether the session currently has elevated access.
+    pub fn is_elevated(&self) -> bool {
+        self.elevated_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+/// Decides whether a session may use a service.
+pub(crate) type Authorizer<Services> = Box<dyn Fn(&AuthContext, &Services) -> bool + Send + Sync>;
+
+/// Per-service access rules configured via [Builder](crate::Builder).
+pub(crate) struct Policy<Services> {
+    pub(crate) authorizer: Option<Authorizer<Services>>,
+    /// Networks individual services are restricted to, see [Builder::restrict_service](crate::Builder::restrict_service).
+    pub(crate) networks: HashMap<Services, Vec<IpNet>>,
+}
+
+impl<Services: Eq + Hash> Policy<Services> {
+    /// Whether `peer` belongs to the networks `service_id` is restricted to, if any.
+    pub(crate) fn reachable(&self, peer: IpAddr, service_id: &Services) -> bool {
+        self.networks
+            .get(service_id)
+            .is_none_or(|networks| networks.iter().any(|network| network.contains(peer)))
+    }
+
+    /// Whether the [Builder::authorizer](crate::Builder::authorizer), if any, allows the session to use `service_id`.
+    pub(crate) fn authorized(&self, auth: &AuthContext, service_id: &Services) -> bool {
+        self.authorizer
+            .as_ref()
+            .is_none_or(|authorizer| authorizer(auth, service_id))
+    }
+}
+
+impl<Services> Default for Policy<Services> {
+    fn default() -> Self {
+        Self {
+            authorizer: None,
+            networks: HashMap::new(),
+        }
+    }
+}
+
+/// Decides whether to grant elevated access for the given reason, and for how long.
+pub(crate) type Approver = Box<dyn Fn(&AuthContext, &str) -> Option<Duration> + Send + Sync>;
@@ -0,0 +1,95 @@
+//! Several typed messages handled in one request, see [Client::send_batch](crate::Client::send_batch).
+//!
+//! A batch travels in a typed frame opening with [MARKER], which neither a message envelope
+//! nor a control frame starts with, and is answered by a single [Reply](crate::console::Reply)
+//! carrying the results of its messages in order.
+
+use crate::codec::Frame;
+use crate::console::{Error, Message, ReplyError};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// First byte of batch frames, control frames open with `0xFF`.
+const MARKER: u8 = 0xFE;
+
+/// Result of each message of a batch, in order.
+pub(crate) type Results = Vec<Result<Option<Bytes>, ReplyError>>;
+
+/// Ordered typed messages handled one after the other by [Console](crate::Console)
+/// and answered at once, see [Client::send_batch](crate::Client::send_batch).
+///
+/// ```
+/// # #[derive(serde::Serialize)]
+/// # enum Services { Pause, Snapshot, Resume }
+/// let batch = tcp_console::Batch::new()
+///     .with(Services::Pause, &())?
+///     .with(Services::Snapshot, &"daily")?
+///     .with(Services::Resume, &())?;
+/// assert_eq!(batch.len(), 3);
+/// # Ok::<(), tcp_console::Error>(())
+/// ```
+pub struct Batch<Services> {
+    messages: Vec<Message<Services>>,
+}
+
+impl<Services> Batch<Services> {
+    pub fn new() -> Self {
+        Self {
+            messages: Vec::new(),
+        }
+    }
+
+    /// Appends a message for `service_id` with any serializable payload.
+    pub fn with(mut self, service_id: Services, message: &impl Serialize) -> Result<Self, Error> {
+        self.messages.push(Message::new(None, service_id, message)?);
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+impl<Services> Default for Batch<Services> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Batch as sent, the ids of its messages are not used.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Envelope<Services> {
+    /// Correlation id of the [Reply](crate::console::Reply) carrying the [Results].
+    pub(crate) id: u64,
+    pub(crate) messages: Vec<Message<Services>>,
+}
+
+impl<Services: Serialize> Envelope<Services> {
+    pub(crate) fn new(id: u64, batch: Batch<Services>) -> Self {
+        Self {
+            id,
+            messages: batch.messages,
+        }
+    }
+
+    pub(crate) fn to_frame(&self) -> Result<Frame, Error> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(bcs::to_bytes(self)?);
+        Ok(Frame::Typed(bytes.into()))
+    }
+}
+
+impl<Services: DeserializeOwned> Envelope<Services> {
+    /// Batch carried by the typed frame `bytes`, `None` if it carries anything else.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Result<Self, bcs::Error>> {
+        match bytes.split_first() {
+            Some((&MARKER, batch)) => Some(bcs::from_bytes(batch)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! A small line editor for the REPL: cursor movement, history with reverse search and completion.
+//!
+//! The terminal is switched to raw mode with `stty` while a line is edited. Without a terminal,
+//! e.g. when input is piped, lines are read as they are.
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Lines of history kept, the oldest are dropped beyond that.
+const MAX_HISTORY: usize = 1000;
+
+/// Outcome of reading a line.
+pub enum Line {
+    Accepted(String),
+    /// Ctrl-C discarded the line.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of the input.
+    Eof,
+}
+
+pub struct Editor {
+    prompt: String,
+    history: Vec<String>,
+    /// File the history is loaded from and appended to.
+    history_file: Option<PathBuf>,
+    /// Words completed at the start of a line, e.g. service names.
+    completions: Vec<String>,
+}
+
+impl Editor {
+    pub fn new(prompt: impl Into<String>, history_file: Option<PathBuf>) -> Self {
+        let history = history_file
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_owned).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let excess = history.len().saturating_sub(MAX_HISTORY);
+
+        Self {
+            prompt: prompt.into(),
+            history: history[excess..].to_vec(),
+            history_file,
+            completions: Vec::new(),
+        }
+    }
+
+    pub fn set_completions(&mut self, completions: Vec<String>) {
+        self.completions = completions;
+    }
+
+    /// Reads a line, adding it to the history if accepted.
+    pub fn read_line(&mut self) -> io::Result<Line> {
+        let line = if io::stdin().is_terminal() {
+            match RawMode::enable() {
+                Ok(raw) => {
+                    let line = Edit::new(self).run();
+                    drop(raw);
+                    println!();
+                    line?
+                }
+                // No `stty`, e.g. on Windows.
+                Err(_) => self.read_plain()?,
+            }
+        } else {
+            self.read_plain()?
+        };
+
+        if let Line::Accepted(line) = &line {
+            self.remember(line);
+        }
+        Ok(line)
+    }
+
+    fn read_plain(&self) -> io::Result<Line> {
+        print!("{}", self.prompt);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(Line::Eof);
+        }
+        Ok(Line::Accepted(
+            line.trim_end_matches(['\r', '\n']).to_owned(),
+        ))
+    }
+
+    fn remember(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_owned());
+        let excess = self.history.len().saturating_sub(MAX_HISTORY);
+        self.history.drain(..excess);
+
+        if let Some(path) = &self.history_file {
+            // History is a convenience, failing to persist it does not fail the REPL.
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{line}");
+            }
+        }
+    }
+}
+
+/// Puts the terminal into raw mode until dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?.trim().to_owned();
+        stty(&["raw", "-echo"])?;
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// A control character, by its letter, e.g. `'r'` for Ctrl-R.
+    Ctrl(char),
+    Escape,
+}
+
+/// Reads the next key from the raw terminal, `None` at the end of the input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7F | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x1B => read_escape(input)?,
+        0x01..=0x1A => Key::Ctrl((b'a' + byte - 1) as char),
+        0x00..=0x1F => return Ok(Some(Key::Escape)),
+        _ => {
+            // The first byte of a UTF-8 sequence tells its length.
+            let len = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|text| text.chars().next())
+            {
+                Some(char) => Key::Char(char),
+                None => Key::Char(char::REPLACEMENT_CHARACTER),
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Reads the rest of an escape sequence, e.g. `[A` for the up arrow.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') => match read_byte(input)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'C') => Key::Right,
+            Some(b'D') => Key::Left,
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            Some(digit @ b'0'..=b'9') => {
+                // Sequences like `3~` run up to the tilde.
+                let mut code = vec![digit];
+                while let Some(byte) = read_byte(input)? {
+                    if byte == b'~' {
+                        break;
+                    }
+                    code.push(byte);
+                }
+                match code.as_slice() {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Escape,
+                }
+            }
+            _ => Key::Escape,
+        },
+        Some(b'O') => match read_byte(input)? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            _ => Key::Escape,
+        },
+        _ => Key::Escape,
+    };
+    Ok(key)
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// State of the line being edited.
+struct Edit<'a> {
+    editor: &'a Editor,
+    buffer: Vec<char>,
+    cursor: usize,
+    /// Position in the history while browsing it, `history.len()` for the line being written.
+    browsed: usize,
+    /// Line being written while browsing the history.
+    draft: Vec<char>,
+    /// Query and matching history entry of a reverse search in progress.
+    search: Option<(String, Option<usize>)>,
+}
+
+impl<'a> Edit<'a> {
+    fn new(editor: &'a Editor) -> Self {
+        Self {
+            editor,
+            buffer: Vec::new(),
+            cursor: 0,
+            browsed: editor.history.len(),
+            draft: Vec::new(),
+            search: None,
+        }
+    }
+
+    fn run(mut self) -> io::Result<Line> {
+        let mut input = io::stdin().lock();
+        loop {
+            self.render()?;
+            let Some(key) = read_key(&mut input)? else {
+                return Ok(Line::Eof);
+            };
+            if self.search.is_some() {
+                if let Some(line) = self.search_key(key) {
+                    return Ok(line);
+                }
+                continue;
+            }
+
+            match key {
+                Key::Enter => return Ok(Line::Accepted(self.buffer.iter().collect())),
+                Key::Ctrl('c') => return Ok(Line::Interrupted),
+                Key::Ctrl('d') if self.buffer.is_empty() => return Ok(Line::Eof),
+                Key::Ctrl('d') | Key::Delete => {
+                    if self.cursor < self.buffer.len() {
+                        self.buffer.remove(self.cursor);
+                    }
+                }
+                Key::Ctrl('r') => self.search = Some((String::new(), None)),
+                Key::Char(char) => {
+                    self.buffer.insert(self.cursor, char);
+                    self.cursor += 1;
+                }
+                Key::Backspace | Key::Ctrl('h') => {
+                    if self.cursor > 0 {
+                        self.cursor -= 1;
+                        self.buffer.remove(self.cursor);
+                    }
+                }
+                Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => {
+                    self.cursor = (self.cursor + 1).min(self.buffer.len())
+                }
+                Key::Home | Key::Ctrl('a') => self.cursor = 0,
+                Key::End | Key::Ctrl('e') => self.cursor = self.buffer.len(),
+                Key::Ctrl('u') => {
+                    self.buffer.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                Key::Ctrl('k') => self.buffer.truncate(self.cursor),
+                Key::Up | Key::Ctrl('p') => self.browse(-1),
+                Key::Down | Key::Ctrl('n') => self.browse(1),
+                Key::Tab => self.complete()?,
+                Key::Ctrl(_) | Key::Escape => {}
+            }
+        }
+    }
+
+    /// Handles a key during a reverse search, returns the line if the key ends editing.
+    fn search_key(&mut self, key: Key) -> Option<Line> {
+        let (query, matched) = self.search.clone()?;
+        let len = self.editor.history.len();
+        let (query, matched) = match key {
+            Key::Char(char) => {
+                let query = format!("{query}{char}");
+                // The current match stays if it still matches.
+                let before = matched.map_or(len, |index| index + 1);
+                let matched = find(&self.editor.history, &query, before);
+                (query, matched)
+            }
+            Key::Backspace => {
+                let mut query = query;
+                query.pop();
+                let matched = find(&self.editor.history, &query, len);
+                (query, matched)
+            }
+            // Another Ctrl-R looks further back.
+            Key::Ctrl('r') => {
+                let before = matched.unwrap_or(len);
+                let older = find(&self.editor.history, &query, before);
+                (query, older.or(matched))
+            }
+            Key::Ctrl('c') => return Some(Line::Interrupted),
+            Key::Ctrl('g') | Key::Escape => {
+                self.search = None;
+                return None;
+            }
+            Key::Enter => {
+                self.take_match();
+                return Some(Line::Accepted(self.buffer.iter().collect()));
+            }
+            // Any other key keeps the match for editing.
+            _ => {
+                self.take_match();
+                return None;
+            }
+        };
+        self.search = Some((query, matched));
+        None
+    }
+
+    fn take_match(&mut self) {
+        if let Some((_, Some(index))) = self.search.take() {
+            self.buffer = self.editor.history[index].chars().collect();
+            self.cursor = self.buffer.len();
+        }
+    }
+
+    /// Moves through the history by `step` entries, keeping the line being written as a draft.
+    fn browse(&mut self, step: isize) {
+        let history = &self.editor.history;
+        let Some(browsed) = self
+            .browsed
+            .checked_add_signed(step)
+            .filter(|browsed| *browsed <= history.len())
+        else {
+            return;
+        };
+        if self.browsed == history.len() {
+            self.draft = self.buffer.clone();
+        }
+        self.browsed = browsed;
+        self.buffer = match history.get(browsed) {
+            Some(entry) => entry.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    /// Completes the first word from the completions, listing them if several match.
+    fn complete(&mut self) -> io::Result<()> {
+        if self.buffer[..self.cursor].contains(&' ') {
+            return Ok(());
+        }
+        let prefix = self.buffer[..self.cursor].iter().collect::<String>();
+        let candidates = self
+            .editor
+            .completions
+            .iter()
+            .filter(|candidate| candidate.starts_with(&prefix))
+            .collect::<Vec<_>>();
+
+        let completed = match candidates.as_slice() {
+            [] => return Ok(()),
+            [only] => format!("{only} "),
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.len(), |len, candidate| {
+                    first
+                        .chars()
+                        .zip(candidate.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a.len_utf8())
+                        .sum::<usize>()
+                        .min(len)
+                });
+                if common == prefix.len() {
+                    let listing = candidates
+                        .iter()
+                        .map(|candidate| candidate.as_str())
+                        .collect::<Vec<_>>();
+                    print!("\r\n{}\r\n", listing.join("  "));
+                    return io::stdout().flush();
+                }
+                first[..common].to_owned()
+            }
+        };
+        let tail = self.buffer.split_off(self.cursor);
+        self.buffer = completed.chars().chain(tail).collect();
+        self.cursor = completed.chars().count();
+        Ok(())
+    }
+
+    /// Redraws the current line and places the cursor.
+    fn render(&self) -> io::Result<()> {
+        let (prompt, line, cursor) = match &self.search {
+            Some((query, matched)) => {
+                let found = matched.map_or("", |index| self.editor.history[index].as_str());
+                let prompt = format!("(reverse-i-search)`{query}': ");
+                (prompt, found.to_owned(), found.chars().count())
+            }
+            None => (
+                self.editor.prompt.clone(),
+                self.buffer.iter().collect(),
+                self.cursor,
+            ),
+        };
+
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "\r\x1b[K{prompt}{line}\r")?;
+        let column = prompt.chars().count() + cursor;
+        if column > 0 {
+            write!(stdout, "\x1b[{column}C")?;
+        }
+        stdout.flush()
+    }
+}
+
+/// Latest entry of `history` before `before` containing `query`.
+fn find(history: &[String], query: &str, before: usize) -> Option<usize> {
+    history[..before.min(history.len())]
+        .iter()
+        .rposition(|entry| entry.contains(query))
+}
@@ -0,0 +1,369 @@
+//! A command-line client for shell scripts: connects, sends one message, prints the reply and exits.
+//!
+//! - `send <TEXT>...` sends a text command and prints the reply,
+//! - `send-typed (--service <INDEX> | --service-name <NAME>) [--version <N>] [--hex <BCS> | --string <TEXT>] [--reply-string]`
+//!   sends a typed message and prints the reply in hex, or decoded as a string,
+//! - `read` prints everything the console sends until it disconnects,
+//! - `services` lists the services of the console,
+//! - `repl` reads commands interactively, with history, reverse search (Ctrl-R)
+//!   and completion of service names (Tab).
+//!
+//! Example: `tcp-console --addr 127.0.0.1:3838 send status`.
+
+mod editor;
+
+use editor::{Editor, Line};
+use serde::ser::{SerializeTuple, Serializer};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+use tcp_console::{Client, ClientConfig, ClientError};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:3838";
+
+/// File in the home directory the REPL history is kept in.
+const HISTORY_FILE: &str = ".tcp_console_history";
+
+/// How long the REPL waits for the first text of a reply, and for more text after that.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLY_QUIET: Duration = Duration::from_millis(100);
+
+const USAGE: &str =
+    "Usage: tcp-console [--addr <ADDR>] [--token <TOKEN>] [--timeout <SECONDS>] <COMMAND>
+Commands:
+  send <TEXT>...
+  send-typed (--service <INDEX> | --service-name <NAME>) [--version <N>] [--hex <BCS> | --string <TEXT>] [--reply-string]
+  read
+  services
+  repl";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (options, command) = parse_args(std::env::args().skip(1))?;
+    let config = ClientConfig {
+        token: options.token,
+        connect_timeout: Some(options.timeout),
+        // Reading streams, or waits for the user, for as long as the console keeps the connection.
+        read_timeout: (!matches!(command, Command::Read | Command::Repl))
+            .then_some(options.timeout),
+        write_timeout: Some(options.timeout),
+        ..ClientConfig::default()
+    };
+    let mut client = Client::with_config(options.address.as_str(), config).await?;
+
+    match command {
+        Command::Send(text) => {
+            client.weak_send(&text).await?;
+            println!("{}", client.weak_read().await?);
+        }
+        Command::SendTyped(typed) => {
+            if let Some(reply) = send_typed(&mut client, &typed).await? {
+                println!("{reply}");
+            }
+        }
+        // The console closing the connection ends the output.
+        Command::Read => {
+            while let Ok(text) = client.weak_read().await {
+                println!("{text}");
+            }
+        }
+        Command::Repl => repl(client, &options.address).await?,
+        Command::Services => {
+            for service in client.list_services().await? {
+                let versions = service
+                    .versions
+                    .iter()
+                    .map(|version| format!(" v{version}"))
+                    .collect::<String>();
+                match service.description {
+                    Some(description) => println!("{}{versions} - {description}", service.name),
+                    None => println!("{}{versions}", service.name),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the typed message and returns the reply as printed, `None` if it carries no payload.
+async fn send_typed(client: &mut Client, typed: &Typed) -> anyhow::Result<Option<String>> {
+    let id = match typed.version {
+        Some(version) => {
+            client
+                .send_versioned_request(&typed.service, version, &typed.payload)
+                .await?
+        }
+        None => client.send_request(&typed.service, &typed.payload).await?,
+    };
+    Ok(match client.recv_response(id).await? {
+        Some(bytes) if typed.reply_string => Some(bcs::from_bytes::<String>(&bytes)?),
+        Some(bytes) => Some(to_hex(&bytes)),
+        None => None,
+    })
+}
+
+/// Sends every line read as a text command and prints the reply, until `exit`, `quit` or Ctrl-D.
+async fn repl(mut client: Client, address: &str) -> anyhow::Result<()> {
+    let history_file = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    let mut editor = Editor::new(format!("{address}> "), history_file);
+    // Consoles which do not list their services only miss completion.
+    if let Ok(services) = client.list_services().await {
+        let names = services.into_iter().map(|service| service.name);
+        editor.set_completions(names.chain(["help".to_owned()]).collect());
+    }
+
+    loop {
+        // Reading the terminal blocks, the client is not used meanwhile.
+        let (returned, line) = tokio::task::spawn_blocking(move || {
+            let line = editor.read_line();
+            (editor, line)
+        })
+        .await?;
+        editor = returned;
+
+        let line = match line? {
+            Line::Accepted(line) => line,
+            Line::Interrupted => continue,
+            Line::Eof => break,
+        };
+        let command = line.trim();
+        match command {
+            "" => continue,
+            "exit" | "quit" => break,
+            _ => client.weak_send(command).await?,
+        }
+
+        // Replies may arrive in several pieces, print them until the console goes quiet.
+        let mut timeout = REPLY_TIMEOUT;
+        loop {
+            match client.weak_read_timeout(timeout).await {
+                Ok(text) => println!("{text}"),
+                Err(err) if matches!(err.downcast_ref(), Some(ClientError::ReadTimeout(_))) => {
+                    if timeout == REPLY_TIMEOUT {
+                        println!("(no reply)");
+                    }
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+            timeout = REPLY_QUIET;
+        }
+    }
+
+    Ok(())
+}
+
+struct Options {
+    address: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+enum Command {
+    Send(String),
+    SendTyped(Typed),
+    Read,
+    Services,
+    Repl,
+}
+
+struct Typed {
+    service: Service,
+    version: Option<u32>,
+    payload: Payload,
+    /// Whether the reply is a BCS-encoded string to print as text.
+    reply_string: bool,
+}
+
+/// Service id as the console decodes it.
+#[derive(Debug, PartialEq)]
+enum Service {
+    /// Index of the variant of a fieldless enum, which is how BCS encodes those.
+    Index(u32),
+    /// Name, for consoles keyed by `String`.
+    Name(String),
+}
+
+impl Serialize for Service {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Service::Index(index) => serializer.serialize_unit_variant("Service", *index, ""),
+            Service::Name(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+/// BCS-encoded message, serialized as is.
+struct Payload(Vec<u8>);
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Tuples carry no length prefix, so the bytes pass through unchanged.
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for byte in &self.0 {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<(Options, Command)> {
+    let mut options = Options {
+        address: DEFAULT_ADDRESS.to_owned(),
+        token: None,
+        timeout: Duration::from_secs(10),
+    };
+    let mut args = args.peekable();
+    let usage = || anyhow::anyhow!(USAGE);
+
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        let value = args.next().ok_or_else(usage)?;
+        match arg.as_str() {
+            "--addr" => options.address = value,
+            "--token" => options.token = Some(value),
+            "--timeout" => options.timeout = Duration::from_secs(value.parse()?),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+
+    let command = match args.next().ok_or_else(usage)?.as_str() {
+        "send" => {
+            let text = args.collect::<Vec<_>>().join(" ");
+            if text.is_empty() {
+                anyhow::bail!(USAGE);
+            }
+            Command::Send(text)
+        }
+        "send-typed" => Command::SendTyped(parse_typed(args)?),
+        "read" => Command::Read,
+        "services" => Command::Services,
+        "repl" => Command::Repl,
+        _ => anyhow::bail!(USAGE),
+    };
+
+    Ok((options, command))
+}
+
+fn parse_typed(mut args: impl Iterator<Item = String>) -> anyhow::Result<Typed> {
+    let mut service = None;
+    let mut typed = Typed {
+        service: Service::Index(0),
+        version: None,
+        payload: Payload(Vec::new()),
+        reply_string: false,
+    };
+    let usage = || anyhow::anyhow!(USAGE);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--service" => {
+                let value = args.next().ok_or_else(usage)?;
+                // Names of enum variants are never sent, so their index cannot be looked up.
+                let index = value.parse().map_err(|_| {
+                    anyhow::anyhow!(
+                        "--service takes the index of the service's enum variant, not {value:?}; \
+                         use --service-name for consoles keyed by String"
+                    )
+                })?;
+                service = Some(Service::Index(index));
+            }
+            "--service-name" => service = Some(Service::Name(args.next().ok_or_else(usage)?)),
+            "--version" => typed.version = Some(args.next().ok_or_else(usage)?.parse()?),
+            "--hex" => typed.payload = Payload(from_hex(&args.next().ok_or_else(usage)?)?),
+            "--string" => typed.payload = Payload(bcs::to_bytes(&args.next().ok_or_else(usage)?)?),
+            "--reply-string" => typed.reply_string = true,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+
+    typed.service = service.ok_or_else(usage)?;
+    Ok(typed)
+}
+
+fn from_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text.trim_start_matches("0x");
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        anyhow::bail!("Hex payload must have an even number of digits");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| Ok(u8::from_str_radix(&text[index..index + 2], 16)?))
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::net::Ipv4Addr;
+    use tcp_console::SubscriptionError;
+
+    fn typed(args: &[&str]) -> anyhow::Result<Typed> {
+        parse_typed(args.iter().map(|arg| arg.to_string()))
+    }
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl tcp_console::Subscription for Echo {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_messages_reach_services_by_name() -> anyhow::Result<()> {
+        let mut console = tcp_console::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe("Logger".to_owned(), Echo)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = Client::new(address, None).await?;
+        let typed = typed(&[
+            "--service-name",
+            "Logger",
+            "--string",
+            "hello",
+            "--reply-string",
+        ])?;
+        assert_eq!(
+            send_typed(&mut client, &typed).await?.as_deref(),
+            Some("hello")
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[test]
+    fn services_are_named_explicitly() -> anyhow::Result<()> {
+        assert_eq!(typed(&["--service", "2"])?.service, Service::Index(2));
+        assert_eq!(
+            typed(&["--service-name", "Logger"])?.service,
+            Service::Name("Logger".to_owned())
+        );
+        assert_eq!(
+            bcs::to_bytes(&Service::Name("Logger".to_owned()))?,
+            bcs::to_bytes("Logger")?
+        );
+
+        // Enum-keyed consoles could not decode the name.
+        let error = typed(&["--service", "Logger"])
+            .err()
+            .expect("Names are refused");
+        assert!(error.to_string().contains("--service-name"));
+
+        Ok(())
+    }
+}
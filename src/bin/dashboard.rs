@@ -0,0 +1,224 @@
+//! A terminal dashboard for one or more running consoles.
+//!
+//! Every pane owns its own [Client] connection and is rendered into a shared screen:
+//! - `--watch <ADDR> <COMMAND>` periodically sends a text command and shows the latest reply,
+//! - `--tail <ADDR> [COMMAND]` optionally sends a command once and keeps appending everything the console sends back.
+//!
+//! Example: `tcp-console-dashboard --watch 127.0.0.1:3838 status --tail 127.0.0.1:3838`.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tcp_console::Client;
+use tokio::{signal, time};
+
+/// Number of lines kept per pane in memory.
+const MAX_LINES: usize = 1000;
+
+const USAGE: &str = "Usage: tcp-console-dashboard [--interval <SECONDS>] \
+    (--watch <ADDR> <COMMAND> | --tail <ADDR> [COMMAND])...";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (interval, specs) = parse_args(std::env::args().skip(1))?;
+
+    let panes = specs
+        .into_iter()
+        .map(|spec| {
+            let pane = Arc::new(Pane::new(spec.title()));
+            tokio::spawn(run_pane(spec, pane.clone(), interval));
+            pane
+        })
+        .collect::<Vec<_>>();
+
+    let mut ticker = time::interval(Duration::from_millis(250));
+    loop {
+        tokio::select! {
+            _ = signal::ctrl_c() => break,
+            _ = ticker.tick() => render(&panes)?,
+        }
+    }
+
+    // Leave the alternate screen and show the cursor again.
+    print!("\x1b[?25h\x1b[?1049l");
+    std::io::stdout().flush()?;
+
+    Ok(())
+}
+
+/// What a single pane shows.
+enum PaneSpec {
+    Watch {
+        address: String,
+        command: String,
+    },
+    Tail {
+        address: String,
+        command: Option<String>,
+    },
+}
+
+impl PaneSpec {
+    fn title(&self) -> String {
+        match self {
+            PaneSpec::Watch { address, command } => format!("watch {address} `{command}`"),
+            PaneSpec::Tail {
+                address,
+                command: Some(command),
+            } => format!("tail {address} `{command}`"),
+            PaneSpec::Tail {
+                address,
+                command: None,
+            } => format!("tail {address}"),
+        }
+    }
+
+    fn address(&self) -> &str {
+        match self {
+            PaneSpec::Watch { address, .. } | PaneSpec::Tail { address, .. } => address,
+        }
+    }
+}
+
+/// Shared state of a pane: updated by its connection task, read by the renderer.
+struct Pane {
+    title: String,
+    lines: Mutex<Vec<String>>,
+}
+
+impl Pane {
+    fn new(title: String) -> Self {
+        Self {
+            title,
+            lines: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn replace(&self, text: &str) {
+        let mut lines = self.lines.lock().expect("Pane lock must not be poisoned");
+        *lines = text.lines().map(str::to_owned).collect();
+    }
+
+    fn append(&self, text: &str) {
+        let mut lines = self.lines.lock().expect("Pane lock must not be poisoned");
+        lines.extend(text.lines().map(str::to_owned));
+        let excess = lines.len().saturating_sub(MAX_LINES);
+        lines.drain(..excess);
+    }
+}
+
+/// Keeps a pane connected to its console, reconnecting after every failure.
+async fn run_pane(spec: PaneSpec, pane: Arc<Pane>, interval: Duration) {
+    loop {
+        if let Err(err) = drive_pane(&spec, &pane, interval).await {
+            pane.append(&format!(
+                "!! {err}; reconnecting in {}s",
+                interval.as_secs()
+            ));
+        }
+        time::sleep(interval).await;
+    }
+}
+
+async fn drive_pane(spec: &PaneSpec, pane: &Pane, interval: Duration) -> anyhow::Result<()> {
+    let mut client = Client::new(spec.address(), None).await?;
+
+    match spec {
+        PaneSpec::Watch { command, .. } => loop {
+            client.weak_send(command).await?;
+            let reply = client.weak_read().await?;
+            pane.replace(&reply);
+            time::sleep(interval).await;
+        },
+        PaneSpec::Tail { command, .. } => {
+            if let Some(command) = command {
+                client.weak_send(command).await?;
+            }
+            loop {
+                let text = client.weak_read().await?;
+                pane.append(&text);
+            }
+        }
+    }
+}
+
+/// Redraws all panes stacked vertically, each getting an equal share of the terminal height.
+fn render(panes: &[Arc<Pane>]) -> std::io::Result<()> {
+    let (rows, columns) = terminal_size();
+    let height = (rows / panes.len().max(1)).max(2);
+
+    let mut screen = String::from("\x1b[?1049h\x1b[?25l\x1b[H\x1b[2J");
+    for pane in panes {
+        let title = format!("── {} ", pane.title);
+        screen.push_str("\x1b[1m");
+        screen.push_str(&fit(&title, columns, '─'));
+        screen.push_str("\x1b[0m\r\n");
+
+        let lines = pane.lines.lock().expect("Pane lock must not be poisoned");
+        let body = height - 1;
+        let start = lines.len().saturating_sub(body);
+        for line in &lines[start..] {
+            screen.push_str(&fit(line, columns, ' '));
+            screen.push_str("\r\n");
+        }
+        for _ in lines.len() - start..body {
+            screen.push_str("\r\n");
+        }
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(screen.as_bytes())?;
+    stdout.flush()
+}
+
+/// Truncates or pads `text` to exactly `width` characters.
+fn fit(text: &str, width: usize, pad: char) -> String {
+    let mut fitted = text.chars().take(width).collect::<String>();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(pad, width - len));
+    fitted
+}
+
+/// Terminal size as `(rows, columns)`, taken from `LINES`/`COLUMNS` with a classic 24x80 fallback.
+fn terminal_size() -> (usize, usize) {
+    let read = |name: &str, default: usize| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+
+    (read("LINES", 24), read("COLUMNS", 80))
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<(Duration, Vec<PaneSpec>)> {
+    let mut interval = Duration::from_secs(2);
+    let mut specs = Vec::new();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                let seconds = args.next().ok_or(anyhow::anyhow!(USAGE))?.parse()?;
+                interval = Duration::from_secs(seconds);
+            }
+            "--watch" => {
+                let address = args.next().ok_or(anyhow::anyhow!(USAGE))?;
+                let command = args.next().ok_or(anyhow::anyhow!(USAGE))?;
+                specs.push(PaneSpec::Watch { address, command });
+            }
+            "--tail" => {
+                let address = args.next().ok_or(anyhow::anyhow!(USAGE))?;
+                let command = args.next_if(|next| !next.starts_with("--"));
+                specs.push(PaneSpec::Tail { address, command });
+            }
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+
+    if specs.is_empty() {
+        anyhow::bail!(USAGE);
+    }
+
+    Ok((interval, specs))
+}
@@ -0,0 +1,315 @@
+//! Synchronous client for [Console](crate::Console), usable without an async runtime.
+
+use crate::auth::{AUTH_COMMAND, AUTH_OK};
+use crate::chunked::Reassembly;
+use crate::client::MAX_UNREAD_TEXTS;
+use crate::codec::{is_machine_hello, Frame, FrameCodec, MACHINE_HELLO};
+use crate::compression::{self, Compressor};
+use crate::console::{Message, Reply, ReplyError};
+use crate::control::{self, Control, LEGACY_VERSION};
+use crate::targets::CLIENT;
+use crate::{ensure_newline, ClientConfig, ClientError};
+use bytes::{Bytes, BytesMut};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+/// Blocking counterpart of [crate::Client] built on [std::net::TcpStream].
+pub struct Client {
+    stream: TcpStream,
+    config: ClientConfig,
+    codec: FrameCodec,
+    /// Protocol version negotiated with [Console](crate::Console).
+    protocol_version: u32,
+    /// Compressor typed frames are compressed with, see [ClientConfig::compressors].
+    compressor: Option<Arc<dyn Compressor>>,
+    /// Chunked frame being received, see [Builder::chunk_size](crate::Builder::chunk_size).
+    reassembly: Reassembly,
+    /// Bytes received but not yet decoded into frames.
+    buffer: BytesMut,
+    /// Correlation id for the next request.
+    next_id: u64,
+    /// Replies received while waiting for other frames.
+    replies: HashMap<u64, Result<Option<Bytes>, ReplyError>>,
+    /// Text frames received while waiting for replies.
+    texts: VecDeque<Bytes>,
+}
+
+impl Client {
+    pub fn new<A: ToSocketAddrs>(address: A) -> anyhow::Result<Self> {
+        Self::with_config(address, ClientConfig::default())
+    }
+
+    /// Connects to [Console](crate::Console) applying the timeouts from `config`
+    /// to this and all subsequent calls, and authenticating if `config` holds a token.
+    pub fn with_config<A: ToSocketAddrs>(address: A, config: ClientConfig) -> anyhow::Result<Self> {
+        let stream = match config.connect_timeout {
+            Some(timeout) => {
+                let address = address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or(anyhow::anyhow!("Console address does not resolve"))?;
+                TcpStream::connect_timeout(&address, timeout).map_err(|err| match err.kind() {
+                    io::ErrorKind::TimedOut => ClientError::ConnectTimeout(timeout).into(),
+                    _ => anyhow::Error::from(err),
+                })?
+            }
+            None => TcpStream::connect(address)?,
+        };
+        stream.set_read_timeout(config.read_timeout)?;
+        stream.set_write_timeout(config.write_timeout)?;
+        debug!(target: CLIENT, "Connected to server");
+
+        let mut client = Client {
+            stream,
+            config,
+            codec: FrameCodec::client(),
+            protocol_version: LEGACY_VERSION,
+            compressor: None,
+            reassembly: Reassembly::default(),
+            buffer: BytesMut::new(),
+            next_id: 0,
+            replies: HashMap::new(),
+            texts: VecDeque::new(),
+        };
+
+        // Announce a programmatic client, anything before the acknowledgement is meant for humans.
+        client.write_frame(MACHINE_HELLO)?;
+        loop {
+            let frame = client.receive()?;
+            if is_machine_hello(&frame) {
+                break;
+            }
+            if let Some(Control::Offer(offered)) = Control::from_frame(&frame) {
+                client.protocol_version = control::choose(&offered)?;
+                client.write_frame(Control::Select(client.protocol_version).to_frame())?;
+                if let Some(accept) =
+                    control::accept(client.protocol_version, &client.config.compressors)
+                {
+                    client.write_frame(accept.to_frame())?;
+                }
+                break;
+            }
+        }
+
+        if let Some(token) = client.config.token.clone() {
+            client.authenticate(&token)?;
+        }
+
+        Ok(client)
+    }
+
+    /// Protocol version negotiated with [Console](crate::Console) when connecting.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Sends a message to [Console](crate::Console) with any serializable payload.
+    ///
+    /// The message carries no correlation id, so the reply, if any, is not tracked.
+    pub fn send<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<()> {
+        self.send_message(Message::new(None, service_id, message)?)
+    }
+
+    /// Sends a message to [Console](crate::Console) and waits for its reply.
+    pub fn send_recv<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.send_message(Message::new(Some(id), service_id, message)?)?;
+
+        loop {
+            if let Some(reply) = self.replies.remove(&id) {
+                return Ok(reply.map_err(ClientError::from)?);
+            }
+
+            match self.read_frame()? {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => self.stash_text(bytes),
+            }
+        }
+    }
+
+    /// Sends a message to [Console](crate::Console) with any text.
+    pub fn weak_send(&mut self, message: &str) -> anyhow::Result<()> {
+        let bytes: Bytes = ensure_newline(message.to_owned()).into_bytes().into();
+        self.write_frame(Frame::Text(bytes))
+    }
+
+    /// Receives a text message from [Console](crate::Console).
+    /// Of the texts received while waiting for replies, only the latest 64 unread ones are kept.
+    pub fn weak_read(&mut self) -> anyhow::Result<String> {
+        let bytes = loop {
+            if let Some(bytes) = self.texts.pop_front() {
+                break bytes;
+            }
+
+            match self.read_frame()? {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => break bytes,
+            }
+        };
+
+        Ok(String::from_utf8_lossy(bytes.as_ref()).trim().to_string())
+    }
+
+    fn authenticate(&mut self, token: &str) -> anyhow::Result<()> {
+        self.weak_send(&format!("{AUTH_COMMAND} {token}"))?;
+        match self.weak_read()?.as_str() {
+            AUTH_OK => Ok(()),
+            answer => Err(ClientError::AuthenticationFailed(answer.to_owned()).into()),
+        }
+    }
+
+    fn send_message<S: Serialize>(&mut self, message: Message<S>) -> anyhow::Result<()> {
+        let bytes: Bytes = bcs::to_bytes(&message)?.into();
+        self.write_frame(Frame::Typed(bytes))
+    }
+
+    fn write_frame(&mut self, frame: Frame) -> anyhow::Result<()> {
+        let mut bytes = BytesMut::new();
+        self.codec.encode(frame, &mut bytes)?;
+
+        self.stream
+            .write_all(&bytes)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    ClientError::WriteTimeout(self.config.write_timeout.unwrap_or_default()).into()
+                }
+                _ => anyhow::Error::from(err),
+            })
+    }
+
+    /// Receives the next frame which is not a control frame, answering heartbeats meanwhile.
+    fn read_frame(&mut self) -> anyhow::Result<Frame> {
+        loop {
+            let frame = self.receive()?;
+            let Some(frame) = self.reassembly.push(frame)? else {
+                continue;
+            };
+            let frame = compression::decompress(frame, self.compressor.as_deref())?;
+            match Control::from_frame(&frame) {
+                Some(Control::Ping) => self.write_frame(Control::Pong.to_frame())?,
+                Some(Control::Compress(name)) => {
+                    self.compressor = control::negotiated(&name, &self.config.compressors);
+                }
+                Some(control) => debug!(target: CLIENT, "Ignoring an unexpected {control:?}"),
+                None => return Ok(frame),
+            }
+        }
+    }
+
+    fn receive(&mut self) -> anyhow::Result<Frame> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(frame) = self.codec.decode(&mut self.buffer)? {
+                return Ok(frame);
+            }
+
+            let read = self
+                .stream
+                .read(&mut chunk)
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        ClientError::ReadTimeout(self.config.read_timeout.unwrap_or_default())
+                            .into()
+                    }
+                    _ => anyhow::Error::from(err),
+                })?;
+
+            if read == 0 {
+                return self
+                    .codec
+                    .decode_eof(&mut self.buffer)?
+                    .ok_or(anyhow::anyhow!("Connection closed unexpectedly"));
+            }
+
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Keeps a received reply until it is asked for.
+    fn stash_text(&mut self, bytes: Bytes) {
+        if self.texts.len() == MAX_UNREAD_TEXTS {
+            debug!(target: CLIENT, "Discarding the oldest unread text message");
+            self.texts.pop_front();
+        }
+        self.texts.push_back(bytes);
+    }
+
+    fn stash_reply(&mut self, bytes: Bytes) -> anyhow::Result<()> {
+        let Reply { id, bytes, error } = bcs::from_bytes(bytes.as_ref())?;
+
+        match id {
+            Some(id) => {
+                self.replies.insert(id, error.map_or(Ok(bytes), Err));
+            }
+            None => debug!(target: CLIENT, "Discarding a reply without correlation id"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::TestService;
+    use crate::{Subscription, SubscriptionError};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn blocking_round_trip() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, Echo)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let (text, typed) = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let mut client = crate::blocking::Client::new(address)?;
+            client.weak_send("hello")?;
+            let text = client.weak_read()?;
+            let typed = client.send_recv(TestService::One, &"typed")?;
+            Ok((text, typed))
+        })
+        .await??;
+
+        assert_eq!(text, "hello");
+        assert_eq!(
+            bcs::from_bytes::<String>(&typed.expect("Echo must reply"))?,
+            "typed"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Echo;
+
+    #[async_trait]
+    impl Subscription for Echo {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(message.to_owned()))
+        }
+    }
+}
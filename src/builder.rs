@@ -1,83 +1,684 @@
-use crate::console::{Console, Error};
+use crate::audit::{Audit, AuditSink};
+use crate::auth::{AuthContext, Policy};
+use crate::compression::Compressor;
+use crate::config::{ConsoleConfig, RateLimitConfig};
+use crate::console::{
+    Console, Error, ErrorReporting, Settings, SpanFields, WeakDispatch, WireDirection,
+};
+use crate::context::{Context, Extensions};
 use crate::ensure_newline;
-use crate::subscription::{BoxedSubscription, Subscription};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::fmt::Debug;
-use std::hash::Hash;
+use crate::history::{HistoryStore, MemoryHistory};
+use crate::ip_net::IpNet;
+use crate::metrics::MetricsProvider;
+use crate::middleware::Middleware;
+use crate::outbox::SlowConsumer;
+use crate::service::ServiceId;
+use crate::subscription::{Subscription, Subscriptions};
+use bytes::Bytes;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::ToSocketAddrs;
 
 /// A builder for [Console].
-pub struct Builder<Services, A> {
-    subscriptions: HashMap<Services, BoxedSubscription>,
-    bind_address: Option<A>,
-    welcome: Option<String>,
-    accept_only_localhost: bool,
+///
+/// The builder tracks its configuration state in types, so that [Builder::build]
+/// is only available once a bind address is set and at least one subscription is registered:
+/// - `A` is [NoAddress] until [Builder::bind_address] is called,
+/// - `S` is [NoSubscriptions] until [Builder::subscribe] is called, then it becomes [Subscribed].
+///
+/// For config-driven construction, pass an `Option` to [Builder::bind_address]
+/// and use [Builder::try_build], which checks the configuration at runtime.
+pub struct Builder<Services, A = NoAddress, S = NoSubscriptions> {
+    subscriptions: Subscriptions<Services>,
+    policy: Policy<Services>,
+    bind_address: A,
+    settings: Settings,
+    _subscribed: PhantomData<S>,
 }
 
-impl<Services, A> Builder<Services, A>
+/// [Builder] state: bind address is not set.
+pub struct NoAddress;
+
+/// [Builder] state: no subscriptions registered.
+pub struct NoSubscriptions;
+
+/// [Builder] state: at least one subscription registered.
+pub struct Subscribed;
+
+impl<Services> Builder<Services>
 where
-    Services: Eq + Hash + Debug,
-    A: ToSocketAddrs,
+    Services: ServiceId,
 {
     pub fn new() -> Self {
         Self {
-            subscriptions: HashMap::new(),
-            bind_address: None,
-            welcome: None,
-            accept_only_localhost: false,
+            subscriptions: Subscriptions::new(),
+            policy: Policy::default(),
+            bind_address: NoAddress,
+            settings: Settings::default(),
+            _subscribed: PhantomData,
         }
     }
 
-    pub fn subscribe<S>(mut self, service_id: Services, subscription: S) -> Result<Self, Error>
+    /// Starts from the knobs of `config`, typically read from the host's configuration file.
+    /// Subscriptions and any other option are added as usual, and the console is built with
+    /// [Builder::try_build] since the bind address is only known at runtime.
+    pub fn from_config(config: ConsoleConfig) -> Builder<Services, Option<SocketAddr>> {
+        let mut builder = Self::new().bind_address(config.bind_address);
+        if let Some(welcome) = &config.welcome {
+            builder = builder.welcome(welcome);
+        }
+        if let Some(prompt) = &config.prompt {
+            builder = builder.prompt(prompt);
+        }
+        if let Some(networks) = &config.allowed_networks {
+            builder = builder.allow_networks(networks);
+        }
+        if let Some(max) = config.max_connections {
+            builder = builder.max_connections(max);
+        }
+        if let Some(bytes) = config.max_message_size {
+            builder = builder.max_message_size(bytes);
+        }
+        if let Some(RateLimitConfig { burst, period_secs }) = config.rate_limit {
+            builder = builder.rate_limit_per_peer(burst, Duration::from_secs(period_secs));
+        }
+        if let Some(secs) = config.idle_timeout_secs {
+            builder = builder.idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = config.keep_alive_secs {
+            builder = builder.keep_alive(Duration::from_secs(secs));
+        }
+        builder
+    }
+}
+
+impl<Services, A, S> Builder<Services, A, S>
+where
+    Services: ServiceId,
+{
+    pub fn subscribe<T>(
+        self,
+        service_id: Services,
+        subscription: T,
+    ) -> Result<Builder<Services, A, Subscribed>, Error>
+    where
+        T: Subscription + Send + Sync + 'static,
+    {
+        self.subscribe_with_priority(service_id, 0, subscription)
+    }
+
+    /// Subscribes like [Builder::subscribe], with free-form messages offered to subscriptions
+    /// of a higher `priority` first under [WeakDispatch::Ordered]. [Builder::subscribe] uses priority 0.
+    pub fn subscribe_with_priority<T>(
+        mut self,
+        service_id: Services,
+        priority: i32,
+        subscription: T,
+    ) -> Result<Builder<Services, A, Subscribed>, Error>
     where
-        S: Subscription + Send + Sync + 'static,
+        T: Subscription + Send + Sync + 'static,
     {
-        // `HashMap::entry(x)` consumes its argument, while we might need this string afterwards.
-        let service_id_string = format!("{service_id:?}");
-
-        match self.subscriptions.entry(service_id) {
-            Entry::Occupied(_) => Err(Error::ServiceIdUsed(service_id_string)),
-            Entry::Vacant(entry) => {
-                entry.insert(Box::new(subscription));
-                Ok(self)
-            }
+        if self.subscriptions.contains(&service_id) {
+            return Err(Error::ServiceIdUsed(service_id.name().to_owned()));
         }
+
+        self.subscriptions
+            .push(service_id, priority, Box::new(subscription));
+        Ok(Builder {
+            subscriptions: self.subscriptions,
+            policy: self.policy,
+            bind_address: self.bind_address,
+            settings: self.settings,
+            _subscribed: PhantomData,
+        })
     }
 
-    pub fn bind_address(mut self, bind_address: A) -> Self {
-        self.bind_address = Some(bind_address);
-        self
+    /// Registers `subscription` for everything no other subscription handles: typed messages
+    /// for services without a subscription, and free-form messages no subscription answers,
+    /// e.g. to proxy them to a legacy console. Registering another one replaces it.
+    pub fn default_subscription<T>(mut self, subscription: T) -> Builder<Services, A, Subscribed>
+    where
+        T: Subscription + Send + Sync + 'static,
+    {
+        self.subscriptions.set_fallback(Box::new(subscription));
+        Builder {
+            subscriptions: self.subscriptions,
+            policy: self.policy,
+            bind_address: self.bind_address,
+            settings: self.settings,
+            _subscribed: PhantomData,
+        }
     }
 
+    /// Subscribes `subscription` to typed messages for `version` of `service_id`, e.g. `Status@2`,
+    /// sent with [Client::send_versioned_request](crate::Client::send_versioned_request),
+    /// so that several versions of a service's API can be served during a migration.
+    /// Requests for an unregistered version are answered with an error message listing the supported ones.
+    ///
+    /// Messages without a version go to the subscription registered with [Builder::subscribe], if any,
+    /// or else to the latest version. Free-form messages only go to the former.
+    pub fn subscribe_version<T>(
+        mut self,
+        service_id: Services,
+        version: u32,
+        subscription: T,
+    ) -> Result<Builder<Services, A, Subscribed>, Error>
+    where
+        T: Subscription + Send + Sync + 'static,
+    {
+        if self.subscriptions.contains_version(&service_id, version) {
+            return Err(Error::ServiceIdUsed(format!(
+                "{}@{version}",
+                service_id.name()
+            )));
+        }
+
+        self.subscriptions
+            .push_version(service_id, version, Box::new(subscription));
+        Ok(Builder {
+            subscriptions: self.subscriptions,
+            policy: self.policy,
+            bind_address: self.bind_address,
+            settings: self.settings,
+            _subscribed: PhantomData,
+        })
+    }
+
+    pub fn bind_address<B>(self, bind_address: B) -> Builder<Services, B, S> {
+        Builder {
+            subscriptions: self.subscriptions,
+            policy: self.policy,
+            bind_address,
+            settings: self.settings,
+            _subscribed: PhantomData,
+        }
+    }
+
+    /// Sends `message` to every interactive session when it opens. Placeholders are replaced
+    /// with their values at that time: `{hostname}`, `{version}` of this crate, `{uptime}`
+    /// of the console and the number of registered `{services}`.
     pub fn welcome(mut self, message: &str) -> Self {
-        self.welcome = Some(message.to_owned());
+        self.settings.welcome = message.to_owned();
+        self
+    }
+
+    /// Calls `welcome` whenever an interactive session opens to produce its welcome message,
+    /// e.g. a banner with live figures of the host. Takes precedence over [Builder::welcome].
+    pub fn welcome_with<F>(mut self, welcome: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.settings.welcome_with = Some(Box::new(welcome));
+        self
+    }
+
+    /// Opens interactive sessions without any welcome message, not even the empty line sent
+    /// by default, e.g. for scripts reading every line. Overrides [Builder::welcome] and [Builder::welcome_with];
+    /// greetings returned by [Builder::on_connect] are still sent.
+    pub fn no_welcome(mut self) -> Self {
+        self.settings.no_welcome = true;
+        self
+    }
+
+    /// Sends `prompt` as is, e.g. `"node> "`, after the welcome message and after handling every
+    /// free-form message, so that operators under netcat or telnet see when a response is complete.
+    /// Programmatic clients are not sent prompts.
+    pub fn prompt(mut self, prompt: &str) -> Self {
+        self.settings.prompt = Some(prompt.to_owned());
+        self
+    }
+
+    /// Answers free-form messages which no subscription or built-in command answers with `message`,
+    /// e.g. "Unknown command, try `help`", instead of leaving the operator without a response.
+    pub fn unknown_command(mut self, message: &str) -> Self {
+        self.settings.unknown_command = Some(message.to_owned());
+        self
+    }
+
+    /// Speaks the telnet protocol to text clients: `IAC` negotiation sequences are stripped
+    /// instead of reaching subscriptions as garbage, and answered so that `telnet` stays in
+    /// line mode, echoing and editing lines locally. Typed frames are unaffected.
+    pub fn telnet(mut self) -> Self {
+        self.settings.telnet = true;
         self
     }
 
+    /// Accepts connections only from loopback addresses.
     pub fn accept_only_localhost(mut self) -> Self {
-        self.accept_only_localhost = true;
+        self.settings.allowed_networks = Some(IpNet::loopback().to_vec());
+        self
+    }
+
+    /// Accepts connections only from peers within one of `networks`,
+    /// replacing the loopback-only restriction of [Builder::accept_only_localhost].
+    /// Loopback addresses are not allowed implicitly, list them to keep local access.
+    pub fn allow_networks(mut self, networks: &[IpNet]) -> Self {
+        self.settings.allowed_networks = Some(networks.to_vec());
+        self
+    }
+
+    /// Limits the number of concurrently open sessions.
+    /// Connections beyond the limit are told the console is busy and closed.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.settings.max_connections = Some(max);
+        self
+    }
+
+    /// Handles up to `max` typed requests of a session at once, each in its own task, so that
+    /// a long-running command does not hold up quick queries on the same connection. Replies are
+    /// sent as requests complete, clients match them by correlation id, see [Client::send_request](crate::Client::send_request).
+    /// Free-form messages are still handled one at a time, in order.
+    pub fn concurrent_requests(mut self, max: usize) -> Self {
+        self.settings.concurrent_requests = Some(max.max(1));
+        self
+    }
+
+    /// Queues up to `capacity` frames per session for writing, 64 by default, and applies `policy`
+    /// when the queue is full because the client reads slower than subscriptions produce output,
+    /// so that one stuck client cannot hold up the console or exhaust its memory.
+    pub fn write_queue(mut self, capacity: usize, policy: SlowConsumer) -> Self {
+        self.settings.write_queue = Some((capacity, policy));
+        self
+    }
+
+    /// Drops typed messages and text lines longer than `bytes` as they arrive, instead of buffering them,
+    /// and tells the client with an error message. Typed payloads are never longer than 16 MiB.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.settings.max_message_size = Some(bytes);
+        self
+    }
+
+    /// Closes sessions which have not sent any message within `timeout`,
+    /// after notifying them with a "closing idle session" message.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends a blank line, followed by the [Builder::prompt] if any,
+    /// to interactive sessions which have not been sent anything within `interval`,
+    /// so that NAT gateways and firewalls do not drop connections of operators pondering their next command.
+    /// Programmatic clients are spared, and keep-alives do not count as activity for [Builder::idle_timeout].
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.settings.keep_alive = Some(interval);
+        self
+    }
+
+    /// Pings programmatic clients every `interval` and closes sessions which have not sent
+    /// anything for `missed` pings in a row, so that connections dropped silently by NAT gateways
+    /// or proxies are noticed. [Client](crate::Client) answers pings whenever it receives frames,
+    /// and [Client::ping](crate::Client::ping) checks the console is still there.
+    pub fn heartbeat(mut self, interval: Duration, missed: u32) -> Self {
+        self.settings.heartbeat = Some((interval, missed));
+        self
+    }
+
+    /// Compresses typed frames of at least 1 KiB, e.g. log dumps, to programmatic clients
+    /// which can decompress with a compressor of the same name, see [ClientConfig::compressors](crate::ClientConfig::compressors).
+    /// Compressors registered first are preferred. Text frames are never compressed.
+    pub fn compressor(mut self, compressor: impl Compressor + 'static) -> Self {
+        self.settings.compressors.push(Arc::new(compressor));
+        self
+    }
+
+    /// Sends typed frames larger than `bytes`, 256 KiB by default, in chunks of that size
+    /// to programmatic clients, which reassemble them transparently. This keeps any single frame
+    /// below the 16 MiB limit of typed payloads, so responses may be larger than that.
+    pub fn chunk_size(mut self, bytes: usize) -> Self {
+        self.settings.chunk_size = Some(bytes.max(1));
+        self
+    }
+
+    /// Limits how often a single peer address may connect:
+    /// up to `burst` connections at once, regained evenly over `period`.
+    /// IPv6 peers are limited per /64 prefix.
+    /// Connections beyond the limit are closed immediately and counted,
+    /// see [Console::rate_limited_count].
+    pub fn rate_limit_per_peer(mut self, burst: u32, period: Duration) -> Self {
+        self.settings.rate_limit = Some((burst, period));
+        self
+    }
+
+    /// Requires sessions to authenticate with `AUTH <secret>` right after the welcome message.
+    /// Nothing is dispatched before that, and a wrong secret closes the session.
+    /// [Client](crate::Client) performs the handshake when given the token.
+    pub fn require_token(mut self, secret: &str) -> Self {
+        self.settings.token = Some(secret.to_owned());
+        self
+    }
+
+    /// Restricts which services a session may use: `authorizer` is consulted before every
+    /// typed or free-form dispatch. Denied requests are refused with an "access denied" error,
+    /// denied services are skipped when offering free-form messages.
+    pub fn authorizer<F>(mut self, authorizer: F) -> Self
+    where
+        F: Fn(&AuthContext, &Services) -> bool + Send + Sync + 'static,
+    {
+        self.policy.authorizer = Some(Box::new(authorizer));
+        self
+    }
+
+    /// Wraps the dispatch of every typed and free-form message in the hooks of `middleware`,
+    /// e.g. for audit logging, rate limiting or metrics shared by all subscriptions.
+    /// Layers compose like tower layers: [Middleware::before](crate::Middleware::before) hooks run
+    /// in the order layers are added, [Middleware::after](crate::Middleware::after) hooks in reverse.
+    pub fn layer<M>(mut self, middleware: M) -> Self
+    where
+        M: Middleware + 'static,
+    {
+        self.settings.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Gives up on subscriptions still handling a message after `timeout`, so that a hung handler
+    /// does not wedge its session: the handler is dropped, the overrun logged, requests carrying
+    /// a correlation id fail with [ClientError::HandlerTimeout](crate::ClientError::HandlerTimeout)
+    /// and free-form senders are told the service timed out.
+    /// Subscriptions may override it with [Subscription::handler_timeout].
+    ///
+    /// Only producing a reply is limited, streamed replies may keep coming for longer.
+    pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Hands a record of every message dispatched to subscriptions to `sink`, e.g. a [FileAudit](crate::FileAudit),
+    /// with its time, peer, session identity, service and payload redacted by [AuditSink::redact](crate::AuditSink::redact).
+    ///
+    /// Auditing is a [Builder::layer], messages refused by layers added before are not recorded.
+    pub fn audit(self, sink: impl AuditSink + 'static) -> Self {
+        self.layer(Audit(sink))
+    }
+
+    /// Exposes `service_id` only to peers within one of `networks`,
+    /// e.g. an `Exec` service only to loopback while other services stay reachable from a management subnet.
+    /// Requests from other peers are refused with an "access denied" error, free-form messages
+    /// left unanswered because of the restriction get an "access denied" response.
+    pub fn restrict_service(mut self, service_id: Services, networks: &[IpNet]) -> Self {
+        self.policy.networks.insert(service_id, networks.to_vec());
+        self
+    }
+
+    /// Enables the `elevate <reason>` text command: `approver` is asked to grant elevated access
+    /// and returns for how long, or `None` to deny it. The access is dropped once it expires.
+    /// Elevation is reported by [AuthContext::is_elevated], typically checked in the [Builder::authorizer]
+    /// to allow mutating services just in time.
+    pub fn elevation<F>(mut self, approver: F) -> Self
+    where
+        F: Fn(&AuthContext, &str) -> Option<Duration> + Send + Sync + 'static,
+    {
+        self.settings.approver = Some(Box::new(approver));
+        self
+    }
+
+    /// Keeps the last `capacity` commands of every identity in memory,
+    /// see [Builder::history_store].
+    pub fn command_history(self, capacity: usize) -> Self {
+        self.history_store(MemoryHistory::new(capacity))
+    }
+
+    /// Records every executed command per identity in `store`
+    /// and enables the `audit <identity>` text command listing them.
+    /// Until sessions authenticate, the identity of a session is its peer IP address.
+    pub fn history_store(mut self, store: impl HistoryStore + 'static) -> Self {
+        self.settings.history = Some(Arc::new(store));
+        self
+    }
+
+    /// Shares host dependencies, such as app handles or connection pools, with all handlers,
+    /// which look them up in their [Context](crate::Context).
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.settings.extensions = Arc::new(extensions);
+        self
+    }
+
+    /// Enables the `selftest` text command, which probes every registered subscription with
+    /// [Subscription::health] and reports which ones answer healthy within `timeout`,
+    /// a quick smoke check after deploys.
+    pub fn selftest(mut self, timeout: Duration) -> Self {
+        self.settings.selftest = Some(timeout);
+        self
+    }
+
+    /// Enables the `help` text command listing the services a session may use,
+    /// with their [Subscription::description], and the enabled built-in commands.
+    pub fn with_help(mut self) -> Self {
+        self.settings.help = true;
+        self
+    }
+
+    /// Reports the metrics of `provider`, e.g. domain counters and gauges of the application,
+    /// alongside the console's own, such as in the output of the `stats` command.
+    /// Can be called several times to add more providers.
+    pub fn metrics_provider(mut self, provider: impl MetricsProvider + 'static) -> Self {
+        self.settings.metrics_providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Enables the `color on|off` text command, letting operators strip ANSI escapes,
+    /// e.g. written with [Styled](crate::Styled), from the text sent to their session.
+    /// Colors are on by default for interactive sessions and always off for programmatic clients.
+    pub fn color_command(mut self) -> Self {
+        self.settings.color_command = true;
+        self
+    }
+
+    /// Enables the `stats [service]` text command reporting calls, errors and latencies
+    /// of all services or of the given one, as returned by [Console::service_stats].
+    /// Without a service, metrics of the [Builder::metrics_provider]s follow.
+    pub fn stats_command(mut self) -> Self {
+        self.settings.stats_command = true;
+        self
+    }
+
+    /// Enables the `runtime` text command reporting the uptime of the console, its open sessions,
+    /// the workers, alive tasks and global queue depth of the tokio runtime, and the resident memory
+    /// of the process where the platform exposes it, e.g. `/proc` on Linux.
+    pub fn runtime_command(mut self) -> Self {
+        self.settings.runtime_command = true;
+        self
+    }
+
+    /// Enables the `script` text command, after which a session runs a runbook piped into it,
+    /// e.g. `nc -N localhost 3838 < runbook.txt` with `script` as the first line: every following line
+    /// is handled in order, blank ones and `#` comments aside, and its response is preceded by
+    /// `> ` and the line itself. Streamed responses are not interrupted by the next line and no prompt
+    /// is sent. The session closes once the input ends and every line is answered.
+    pub fn script_command(mut self) -> Self {
+        self.settings.script_command = true;
+        self
+    }
+
+    /// Enables the `sessions` text command listing the open sessions with their peer, connection time
+    /// and traffic, as returned by [Console::sessions]. Sessions are closed with [Console::kick].
+    pub fn sessions_command(mut self) -> Self {
+        self.settings.sessions_command = true;
+        self
+    }
+
+    /// Enables the `tz [zone]` text command, letting operators across regions have timestamps
+    /// of built-in commands, such as `audit`, rendered in their time zone rather than UTC.
+    /// Zones are `UTC`, fixed offsets such as `+02:00`, or IANA names such as `Europe/Berlin`
+    /// found in the system time zone database.
+    pub fn time_zones(mut self) -> Self {
+        self.settings.time_zones = true;
+        self
+    }
+
+    /// Enables the `subscribe <topic>` and `unsubscribe <topic>` text commands: sessions subscribed
+    /// to a topic receive everything published to it with [Console::publish](crate::Console::publish),
+    /// turning the console into a live event monitor.
+    pub fn topics(mut self) -> Self {
+        self.settings.topics = true;
+        self
+    }
+
+    /// Lets sessions opt into receiving text replies as line-based deltas against the previous reply
+    /// with the `delta on` command, cutting traffic when polling large status replies.
+    /// [Client::enable_delta_updates](crate::Client::enable_delta_updates) negotiates and decodes them.
+    pub fn delta_updates(mut self) -> Self {
+        self.settings.delta_updates = true;
+        self
+    }
+
+    /// Calls `hook` whenever a session opens, e.g. to set up per-session resources
+    /// keyed by [Context::session_id](crate::Context::session_id). A returned greeting,
+    /// such as a dynamic status line, is appended to the welcome message.
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        self.settings.on_connect = Some(Box::new(hook));
+        self
+    }
+
+    /// Calls `hook` whenever a session closes, by either side or on shutdown,
+    /// e.g. to clean up what [Builder::on_connect] set up.
+    pub fn on_disconnect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Context) + Send + Sync + 'static,
+    {
+        self.settings.on_disconnect = Some(Box::new(hook));
+        self
+    }
+
+    /// Chooses how free-form messages are dispatched, offering them to every subscription until
+    /// the first success by default. [WeakDispatch::Prefix] makes the answering service predictable
+    /// when several subscriptions accept similar text.
+    pub fn weak_dispatch(mut self, mode: WeakDispatch) -> Self {
+        self.settings.weak_dispatch = mode;
         self
     }
 
+    /// Seeds the random order in which free-form messages are offered to subscriptions,
+    /// so that which subscription answers first is reproducible across runs.
+    /// Every session replays the same sequence of orders.
+    pub fn weak_dispatch_seed(mut self, seed: u64) -> Self {
+        self.settings.weak_dispatch_seed = Some(seed);
+        self
+    }
+
+    /// Chooses what the sender of a message is told when the subscription handling it fails:
+    /// nothing by default, only the failing service with [ErrorReporting::Summary],
+    /// or the error as well with [ErrorReporting::Full]. Typed requests still get their acknowledgement.
+    pub fn error_reporting(mut self, reporting: ErrorReporting) -> Self {
+        self.settings.error_reporting = reporting;
+        self
+    }
+
+    /// Chooses the fields of the `session` span wrapping each session and the `dispatch` span
+    /// wrapping each message handed to a subscription, e.g. to filter one operator's actions
+    /// in the logs by `identity`. Dropping `peer` keeps addresses out of the logs.
+    pub fn span_fields(mut self, fields: SpanFields) -> Self {
+        self.settings.span_fields = fields;
+        self
+    }
+
+    /// Calls `tap` with the raw bytes of every session, e.g. to debug a client at the wire level:
+    /// bytes read from the peer as [WireDirection::Inbound] before they are decoded, as they arrive,
+    /// and every frame written to the peer as [WireDirection::Outbound] once encoded.
+    /// `tap` runs on the session tasks, so it must be quick.
+    pub fn frame_tap<F>(mut self, tap: F) -> Self
+    where
+        F: Fn(WireDirection, &Bytes) + Send + Sync + 'static,
+    {
+        self.settings.frame_tap = Some(Arc::new(tap));
+        self
+    }
+
+    /// Silences per-message debug logs on the [DISPATCH](crate::targets::DISPATCH) target,
+    /// keeping connection-level logs and dispatch warnings.
+    pub fn quiet_messages(mut self) -> Self {
+        self.settings.quiet_messages = true;
+        self
+    }
+
+    /// Injects faults into frames sent by the console, see [FaultInjector](crate::FaultInjector).
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(mut self, injector: crate::FaultInjector) -> Self {
+        self.settings.fault_injector = Some(injector);
+        self
+    }
+}
+
+impl<Services, A, S> Builder<Services, A, S> {
+    /// Lets the console be spawned before all subscriptions are registered, e.g. by an application
+    /// with a long initialization phase, so [Builder::build] no longer requires a subscription.
+    /// Subscriptions are then added with [Console::subscribe] and the phase ends with [Console::ready].
+    /// Until then, messages for services not registered yet are answered "Console is starting up, retry later".
+    pub fn starting_up(mut self) -> Builder<Services, A, Subscribed> {
+        self.settings.starting_up = true;
+        Builder {
+            subscriptions: self.subscriptions,
+            policy: self.policy,
+            bind_address: self.bind_address,
+            settings: self.settings,
+            _subscribed: PhantomData,
+        }
+    }
+}
+
+impl<Services, A> Builder<Services, A, Subscribed>
+where
+    Services: ServiceId,
+    A: ToSocketAddrs,
+{
+    /// Builds the [Console], failing with [Error::InvalidSubscriptions]
+    /// if any subscription fails [Subscription::validate].
     pub fn build(self) -> Result<Console<Services, A>, Error> {
+        finish(
+            self.subscriptions,
+            self.policy,
+            self.bind_address,
+            self.settings,
+        )
+    }
+}
+
+impl<Services> Builder<Services, NoAddress, Subscribed>
+where
+    Services: ServiceId,
+{
+    /// Builds a [Console] which does not listen for connections,
+    /// but serves sessions over outbound connections, see [Console::dial].
+    pub fn build_outbound(self) -> Result<Console<Services, NoAddress>, Error> {
+        finish(self.subscriptions, self.policy, NoAddress, self.settings)
+    }
+}
+
+impl<Services, A, S> Builder<Services, Option<A>, S>
+where
+    Services: ServiceId,
+    A: ToSocketAddrs,
+{
+    /// Builds a [Console] from a configuration only known at runtime,
+    /// failing if the bind address is missing. A console without subscriptions is allowed.
+    pub fn try_build(self) -> Result<Console<Services, A>, Error> {
         let Some(bind_address) = self.bind_address else {
             return Err(Error::NoBindAddress);
         };
 
-        Ok(Console::new(
-            self.subscriptions,
-            bind_address,
-            ensure_newline(self.welcome.unwrap_or_default()),
-            self.accept_only_localhost,
-        ))
+        finish(self.subscriptions, self.policy, bind_address, self.settings)
     }
 }
 
-impl<Services, A> Default for Builder<Services, A>
+fn finish<Services: ServiceId, A>(
+    subscriptions: Subscriptions<Services>,
+    policy: Policy<Services>,
+    bind_address: A,
+    mut settings: Settings,
+) -> Result<Console<Services, A>, Error> {
+    subscriptions.validate()?;
+    settings.welcome = ensure_newline(settings.welcome);
+
+    Ok(Console::new(subscriptions, policy, bind_address, settings))
+}
+
+impl<Services> Default for Builder<Services>
 where
-    Services: Eq + Hash + Debug,
-    A: ToSocketAddrs,
+    Services: ServiceId,
 {
     fn default() -> Self {
         Self::new()
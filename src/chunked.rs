@@ -0,0 +1,118 @@
+//! Typed frames split into chunks, see [Builder::chunk_size](crate::Builder::chunk_size).
+//!
+//! A chunked frame is sent as a begin frame announcing the total size, chunk frames with the
+//! payload and an end frame, all typed frames starting with [MARKER] and their kind.
+
+use crate::codec::Frame;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+
+/// Payload of typed frames sent in one piece by default.
+pub(crate) const CHUNK_SIZE: usize = 256 << 10;
+
+/// First byte of chunked transfer frames. A message envelope starts with the tag of its optional id,
+/// control and compressed frames with their own markers, which are never this.
+const MARKER: u8 = 0xFD;
+
+const BEGIN: u8 = 0;
+const CHUNK: u8 = 1;
+const END: u8 = 2;
+
+/// Most a client reserves up front for a chunked frame, whatever size it announces.
+const MAX_RESERVED: usize = 16 << 20;
+
+/// Whether `frame` is to be sent as several frames of at most `chunk_size` bytes.
+pub(crate) fn needs_split(frame: &Frame, chunk_size: usize) -> bool {
+    matches!(frame, Frame::Typed(bytes) if bytes.len() > chunk_size)
+}
+
+/// Splits a typed frame into begin, chunk and end frames.
+pub(crate) fn split(bytes: &Bytes, chunk_size: usize) -> Vec<Frame> {
+    let mut frames = Vec::with_capacity(bytes.len().div_ceil(chunk_size) + 2);
+    let mut begin = BytesMut::with_capacity(10);
+    begin.put_slice(&[MARKER, BEGIN]);
+    begin.put_u64(bytes.len() as u64);
+    frames.push(Frame::Typed(begin.freeze()));
+    for chunk in bytes.chunks(chunk_size.max(1)) {
+        let mut frame = BytesMut::with_capacity(chunk.len() + 2);
+        frame.put_slice(&[MARKER, CHUNK]);
+        frame.put_slice(chunk);
+        frames.push(Frame::Typed(frame.freeze()));
+    }
+    frames.push(Frame::Typed(Bytes::from_static(&[MARKER, END])));
+    frames
+}
+
+/// Reassembles chunked frames on the client side.
+#[derive(Default)]
+pub(crate) struct Reassembly {
+    /// Payload received so far, `None` outside a chunked frame.
+    buffer: Option<BytesMut>,
+}
+
+impl Reassembly {
+    /// Returns `frame` unless it is part of a chunked frame, the reassembled frame after its end,
+    /// and `None` while a chunked frame is incomplete.
+    pub(crate) fn push(&mut self, frame: Frame) -> io::Result<Option<Frame>> {
+        let bytes = match frame {
+            Frame::Typed(bytes) if bytes.first() == Some(&MARKER) => bytes,
+            frame => return Ok(Some(frame)),
+        };
+
+        match (bytes.get(1).copied(), &mut self.buffer) {
+            (Some(BEGIN), None) if bytes.len() == 10 => {
+                let total = u64::from_be_bytes(bytes[2..].try_into().expect("Length is checked"));
+                let reserved = usize::try_from(total)
+                    .unwrap_or(usize::MAX)
+                    .min(MAX_RESERVED);
+                self.buffer = Some(BytesMut::with_capacity(reserved));
+                Ok(None)
+            }
+            (Some(CHUNK), Some(buffer)) => {
+                buffer.put_slice(&bytes[2..]);
+                Ok(None)
+            }
+            (Some(END), Some(_)) => {
+                let buffer = self.buffer.take().expect("Buffer is matched");
+                Ok(Some(Frame::Typed(buffer.freeze())))
+            }
+            _ => {
+                self.buffer = None;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Received an out of order chunked frame",
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{needs_split, split, Reassembly};
+    use crate::codec::Frame;
+    use bytes::Bytes;
+
+    #[test]
+    fn chunked_frames_are_reassembled() -> std::io::Result<()> {
+        let bytes = Bytes::from_iter((0..10u8).cycle().take(25));
+        assert!(needs_split(&Frame::Typed(bytes.clone()), 10));
+        assert!(!needs_split(&Frame::Typed(bytes.clone()), 25));
+        assert!(!needs_split(&Frame::Text(bytes.clone()), 10));
+
+        let frames = split(&bytes, 10);
+        assert_eq!(frames.len(), 5);
+        let mut reassembly = Reassembly::default();
+        let text = Frame::Text(Bytes::from_static(b"passes through"));
+        assert_eq!(reassembly.push(text.clone())?, Some(text));
+        let mut reassembled = Vec::new();
+        for frame in frames {
+            reassembled.extend(reassembly.push(frame)?);
+        }
+        assert_eq!(reassembled, [Frame::Typed(bytes.clone())]);
+
+        let chunk = split(&bytes, 10).swap_remove(1);
+        assert!(reassembly.push(chunk).is_err());
+        Ok(())
+    }
+}
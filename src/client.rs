@@ -1,71 +1,633 @@
-use crate::console::Message;
+use crate::auth::{AUTH_COMMAND, AUTH_OK};
+use crate::batch::{self, Batch, Envelope};
+use crate::chunked::Reassembly;
+use crate::codec::{decode_payload, is_machine_hello, Frame, FrameCodec, MACHINE_HELLO};
+use crate::compression::{self, Compressor};
+use crate::console::{Message, Reply, ReplyError};
+use crate::control::{self, Control, BATCH_VERSION, HEARTBEAT_VERSION, LEGACY_VERSION};
+use crate::delta::{self, DELTA_ENABLED, DELTA_ON};
+use crate::ensure_newline;
+use crate::introspection::{self, ServiceInfo, SERVICES_COMMAND};
+use crate::subscription::{ConsoleError, TypedSubscription};
+use crate::targets::CLIENT;
+use crate::topics::SUBSCRIBE_COMMAND;
 use bytes::Bytes;
 use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio_util::codec::{BytesCodec, Framed};
+use tokio::time;
+use tokio_util::codec::Framed;
 use tracing::debug;
 
+/// Typed messages not answering a request kept until read, the oldest are dropped beyond that.
+const MAX_UNREAD_TYPED: usize = 64;
+
+/// Text frames received while waiting for replies kept until read, the oldest are dropped beyond that.
+pub(crate) const MAX_UNREAD_TEXTS: usize = 64;
+
+/// Connection settings of [Client]. No timeouts are applied by default.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// Shared secret to authenticate with, see [Builder::require_token](crate::Builder::require_token).
+    pub token: Option<String>,
+    /// Limit on establishing the connection, including the handshake skipping the welcome message.
+    pub connect_timeout: Option<Duration>,
+    /// Limit on waiting for a frame from [Console].
+    pub read_timeout: Option<Duration>,
+    /// Limit on sending a frame to [Console].
+    pub write_timeout: Option<Duration>,
+    /// Compressors typed frames from [Console] may be compressed with, by preference,
+    /// see [Builder::compressor](crate::Builder::compressor).
+    pub compressors: Vec<Arc<dyn Compressor>>,
+}
+
+/// Errors specific to [Client], reported wrapped into [anyhow::Error].
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Connecting to console timed out after {0:?}")]
+    ConnectTimeout(Duration),
+    #[error("Reading from console timed out after {0:?}")]
+    ReadTimeout(Duration),
+    #[error("Writing to console timed out after {0:?}")]
+    WriteTimeout(Duration),
+    #[error("Console refused authentication: {0}")]
+    AuthenticationFailed(String),
+    #[error("Console refused the topic subscription: {0}")]
+    TopicRefused(String),
+    #[error("Console does not support delta updates: {0}")]
+    DeltaUpdatesUnsupported(String),
+    #[error("Received delta does not match the previous text")]
+    DeltaMismatch,
+    #[error("Console speaks protocol versions {offered:?}, the client {supported:?}")]
+    ProtocolMismatch {
+        offered: Vec<u32>,
+        supported: Vec<u32>,
+    },
+    #[error("Console does not support heartbeats with protocol version {0}")]
+    HeartbeatUnsupported(u32),
+    #[error("Console does not support batches with protocol version {0}")]
+    BatchUnsupported(u32),
+    #[error("Console has no service `{0}`")]
+    UnknownService(String),
+    #[error("Console handler failed: {0}")]
+    Failed(ConsoleError),
+    #[error("Console handler timed out after {0:?}")]
+    HandlerTimeout(Duration),
+    #[error("Console handler panicked")]
+    HandlerPanicked,
+    #[error("Console refused the message: {0}")]
+    Refused(String),
+    #[error("Console does not list its services: {0}")]
+    IntrospectionUnsupported(String),
+    #[error("Response does not match {expected}, console and client may be out of sync: {reason}")]
+    UnexpectedResponse {
+        /// Type the response was expected to decode into.
+        expected: &'static str,
+        reason: String,
+    },
+}
+
+/// Byte stream a [Client] talks to [Console] over, a TCP connection or an in-memory pipe.
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Transport for T {}
+
 /// Client for [Console].
+///
+/// Typed requests can be pipelined: [Client::send_request] returns a correlation id
+/// which can later be passed to [Client::recv_response] in any order.
 pub struct Client {
-    stream: Framed<TcpStream, BytesCodec>,
+    stream: Framed<Box<dyn Transport>, FrameCodec>,
+    pub(crate) config: ClientConfig,
+    /// Protocol version negotiated with [Console].
+    protocol_version: u32,
+    /// When the last frame from [Console] arrived.
+    last_heard: Instant,
+    /// Heartbeat answers received, see [Client::ping].
+    pongs: u64,
+    /// Compressor [Console] compresses typed frames with, see [ClientConfig::compressors].
+    compressor: Option<Arc<dyn Compressor>>,
+    /// Chunked frame being received, see [Builder::chunk_size](crate::Builder::chunk_size).
+    reassembly: Reassembly,
+    /// Correlation id for the next request.
+    next_id: u64,
+    /// Replies received while waiting for other frames.
+    replies: HashMap<u64, Result<Option<Bytes>, ReplyError>>,
+    /// Text frames received while waiting for replies.
+    texts: VecDeque<Bytes>,
+    /// Typed messages not answering a request, kept until [Client::recv_typed].
+    typed: VecDeque<Bytes>,
+    /// Last text received since enabling delta updates, the base of the next delta.
+    last_text: Option<String>,
 }
 
 impl Client {
-    pub async fn new<A: ToSocketAddrs>(address: A) -> anyhow::Result<Self> {
-        // Connect to the TCP console server.
-        let mut stream = Framed::new(TcpStream::connect(address).await?, BytesCodec::new());
-        debug!("Connected to server");
+    /// Connects to [Console], authenticating with `token` if given.
+    pub async fn new<A: ToSocketAddrs>(address: A, token: Option<&str>) -> anyhow::Result<Self> {
+        let config = ClientConfig {
+            token: token.map(str::to_owned),
+            ..ClientConfig::default()
+        };
+        Self::with_config(address, config).await
+    }
+
+    /// Connects to [Console] applying the timeouts from `config` to this and all subsequent calls.
+    pub async fn with_config<A: ToSocketAddrs>(
+        address: A,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let connect = async {
+            // Connect to the TCP console server.
+            let stream = TcpStream::connect(address).await?;
+            debug!(target: CLIENT, "Connected to server");
+            Self::handshake(Box::new(stream), config.clone()).await
+        };
+
+        limit(config.connect_timeout, ClientError::ConnectTimeout, connect).await
+    }
+
+    /// Talks to [Console] over `stream`, e.g. an in-memory pipe, see [Console::connect_in_memory].
+    pub(crate) async fn over(
+        stream: Box<dyn Transport>,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let timeout = config.connect_timeout;
+        limit(
+            timeout,
+            ClientError::ConnectTimeout,
+            Self::handshake(stream, config),
+        )
+        .await
+    }
+
+    /// Skips the welcome message and authenticates if `config` carries a token.
+    async fn handshake(stream: Box<dyn Transport>, config: ClientConfig) -> anyhow::Result<Self> {
+        let mut stream = Framed::new(stream, FrameCodec::client());
+        // Announce a programmatic client, anything before the acknowledgement is meant for humans.
+        stream.send(MACHINE_HELLO).await?;
+        let protocol_version = loop {
+            match stream.next().await {
+                Some(Ok(frame)) if is_machine_hello(&frame) => break LEGACY_VERSION,
+                Some(Ok(frame)) => {
+                    if let Some(Control::Offer(offered)) = Control::from_frame(&frame) {
+                        let version = control::choose(&offered)?;
+                        stream.send(Control::Select(version).to_frame()).await?;
+                        if let Some(accept) = control::accept(version, &config.compressors) {
+                            stream.send(accept.to_frame()).await?;
+                        }
+                        break version;
+                    }
+                }
+                Some(Err(e)) => return Err(anyhow::Error::from(e)),
+                None => return Err(anyhow::Error::msg("Connection closed unexpectedly")),
+            }
+        };
+
+        let mut client = Client {
+            stream,
+            config: config.clone(),
+            protocol_version,
+            last_heard: Instant::now(),
+            pongs: 0,
+            compressor: None,
+            reassembly: Reassembly::default(),
+            next_id: 0,
+            replies: HashMap::new(),
+            texts: VecDeque::new(),
+            typed: VecDeque::new(),
+            last_text: None,
+        };
+        if let Some(token) = &config.token {
+            client.authenticate(token).await?;
+        }
+
+        Ok(client)
+    }
+
+    /// Protocol version negotiated with [Console] when connecting.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// When the last frame from [Console] was received, heartbeats included.
+    /// Frames are only received while the client is waiting for something, e.g. in [Client::ping].
+    pub fn last_heard(&self) -> Instant {
+        self.last_heard
+    }
+
+    /// Checks that [Console] is still there, returns the round-trip time.
+    /// Frames received meanwhile are kept for the calls waiting for them.
+    ///
+    /// The client answers the console's heartbeats, see [Builder::heartbeat](crate::Builder::heartbeat),
+    /// whenever it receives frames, so an otherwise idle client should call this regularly.
+    pub async fn ping(&mut self) -> anyhow::Result<Duration> {
+        if self.protocol_version < HEARTBEAT_VERSION {
+            return Err(ClientError::HeartbeatUnsupported(self.protocol_version).into());
+        }
 
-        // Receive the welcome message.
-        match stream.next().await {
-            Some(Ok(_bytes)) => Ok(Client { stream }),
-            Some(Err(e)) => Err(anyhow::Error::from(e)),
-            None => Err(anyhow::Error::msg("Connection closed unexpectedly")),
+        let started = Instant::now();
+        let pongs = self.pongs;
+        self.write_frame(Control::Ping.to_frame()).await?;
+        while self.pongs == pongs {
+            let frame = self.receive().await?;
+            if Control::is_control(&frame) {
+                continue;
+            }
+            match frame {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => self.stash_text(bytes),
+            }
         }
+        Ok(started.elapsed())
     }
 
     /// Sends a message to [Console] with any serializable payload.
+    ///
+    /// The message carries no correlation id, so the reply, if any, is not tracked.
     pub async fn send<S: Serialize, M: Serialize>(
         &mut self,
         service_id: S,
         message: &M,
     ) -> anyhow::Result<()> {
-        let console_message = Message::new(service_id, message)?;
+        self.send_message(Message::new(None, service_id, message)?)
+            .await
+    }
 
-        // Create bytes to send.
-        let bytes: Bytes = bcs::to_bytes(&console_message)?.into();
+    /// Sends a message to [Console] with any serializable payload
+    /// and returns the correlation id to await the reply with [Client::recv_response].
+    pub async fn send_request<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
 
-        // Send bytes.
-        self.stream.send(bytes).await?;
+        self.send_message(Message::new(Some(id), service_id, message)?)
+            .await?;
 
-        Ok(())
+        Ok(id)
+    }
+
+    /// Same as [Client::send_request], for `version` of the service's API,
+    /// see [Builder::subscribe_version](crate::Builder::subscribe_version).
+    pub async fn send_versioned_request<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        version: u32,
+        message: &M,
+    ) -> anyhow::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let message = Message::new(Some(id), service_id, message)?.with_version(version);
+        self.send_message(message).await?;
+
+        Ok(id)
+    }
+
+    /// Waits for the reply to the request with the given correlation id.
+    /// Replies to other in-flight requests received meanwhile are kept until asked for.
+    pub async fn recv_response(&mut self, id: u64) -> anyhow::Result<Option<Bytes>> {
+        loop {
+            if let Some(reply) = self.replies.remove(&id) {
+                return Ok(reply.map_err(ClientError::from)?);
+            }
+
+            match self.read_frame().await? {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => self.stash_text(bytes),
+            }
+        }
+    }
+
+    /// Same as [Client::recv_response], but fails with [ClientError::ReadTimeout]
+    /// if the reply does not arrive within `timeout`.
+    pub async fn recv_response_timeout(
+        &mut self,
+        id: u64,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<Bytes>> {
+        limit(
+            Some(timeout),
+            ClientError::ReadTimeout,
+            self.recv_response(id),
+        )
+        .await
+    }
+
+    /// Sends a message to [Console] and waits for its reply.
+    pub async fn send_recv<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let id = self.send_request(service_id, message).await?;
+        self.recv_response(id).await
+    }
+
+    /// Sends a message to [Console] and decodes its reply into `R`, failing with
+    /// [ClientError::UnexpectedResponse] unless the reply is exactly a valid `R`,
+    /// e.g. after the service changed its response type.
+    pub async fn request<S: Serialize, M: Serialize, R: DeserializeOwned>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<R>> {
+        let Some(bytes) = self.send_recv(service_id, message).await? else {
+            return Ok(None);
+        };
+
+        let response =
+            decode_payload(bytes.as_ref()).map_err(|err| ClientError::UnexpectedResponse {
+                expected: std::any::type_name::<R>(),
+                reason: err.to_string(),
+            })?;
+        Ok(Some(response))
+    }
+
+    /// Same as [Client::request], with the request and response types of the [TypedSubscription]
+    /// `T` serving `service_id`, so that the call is type-checked against the handler,
+    /// e.g. `client.call::<Exec, _>(Services::Exec, &command)` with `Exec` shared by both ends.
+    pub async fn call<T, S>(
+        &mut self,
+        service_id: S,
+        request: &T::Request,
+    ) -> anyhow::Result<Option<T::Response>>
+    where
+        T: TypedSubscription,
+        T::Request: Serialize,
+        T::Response: DeserializeOwned,
+        S: Serialize,
+    {
+        self.request(service_id, request).await
+    }
+
+    /// Sends the messages of `batch` in one request, which [Console] handles one after the other,
+    /// and returns the result of each in order: its reply or why it was not handled.
+    /// A failing message does not stop the following ones.
+    pub async fn send_batch<S: Serialize>(
+        &mut self,
+        batch: Batch<S>,
+    ) -> anyhow::Result<Vec<Result<Option<Bytes>, ClientError>>> {
+        if self.protocol_version < BATCH_VERSION {
+            return Err(ClientError::BatchUnsupported(self.protocol_version).into());
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.write_frame(Envelope::new(id, batch).to_frame()?)
+            .await?;
+
+        let bytes = self.recv_response(id).await?.ok_or(anyhow::anyhow!(
+            "Console answered the batch without results"
+        ))?;
+        let results: batch::Results = bcs::from_bytes(bytes.as_ref())?;
+        Ok(results
+            .into_iter()
+            .map(|result| result.map_err(ClientError::from))
+            .collect())
+    }
+
+    /// Receives a typed message not answering a request: a [Console::broadcast_typed](crate::Console::broadcast_typed)
+    /// or the reply to a message sent with [Client::send]. Only the latest 64 unread ones are kept.
+    pub async fn recv_typed(&mut self) -> anyhow::Result<Bytes> {
+        loop {
+            if let Some(bytes) = self.typed.pop_front() {
+                return Ok(bytes);
+            }
+
+            match self.read_frame().await? {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => self.stash_text(bytes),
+            }
+        }
     }
 
     /// Sends a message to [Console] with any text.
     pub async fn weak_send(&mut self, message: &str) -> anyhow::Result<()> {
-        let bytes: Bytes = message.as_bytes().to_vec().into();
-        self.stream.send(bytes).await?;
-
-        Ok(())
+        let bytes: Bytes = ensure_newline(message.to_owned()).into_bytes().into();
+        self.write_frame(Frame::Text(bytes)).await
     }
 
     /// Receives a text message from [Console].
+    /// Of the texts received while waiting for replies, only the latest 64 unread ones are kept.
     pub async fn weak_read(&mut self) -> anyhow::Result<String> {
-        let bytes = self
-            .stream
-            .next()
-            .await
-            .ok_or(anyhow::anyhow!("Connection closed unexpectedly"))??
-            .freeze();
+        Ok(self.read_text().await?.trim().to_string())
+    }
+
+    /// Lists the services this client may use on [Console], with their versions and descriptions.
+    pub async fn list_services(&mut self) -> anyhow::Result<Vec<ServiceInfo>> {
+        self.weak_send(SERVICES_COMMAND).await?;
+        // A long listing may span several text messages.
+        let mut listing = String::new();
+        loop {
+            listing.push_str(&self.read_text().await?);
+            match introspection::parse(listing.trim_start()) {
+                Ok(Some(services)) => return Ok(services),
+                Ok(None) => {}
+                Err(()) => {
+                    return Err(
+                        ClientError::IntrospectionUnsupported(listing.trim().to_owned()).into(),
+                    )
+                }
+            }
+        }
+    }
+
+    /// Receives a text message from [Console] as sent, decoding deltas.
+    async fn read_text(&mut self) -> anyhow::Result<String> {
+        let bytes = loop {
+            if let Some(bytes) = self.texts.pop_front() {
+                break bytes;
+            }
+
+            match self.read_frame().await? {
+                Frame::Typed(bytes) => self.stash_reply(bytes)?,
+                Frame::Text(bytes) => break bytes,
+            }
+        };
+
+        let mut text = String::from_utf8_lossy(bytes.as_ref()).into_owned();
+        if let Some(previous) = &self.last_text {
+            if text.starts_with(delta::DELTA_MARKER) {
+                text = delta::apply(previous, &text).ok_or(ClientError::DeltaMismatch)?;
+            }
+            self.last_text = Some(text.clone());
+        }
+
+        Ok(text)
+    }
+
+    /// Subscribes to everything published to `topic`, see [Builder::topics](crate::Builder::topics).
+    /// Published texts are received with [Client::weak_read], typed messages with [Client::recv_typed].
+    pub async fn subscribe_topic(&mut self, topic: &str) -> anyhow::Result<()> {
+        self.weak_send(&format!("{SUBSCRIBE_COMMAND} {topic}"))
+            .await?;
+        let answer = self.weak_read().await?;
+        if answer == format!("Subscribed to {topic}")
+            || answer == format!("Already subscribed to {topic}")
+        {
+            Ok(())
+        } else {
+            Err(ClientError::TopicRefused(answer).into())
+        }
+    }
+
+    /// Asks [Console] to send subsequent text replies as deltas against the previous one,
+    /// see [Builder::delta_updates](crate::Builder::delta_updates). [Client::weak_read] decodes them.
+    ///
+    /// Deltas rely on every text being read in order, one reply per text message.
+    pub async fn enable_delta_updates(&mut self) -> anyhow::Result<()> {
+        self.weak_send(DELTA_ON).await?;
+        match self.weak_read().await?.as_str() {
+            DELTA_ENABLED => {
+                self.last_text = Some(ensure_newline(DELTA_ENABLED.to_owned()));
+                Ok(())
+            }
+            answer => Err(ClientError::DeltaUpdatesUnsupported(answer.to_owned()).into()),
+        }
+    }
+
+    /// Same as [Client::weak_read], but fails with [ClientError::ReadTimeout]
+    /// if no text arrives within `timeout`.
+    pub async fn weak_read_timeout(&mut self, timeout: Duration) -> anyhow::Result<String> {
+        limit(Some(timeout), ClientError::ReadTimeout, self.weak_read()).await
+    }
+
+    async fn authenticate(&mut self, token: &str) -> anyhow::Result<()> {
+        self.weak_send(&format!("{AUTH_COMMAND} {token}")).await?;
+        match self.weak_read().await?.as_str() {
+            AUTH_OK => {
+                debug!(target: CLIENT, "Authenticated");
+                Ok(())
+            }
+            answer => Err(ClientError::AuthenticationFailed(answer.to_owned()).into()),
+        }
+    }
+
+    async fn send_message<S: Serialize>(&mut self, message: Message<S>) -> anyhow::Result<()> {
+        // Create bytes to send.
+        let bytes: Bytes = bcs::to_bytes(&message)?.into();
+
+        // Send bytes.
+        self.write_frame(Frame::Typed(bytes)).await
+    }
+
+    pub(crate) async fn write_frame(&mut self, frame: Frame) -> anyhow::Result<()> {
+        let write = async { Ok(self.stream.send(frame).await?) };
+        limit(self.config.write_timeout, ClientError::WriteTimeout, write).await
+    }
+
+    /// Receives the next frame which is not a control frame.
+    pub(crate) async fn read_frame(&mut self) -> anyhow::Result<Frame> {
+        loop {
+            let frame = self.receive().await?;
+            if !Control::is_control(&frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Receives the next frame, reassembling chunked frames, answering and counting heartbeats.
+    async fn receive(&mut self) -> anyhow::Result<Frame> {
+        let frame = loop {
+            let read = async {
+                Ok(self
+                    .stream
+                    .next()
+                    .await
+                    .ok_or(anyhow::anyhow!("Connection closed unexpectedly"))??)
+            };
+            let frame = limit(self.config.read_timeout, ClientError::ReadTimeout, read).await?;
+            self.last_heard = Instant::now();
+            if let Some(frame) = self.reassembly.push(frame)? {
+                break frame;
+            }
+        };
+        let frame = compression::decompress(frame, self.compressor.as_deref())?;
+        match Control::from_frame(&frame) {
+            Some(Control::Ping) => self.write_frame(Control::Pong.to_frame()).await?,
+            Some(Control::Pong) => self.pongs += 1,
+            Some(Control::Compress(name)) => {
+                self.compressor = control::negotiated(&name, &self.config.compressors);
+            }
+            Some(control) => debug!(target: CLIENT, "Ignoring an unexpected {control:?}"),
+            None => {}
+        }
+        Ok(frame)
+    }
 
-        Ok(String::from_utf8_lossy(bytes.as_ref()).trim().to_string())
+    /// Keeps a received reply until it is asked for.
+    fn stash_text(&mut self, bytes: Bytes) {
+        if self.texts.len() == MAX_UNREAD_TEXTS {
+            debug!(target: CLIENT, "Discarding the oldest unread text message");
+            self.texts.pop_front();
+        }
+        self.texts.push_back(bytes);
+    }
+
+    fn stash_reply(&mut self, bytes: Bytes) -> anyhow::Result<()> {
+        let Reply { id, bytes, error } = bcs::from_bytes(bytes.as_ref())?;
+
+        match (id, bytes) {
+            (Some(id), bytes) => {
+                self.replies.insert(id, error.map_or(Ok(bytes), Err));
+            }
+            (None, Some(bytes)) => {
+                if self.typed.len() == MAX_UNREAD_TYPED {
+                    debug!(target: CLIENT, "Discarding the oldest unread typed message");
+                    self.typed.pop_front();
+                }
+                self.typed.push_back(bytes);
+            }
+            (None, None) => {
+                debug!(target: CLIENT, "Discarding an empty reply without correlation id")
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<ReplyError> for ClientError {
+    fn from(error: ReplyError) -> Self {
+        match error {
+            ReplyError::UnknownService(name) => ClientError::UnknownService(name),
+            ReplyError::Refused(reason) => ClientError::Refused(reason),
+            ReplyError::Failed(error) => ClientError::Failed(error),
+            ReplyError::TimedOut(timeout) => ClientError::HandlerTimeout(timeout),
+            ReplyError::Panicked => ClientError::HandlerPanicked,
+        }
+    }
+}
+
+/// Runs `future` failing with `error` if it does not complete within the optional `timeout`.
+pub(crate) async fn limit<T>(
+    timeout: Option<Duration>,
+    error: fn(Duration) -> ClientError,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future)
+            .await
+            .map_err(|_| error(timeout))?,
+        None => future.await,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Subscription, SubscriptionError};
+    use super::MAX_UNREAD_TEXTS;
+    use crate::service::TestService;
+    use crate::{ClientConfig, ClientError, Subscription, SubscriptionError};
     use async_trait::async_trait;
     use bytes::Bytes;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
@@ -88,13 +650,13 @@ mod tests {
             let mut console = crate::Builder::new()
                 .bind_address(address)
                 .welcome("Welcome to TCP console!")
-                .subscribe(1u8, Test)?
+                .subscribe(TestService::One, Test)?
                 .accept_only_localhost()
                 .build()?;
 
             console.spawn().await?;
 
-            let mut client = crate::Client::new(address)
+            let mut client = crate::Client::new(address, None)
                 .await
                 .expect("Failed to create client");
 
@@ -111,6 +673,147 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn pipelined_requests() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Echo)?
+            .build()?;
+
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+
+        let first = client.send_request(TestService::One, &"first").await?;
+        let second = client.send_request(TestService::One, &"second").await?;
+        let unknown = client.send_request(TestService::Two, &"unknown").await?;
+
+        // Replies are matched to requests regardless of the order they are awaited in.
+        let reply = client
+            .recv_response(second)
+            .await?
+            .expect("Echo must reply");
+        assert_eq!(bcs::from_bytes::<String>(&reply)?, "second");
+        let reply = client.recv_response(first).await?.expect("Echo must reply");
+        assert_eq!(bcs::from_bytes::<String>(&reply)?, "first");
+        let err = client
+            .recv_response(unknown)
+            .await
+            .expect_err("Service two is not registered");
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::UnknownService(name)) if name == "two"
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unread_texts_are_bounded() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Echo)?
+            .build()?;
+
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+
+        // Texts echoed while waiting for replies are kept, the oldest are dropped.
+        time::timeout(Duration::from_secs(5), async {
+            for n in 0.. {
+                client.weak_send(&n.to_string()).await?;
+                let reply: Option<String> = client.request(TestService::One, &"typed").await?;
+                assert_eq!(reply.as_deref(), Some("typed"));
+                assert!(client.texts.len() <= MAX_UNREAD_TEXTS);
+                if n > MAX_UNREAD_TEXTS && client.texts.len() == MAX_UNREAD_TEXTS {
+                    break;
+                }
+            }
+            anyhow::Ok(())
+        })
+        .await??;
+        assert_ne!(client.weak_read().await?, "0");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn responses_are_validated() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Echo)?
+            .build()?;
+
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+
+        let reply: Option<String> = client.request(TestService::One, &"echo").await?;
+        assert_eq!(reply.as_deref(), Some("echo"));
+        assert!(client
+            .request::<_, _, u8>(TestService::Two, &7u8)
+            .await
+            .is_err());
+
+        // A string does not decode into a number, leftover bytes are a mismatch too.
+        let err = client
+            .request::<_, _, u64>(TestService::One, &"echo")
+            .await
+            .expect_err("String must not decode into a number");
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::UnexpectedResponse {
+                expected: "u64",
+                ..
+            })
+        ));
+        let err = client
+            .request::<_, _, u8>(TestService::One, &(1u8, 2u8))
+            .await
+            .expect_err("Leftover bytes must be reported");
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::UnexpectedResponse { expected: "u8", .. })
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_times_out() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Test)?
+            .build()?;
+
+        let address = console.spawn().await?;
+
+        let config = ClientConfig {
+            read_timeout: Some(Duration::from_millis(50)),
+            ..ClientConfig::default()
+        };
+        let mut client = crate::Client::with_config(address, config).await?;
+
+        // `Test` never replies.
+        client.weak_send("silence").await?;
+        let err = client.weak_read().await.expect_err("Read must time out");
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::ReadTimeout(_))
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
     struct Test;
 
     #[async_trait]
@@ -125,4 +828,17 @@ mod tests {
             Ok(None)
         }
     }
+
+    struct Echo;
+
+    #[async_trait]
+    impl Subscription for Echo {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(message.to_owned()))
+        }
+    }
 }
@@ -0,0 +1,475 @@
+use crate::console::{FrameTap, WireDirection};
+use crate::targets::SESSION;
+use crate::telnet::Telnet;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+/// Marker opening every typed frame.
+/// Neither byte can ever appear in valid UTF-8, so typed frames cannot be confused with text.
+pub(crate) const MAGIC: [u8; 2] = [0xC0, 0xC1];
+
+/// Size of a typed frame header: [MAGIC] followed by a big-endian `u32` payload length.
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// Longest typed payload accepted. A header announcing more is taken for garbage,
+/// e.g. pasted by an operator, and the decoder resynchronizes on the next [MAGIC].
+const MAX_TYPED_LEN: usize = 16 << 20;
+
+/// Empty typed frame a programmatic client opens the connection with, and the console answers with,
+/// so that frames meant for humans, such as the welcome message, are skipped.
+/// It cannot be confused with a message, whose envelope is never empty.
+pub(crate) const MACHINE_HELLO: Frame = Frame::Typed(Bytes::new());
+
+/// Encodes a typed payload, a request or response of a subscription. Payloads are encoded here only,
+/// so that subscriptions written against types, see [TypedSubscription](crate::TypedSubscription),
+/// never depend on the wire format.
+pub(crate) fn encode_payload<T: Serialize + ?Sized>(payload: &T) -> Result<Bytes, bcs::Error> {
+    bcs::to_bytes(payload).map(Bytes::from)
+}
+
+/// Decodes a typed payload encoded with [encode_payload].
+pub(crate) fn decode_payload<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, bcs::Error> {
+    bcs::from_bytes(bytes)
+}
+
+/// A unit of data exchanged between [Console](crate::Console) and its clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Frame {
+    /// A length-prefixed binary payload, carrying a BCS-encoded envelope.
+    Typed(Bytes),
+    /// Free-form text, e.g., typed by a human in netcat.
+    Text(Bytes),
+}
+
+/// How text is split into frames.
+#[derive(Debug, Clone, Copy)]
+enum TextFraming {
+    /// Every line is a frame. Used by the console to read commands.
+    Lines,
+    /// Everything received up to the next typed frame is a frame.
+    /// Used by clients, since text replies may span several lines.
+    Chunks,
+}
+
+/// A frame dropped for exceeding [FrameCodec::with_max_message_size].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Oversized {
+    /// Correlation id of a typed message, to acknowledge it.
+    pub(crate) id: Option<u64>,
+}
+
+/// Codec multiplexing typed and text frames over a single byte stream.
+pub(crate) struct FrameCodec {
+    text_framing: TextFraming,
+    /// Strips telnet negotiations from text, if enabled.
+    telnet: Option<Telnet>,
+    /// Longest typed payload or text line accepted.
+    max_message_size: Option<usize>,
+    /// Bytes of an oversized typed payload still to be dropped.
+    skip: usize,
+    /// Whether an oversized text line is being dropped up to its end.
+    skip_line: bool,
+    /// The last dropped frame, not reported yet.
+    oversized: Option<Oversized>,
+    /// Shown the bytes read and written, see [FrameCodec::with_tap].
+    tap: Option<FrameTap>,
+    /// Bytes at the end of the read buffer already shown to the tap.
+    tapped: usize,
+}
+
+impl FrameCodec {
+    /// Codec for the console side of a connection.
+    pub(crate) fn console() -> Self {
+        Self {
+            text_framing: TextFraming::Lines,
+            telnet: None,
+            max_message_size: None,
+            skip: 0,
+            skip_line: false,
+            oversized: None,
+            tap: None,
+            tapped: 0,
+        }
+    }
+
+    /// Strips and answers telnet negotiations, see [Builder::telnet](crate::Builder::telnet).
+    /// Answers are picked up with [FrameCodec::telnet_replies], the decoder yields an empty text frame
+    /// whenever there are some, so that they are not held back until a line is complete.
+    pub(crate) fn with_telnet(mut self) -> Self {
+        self.telnet = Some(Telnet::default());
+        self
+    }
+
+    pub(crate) fn telnet_replies(&mut self) -> Option<Bytes> {
+        self.telnet.as_mut()?.take_replies()
+    }
+
+    /// Drops typed payloads and text lines longer than `max` bytes as they arrive, instead of buffering them.
+    /// Dropped frames are picked up with [FrameCodec::take_oversized], announced by an empty text frame.
+    pub(crate) fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = Some(max);
+        self
+    }
+
+    pub(crate) fn take_oversized(&mut self) -> Option<Oversized> {
+        self.oversized.take()
+    }
+
+    /// Shows `tap` the bytes read as they arrive, before anything is decoded or stripped,
+    /// and every frame written once encoded, see [Builder::frame_tap](crate::Builder::frame_tap).
+    pub(crate) fn with_tap(mut self, tap: FrameTap) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Shows the tap the bytes appended to the read buffer since the last call.
+    fn tap_inbound(&mut self, src: &BytesMut) {
+        if let Some(tap) = &self.tap {
+            if src.len() > self.tapped {
+                tap(
+                    WireDirection::Inbound,
+                    &Bytes::copy_from_slice(&src[self.tapped..]),
+                );
+            }
+        }
+    }
+
+    /// Drops what remains of an oversized frame, returns whether it is entirely dropped.
+    fn skip_oversized(&mut self, src: &mut BytesMut) -> bool {
+        if self.skip > 0 {
+            let len = self.skip.min(src.len());
+            src.advance(len);
+            self.skip -= len;
+            return self.skip == 0;
+        }
+        if self.skip_line {
+            // The line ends with a newline, or is cut short by a typed frame.
+            let newline = src
+                .iter()
+                .position(|&b| b == b'\n')
+                .map(|newline| newline + 1);
+            let end = match (newline, find_marker(src)) {
+                (Some(newline), Some(marker)) => Some(newline.min(marker)),
+                (newline, marker) => newline.or(marker),
+            };
+            match end {
+                Some(end) => {
+                    src.advance(end);
+                    self.skip_line = false;
+                }
+                None => {
+                    // Keep a trailing marker byte in case the marker follows.
+                    let keep = usize::from(src.last() == Some(&MAGIC[0]));
+                    src.advance(src.len() - keep);
+                }
+            }
+            return !self.skip_line;
+        }
+        true
+    }
+
+    /// Records a dropped frame, returns the frame announcing it.
+    fn reject(&mut self, id: Option<u64>) -> Option<Frame> {
+        self.oversized = Some(Oversized { id });
+        Some(Frame::Text(Bytes::new()))
+    }
+
+    /// Codec for the client side of a connection.
+    pub(crate) fn client() -> Self {
+        Self {
+            text_framing: TextFraming::Chunks,
+            telnet: None,
+            max_message_size: None,
+            skip: 0,
+            skip_line: false,
+            oversized: None,
+            tap: None,
+            tapped: 0,
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.tap_inbound(src);
+        let frame = self.decode_frame(src);
+        // Decoding only ever consumes or strips bytes, whatever remains has been shown.
+        self.tapped = src.len();
+        frame
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            // An unterminated line is still a command.
+            None if !src.is_empty() && !src.starts_with(&MAGIC) => {
+                self.tapped = 0;
+                Ok(Some(Frame::Text(src.split().freeze())))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl FrameCodec {
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        if !self.skip_oversized(src) {
+            return Ok(None);
+        }
+        // Not a real header, but garbage which happens to contain the marker:
+        // skip the marker and resynchronize on the next one.
+        while src.starts_with(&MAGIC) && src.len() >= HEADER_LEN {
+            let len = u32::from_be_bytes([src[2], src[3], src[4], src[5]]) as usize;
+            if len <= MAX_TYPED_LEN {
+                break;
+            }
+            debug!(target: SESSION, "Typed frame of {len} bytes exceeds the limit of {MAX_TYPED_LEN} bytes. Skipping the marker.");
+            src.advance(MAGIC.len());
+        }
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        if src.starts_with(&MAGIC) {
+            if src.len() < HEADER_LEN {
+                return Ok(None);
+            }
+
+            let len = u32::from_be_bytes([src[2], src[3], src[4], src[5]]) as usize;
+            if self.max_message_size.is_some_and(|max| len > max) {
+                // The envelope opens with the correlation id: an option tag and a little-endian `u64`.
+                let peek = len.min(9);
+                if src.len() < HEADER_LEN + peek {
+                    return Ok(None);
+                }
+                let payload = &src[HEADER_LEN..HEADER_LEN + peek];
+                let id = match payload {
+                    [1, id @ ..] if id.len() == 8 => Some(u64::from_le_bytes(
+                        id.try_into().expect("Slice has 8 bytes"),
+                    )),
+                    _ => None,
+                };
+                src.advance(HEADER_LEN);
+                self.skip = len;
+                self.skip_oversized(src);
+                return Ok(self.reject(id));
+            }
+            if src.len() < HEADER_LEN + len {
+                src.reserve(HEADER_LEN + len - src.len());
+                return Ok(None);
+            }
+
+            src.advance(HEADER_LEN);
+            return Ok(Some(Frame::Typed(src.split_to(len).freeze())));
+        }
+
+        // Text runs up to the next marker. A trailing first byte of the marker
+        // is held back until it is known whether the marker follows.
+        let marker = find_marker(src);
+        let mut limit = match marker {
+            Some(marker) => marker,
+            None if src[src.len() - 1] == MAGIC[0] => src.len() - 1,
+            None => src.len(),
+        };
+        if let Some(telnet) = &mut self.telnet {
+            limit = telnet.strip(src, limit, marker.is_some());
+            if telnet.has_replies() {
+                return Ok(Some(Frame::Text(Bytes::new())));
+            }
+        }
+
+        let end = match self.text_framing {
+            TextFraming::Lines => match src[..limit].iter().position(|&b| b == b'\n') {
+                Some(newline) => newline + 1,
+                // An unterminated line followed by a typed frame is still a line.
+                None if marker.is_some() => limit,
+                None if self.max_message_size.is_some_and(|max| limit > max) => {
+                    // Too long already, drop what arrived and the rest of the line as it comes.
+                    src.advance(limit);
+                    self.skip_line = true;
+                    return Ok(self.reject(None));
+                }
+                None => return Ok(None),
+            },
+            TextFraming::Chunks => limit,
+        };
+        if self.max_message_size.is_some_and(|max| end > max) {
+            src.advance(end);
+            return Ok(self.reject(None));
+        }
+        if end == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(Frame::Text(src.split_to(end).freeze())))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        match frame {
+            Frame::Typed(bytes) => {
+                let len = u32::try_from(bytes.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Typed frame is too large")
+                })?;
+                dst.reserve(HEADER_LEN + bytes.len());
+                dst.put_slice(&MAGIC);
+                dst.put_u32(len);
+                dst.put_slice(&bytes);
+            }
+            Frame::Text(bytes) => dst.put_slice(&bytes),
+        }
+
+        if let Some(tap) = &self.tap {
+            tap(
+                WireDirection::Outbound,
+                &Bytes::copy_from_slice(&dst[start..]),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether `frame` only announces telnet answers or a dropped frame,
+/// see [FrameCodec::with_telnet] and [FrameCodec::with_max_message_size].
+pub(crate) fn is_wakeup(frame: &Frame) -> bool {
+    matches!(frame, Frame::Text(bytes) if bytes.is_empty())
+}
+
+pub(crate) fn is_machine_hello(frame: &Frame) -> bool {
+    matches!(frame, Frame::Typed(bytes) if bytes.is_empty())
+}
+
+/// Position of the first [MAGIC] marker in `src`.
+fn find_marker(src: &[u8]) -> Option<usize> {
+    src.windows(MAGIC.len()).position(|window| window == MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, FrameCodec, Oversized, MAGIC};
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn typed(payload: &'static [u8]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        FrameCodec::console()
+            .encode(Frame::Typed(Bytes::from_static(payload)), &mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn garbage_does_not_corrupt_typed_frames() {
+        let mut codec = FrameCodec::console();
+        let mut src = BytesMut::new();
+        // A lone marker byte, an unterminated line, a fake header announcing 4 GiB.
+        src.put_slice(b"\xC0 pasted");
+        src.unsplit(typed(b"first"));
+        src.put_slice(&MAGIC);
+        src.put_slice(&[0xFF; 4]);
+        src.put_slice(b"status\n");
+        src.unsplit(typed(b"second"));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut src).unwrap() {
+            frames.push(frame);
+        }
+
+        assert_eq!(
+            frames,
+            [
+                Frame::Text(Bytes::from_static(b"\xC0 pasted")),
+                Frame::Typed(Bytes::from_static(b"first")),
+                Frame::Text(Bytes::from_static(b"\xFF\xFF\xFF\xFFstatus\n")),
+                Frame::Typed(Bytes::from_static(b"second")),
+            ]
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn repeated_markers_are_skipped_without_recursion() {
+        let mut codec = FrameCodec::console();
+        // Every marker reads as a header announcing more than 3 GiB.
+        let mut src = BytesMut::from(MAGIC.repeat(1 << 20).as_slice());
+        src.unsplit(typed(b"first"));
+
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Frame::Typed(Bytes::from_static(b"first")))
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn oversized_frames_are_dropped() {
+        let mut codec = FrameCodec::console().with_max_message_size(8);
+        let mut src = BytesMut::new();
+        // A request with correlation id 7 and a line cut by a typed frame, both too long.
+        let mut request = vec![1, 7, 0, 0, 0, 0, 0, 0, 0];
+        request.resize(20, 0);
+        src.put_slice(&MAGIC);
+        src.put_u32(request.len() as u32);
+        src.put_slice(&request[..12]);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Frame::Text(Bytes::new()))
+        );
+        assert_eq!(codec.take_oversized(), Some(Oversized { id: Some(7) }));
+        assert!(src.is_empty());
+
+        src.put_slice(&request[12..]);
+        src.put_slice(b"far too long");
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Frame::Text(Bytes::new()))
+        );
+        assert_eq!(codec.take_oversized(), Some(Oversized { id: None }));
+        src.put_slice(b" indeed");
+        src.unsplit(typed(b"typed"));
+        src.put_slice(b"status\n");
+
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut src).unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            [
+                Frame::Typed(Bytes::from_static(b"typed")),
+                Frame::Text(Bytes::from_static(b"status\n")),
+            ]
+        );
+        assert_eq!(codec.take_oversized(), None);
+    }
+
+    #[test]
+    fn marker_split_across_reads_is_awaited() {
+        let mut codec = FrameCodec::client();
+        let mut src = BytesMut::from(&b"reply\xC0"[..]);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Frame::Text(Bytes::from_static(b"reply")))
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+        let mut rest = typed(b"typed");
+        let _ = rest.split_to(1);
+        src.unsplit(rest);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Frame::Typed(Bytes::from_static(b"typed")))
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Free-form commands whose arguments are parsed into a type, e.g. a `clap::Parser`.
+
+use crate::subscription::{Subscription, SubscriptionError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::future::Future;
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// Arguments of a [Command], parsed from the words of a free-form message.
+///
+/// `clap` derives plug in with a one-line implementation, its usage and error text
+/// is then what the operator gets back for a malformed command:
+///
+/// ```ignore
+/// impl Arguments for PeersArgs {
+///     fn parse(words: &[&str]) -> Result<Self, String> {
+///         Self::try_parse_from(words).map_err(|err| err.render().to_string())
+///     }
+/// }
+/// ```
+pub trait Arguments: Sized {
+    /// Parses `words`, the first of which is the name of the command, like `argv[0]`.
+    /// The error is sent to the operator as is.
+    fn parse(words: &[&str]) -> Result<Self, String>;
+}
+
+/// Why a free-form message could not be [split into words](split_words).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SplitError {
+    #[error("Missing closing quote {0}")]
+    UnterminatedQuote(char),
+    #[error("Nothing to escape after the trailing backslash")]
+    TrailingBackslash,
+}
+
+/// Splits `text` into words the way a shell does, so that handlers parse arguments consistently:
+/// words are separated by whitespace, which single or double quotes keep within a word,
+/// e.g. `say "hello world"` is `say` and `hello world`.
+///
+/// A backslash escapes the next character outside quotes and `"` or `\` within double quotes;
+/// single quotes keep everything as is.
+///
+/// ```
+/// use tcp_console::split_words;
+///
+/// assert_eq!(
+///     split_words(r#"say "hello world" it\'s 'a \ b'"#).unwrap(),
+///     ["say", "hello world", "it's", "a \\ b"]
+/// );
+/// assert!(split_words("say \"hello").is_err());
+/// ```
+pub fn split_words(text: &str) -> Result<Vec<String>, SplitError> {
+    let mut words = Vec::new();
+    // Word being read, `None` between words, so that `""` makes an empty word.
+    let mut word: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                let escaped = chars.next().ok_or(SplitError::TrailingBackslash)?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(SplitError::UnterminatedQuote('\''))? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(SplitError::UnterminatedQuote('"'))? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(SplitError::UnterminatedQuote('"'))? {
+                            c @ ('"' | '\\') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Subscription answering free-form messages starting with its name:
+/// the message is [split into words](split_words), parsed into `A` and handed to the handler.
+/// Messages failing to parse are answered with the error of [Arguments::parse].
+/// Typed messages are ignored.
+pub struct Command<A, F> {
+    name: String,
+    description: Option<String>,
+    mutating: bool,
+    handler: F,
+    _arguments: PhantomData<fn() -> A>,
+}
+
+impl<A, F, Fut> Command<A, F>
+where
+    A: Arguments,
+    F: Fn(A) -> Fut,
+    Fut: Future<Output = Result<String, SubscriptionError>>,
+{
+    pub fn new(name: &str, handler: F) -> Self {
+        Self {
+            name: name.to_owned(),
+            description: None,
+            mutating: false,
+            handler,
+            _arguments: PhantomData,
+        }
+    }
+
+    /// Listed by the `help` command, see [Subscription::description].
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// Marks the command as changing state, see [Subscription::is_mutating].
+    pub fn mutating(mut self) -> Self {
+        self.mutating = true;
+        self
+    }
+}
+
+#[async_trait]
+impl<A, F, Fut> Subscription for Command<A, F>
+where
+    A: Arguments + Send,
+    F: Fn(A) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, SubscriptionError>> + Send,
+{
+    async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        Ok(None)
+    }
+
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        if message.split_whitespace().next() != Some(self.name.as_str()) {
+            return Ok(None);
+        }
+
+        let words = match split_words(message) {
+            Ok(words) => words,
+            Err(err) => return Ok(Some(err.to_string())),
+        };
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        match A::parse(&words) {
+            Ok(arguments) => (self.handler)(arguments).await.map(Some),
+            Err(usage) => Ok(Some(usage)),
+        }
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn is_mutating(&self) -> bool {
+        self.mutating
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_words, Arguments, Command, SplitError};
+    use crate::Subscription;
+
+    /// `say [--loud] <words>...`
+    struct Say {
+        loud: bool,
+        words: Vec<String>,
+    }
+
+    impl Arguments for Say {
+        fn parse(words: &[&str]) -> Result<Self, String> {
+            let (loud, words) = match words[1..] {
+                ["--loud", ref words @ ..] => (true, words),
+                ref words => (false, words),
+            };
+            if words.is_empty() {
+                return Err("Usage: say [--loud] <words>...".to_owned());
+            }
+            Ok(Self {
+                loud,
+                words: words.iter().map(|word| word.to_string()).collect(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_parse_their_arguments() {
+        let say = Command::new("say", |say: Say| async move {
+            let text = say.words.join(" ");
+            Ok(if say.loud { text.to_uppercase() } else { text })
+        });
+
+        let reply = say.weak_handle("say --loud hello  world").await.unwrap();
+        assert_eq!(reply.as_deref(), Some("HELLO WORLD"));
+        let reply = say.weak_handle("say").await.unwrap();
+        assert_eq!(reply.as_deref(), Some("Usage: say [--loud] <words>..."));
+        assert_eq!(say.weak_handle("sayonara").await.unwrap(), None);
+
+        let reply = say.weak_handle(r#"say "hello  world""#).await.unwrap();
+        assert_eq!(reply.as_deref(), Some("hello  world"));
+        let reply = say.weak_handle("say 'hello").await.unwrap();
+        assert_eq!(reply.as_deref(), Some("Missing closing quote '"));
+    }
+
+    #[test]
+    fn words_are_split_like_a_shell() {
+        assert_eq!(split_words("  peers   list ").unwrap(), ["peers", "list"]);
+        assert_eq!(
+            split_words(r#"say "a \"quoted\" \n" it\'s '\' ''"#).unwrap(),
+            ["say", r#"a "quoted" \n"#, "it's", "\\", ""]
+        );
+        assert_eq!(split_words("a\\ b c").unwrap(), ["a b", "c"]);
+        assert_eq!(
+            split_words("say \"hi"),
+            Err(SplitError::UnterminatedQuote('"'))
+        );
+        assert_eq!(split_words("say hi\\"), Err(SplitError::TrailingBackslash));
+    }
+}
@@ -0,0 +1,126 @@
+//! Compression of large typed frames, negotiated with programmatic clients,
+//! see [Builder::compressor](crate::Builder::compressor).
+
+use crate::codec::Frame;
+use crate::targets::SESSION;
+use bytes::Bytes;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use tracing::warn;
+
+/// First byte of compressed typed frames. A message envelope starts with the tag of its optional id,
+/// and a control frame with its own marker, which are never this.
+const MARKER: u8 = 0xFE;
+
+/// Typed frames shorter than this are sent as they are, compressing them gains little.
+pub(crate) const MIN_COMPRESSED_LEN: usize = 1024;
+
+/// A compression algorithm, e.g. zstd or deflate wrapped from the crate of choice.
+///
+/// Consoles compress typed frames to clients which can decompress with a compressor of the same name,
+/// see [Builder::compressor](crate::Builder::compressor) and [ClientConfig::compressors](crate::ClientConfig::compressors).
+/// Text frames are never compressed, so netcat users are unaffected.
+pub trait Compressor: Send + Sync {
+    /// Name both ends agree on, e.g. `zstd`.
+    fn name(&self) -> &str;
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>>;
+
+    fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+impl Debug for dyn Compressor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Compressor({})", self.name())
+    }
+}
+
+/// Compresses `frame` if it is a typed frame worth it, leaves it as is otherwise.
+pub(crate) fn compress(frame: Frame, compressor: &dyn Compressor) -> Frame {
+    match frame {
+        Frame::Typed(bytes) if bytes.len() >= MIN_COMPRESSED_LEN => {
+            let mut compressed = vec![MARKER];
+            match compressor.compress(&bytes) {
+                Ok(payload) => compressed.extend(payload),
+                Err(err) => {
+                    warn!(target: SESSION, "Failed to compress a frame with {}: {err}", compressor.name());
+                    return Frame::Typed(bytes);
+                }
+            }
+            // Incompressible payloads are sent as they are.
+            Frame::Typed(if compressed.len() < bytes.len() {
+                compressed.into()
+            } else {
+                bytes
+            })
+        }
+        frame => frame,
+    }
+}
+
+/// Restores a frame passed through [compress], `compressor` is `None` if none was negotiated.
+pub(crate) fn decompress(frame: Frame, compressor: Option<&dyn Compressor>) -> io::Result<Frame> {
+    match frame {
+        Frame::Typed(bytes) if bytes.first() == Some(&MARKER) => {
+            let compressor = compressor.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Received a compressed frame without negotiating compression",
+                )
+            })?;
+            Ok(Frame::Typed(Bytes::from(
+                compressor.decompress(&bytes[1..])?,
+            )))
+        }
+        frame => Ok(frame),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{compress, decompress, Compressor, MARKER, MIN_COMPRESSED_LEN};
+    use crate::codec::Frame;
+    use std::io;
+
+    /// Run-length encoding, as `(count, byte)` pairs.
+    pub(crate) struct RunLength;
+
+    impl Compressor for RunLength {
+        fn name(&self) -> &str {
+            "rle"
+        }
+
+        fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+            let mut compressed = Vec::new();
+            for chunk in bytes.chunk_by(|a, b| a == b) {
+                for run in chunk.chunks(u8::MAX as usize) {
+                    compressed.extend([run.len() as u8, run[0]]);
+                }
+            }
+            Ok(compressed)
+        }
+
+        fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(bytes
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn large_typed_frames_are_compressed() -> io::Result<()> {
+        let large = Frame::Typed(vec![7; MIN_COMPRESSED_LEN].into());
+        let compressed = compress(large.clone(), &RunLength);
+        assert!(matches!(&compressed, Frame::Typed(bytes) if bytes[0] == MARKER));
+        assert_eq!(decompress(compressed, Some(&RunLength))?, large);
+
+        let small = Frame::Typed(vec![7; 10].into());
+        assert_eq!(compress(small.clone(), &RunLength), small);
+        let text = Frame::Text(vec![b'a'; MIN_COMPRESSED_LEN].into());
+        assert_eq!(compress(text.clone(), &RunLength), text);
+        let noise = Frame::Typed((0..MIN_COMPRESSED_LEN).map(|i| i as u8).collect());
+        assert_eq!(compress(noise.clone(), &RunLength), noise);
+        Ok(())
+    }
+}
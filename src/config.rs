@@ -0,0 +1,49 @@
+//! Operational knobs of [Console](crate::Console) read from the host's configuration file,
+//! see [Builder::from_config](crate::Builder::from_config).
+
+use crate::ip_net::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Console settings in a serde-friendly shape, e.g. the `[console]` table of a node's TOML file:
+///
+/// ```toml
+/// bind_address = "127.0.0.1:3000"
+/// welcome = "Welcome to {hostname}"
+/// allowed_networks = ["127.0.0.0/8", "10.0.0.0/8"]
+/// max_connections = 8
+/// idle_timeout_secs = 600
+/// rate_limit = { burst = 5, period_secs = 60 }
+/// ```
+///
+/// Every field is optional, those missing keep the [Builder](crate::Builder) defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// Address to listen on, [Builder::try_build](crate::Builder::try_build) fails without it.
+    pub bind_address: Option<SocketAddr>,
+    /// See [Builder::welcome](crate::Builder::welcome).
+    pub welcome: Option<String>,
+    /// See [Builder::prompt](crate::Builder::prompt).
+    pub prompt: Option<String>,
+    /// Networks in CIDR notation peers must belong to, see [Builder::allow_networks](crate::Builder::allow_networks).
+    pub allowed_networks: Option<Vec<IpNet>>,
+    /// See [Builder::max_connections](crate::Builder::max_connections).
+    pub max_connections: Option<usize>,
+    /// See [Builder::max_message_size](crate::Builder::max_message_size).
+    pub max_message_size: Option<usize>,
+    /// See [Builder::rate_limit_per_peer](crate::Builder::rate_limit_per_peer).
+    pub rate_limit: Option<RateLimitConfig>,
+    /// See [Builder::idle_timeout](crate::Builder::idle_timeout).
+    pub idle_timeout_secs: Option<u64>,
+    /// See [Builder::keep_alive](crate::Builder::keep_alive).
+    pub keep_alive_secs: Option<u64>,
+}
+
+/// Connections allowed per peer, see [Builder::rate_limit_per_peer](crate::Builder::rate_limit_per_peer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub burst: u32,
+    pub period_secs: u64,
+}
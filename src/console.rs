@@ -1,74 +1,673 @@
+use crate::auth::{Approver, Policy};
+use crate::client::{Client, ClientConfig};
+use crate::codec::{encode_payload, Frame};
+use crate::compression::Compressor;
+use crate::context::{ConnectHook, DisconnectHook, Extensions};
 use crate::ensure_newline;
-use crate::subscription::BoxedSubscription;
+use crate::event::ConsoleEvent;
+use crate::handle::ConsoleHandle;
+use crate::history::HistoryStore;
+use crate::ip_net::IpNet;
+use crate::metrics::{Metric, MetricsProvider};
+use crate::middleware::Middleware;
+use crate::mux;
+use crate::outbox::SlowConsumer;
+use crate::rate_limit::RateLimiter;
+use crate::reconnect::Backoff;
+use crate::registry::{Registry, SessionInfo};
+use crate::service::ServiceId;
+use crate::session::Session;
+use crate::stats::{ConsoleMetrics, ServiceStats, Stats};
+use crate::subscription::{ConsoleError, Subscription, SubscriptionError, Subscriptions};
+use crate::targets::{ACCEPT, DISPATCH, SESSION};
+use crate::topics::Topics;
 use bytes::Bytes;
-use futures_util::{SinkExt, StreamExt};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::hash::Hash;
-use std::sync::Arc;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::sync::Notify;
-use tokio_util::codec::{BytesCodec, Framed};
-use tracing::{debug, warn};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, info_span, warn, Instrument};
+
+/// Number of [ConsoleEvent]s buffered for slow receivers.
+const EVENTS_CAPACITY: usize = 64;
+
+/// Bytes buffered in each direction of a session opened by [Console::connect_in_memory].
+const IN_MEMORY_BUFFER: usize = 64 << 10;
+
+/// Answer to messages for services not registered yet, see [Builder::starting_up](crate::Builder::starting_up).
+pub(crate) const STARTING_UP: &str = "Console is starting up, retry later";
+
+/// Answer to mutating commands while the console is in [Mode::ReadOnly].
+pub(crate) const MAINTENANCE: &str = "Console is in maintenance mode";
 
 /// A TCP console to process both strongly typed and free form messages.
-/// Free form messages are sent to all known subscriptions in random order until the _first_ success.
+/// Free form messages are sent to all known subscriptions in random order until the _first_ success,
+/// the order can be made reproducible with [Builder::weak_dispatch_seed](crate::Builder::weak_dispatch_seed),
+/// or routed by their first word, see [WeakDispatch].
 ///
-/// This console only allows message from localhost.
+/// Peers can be restricted to localhost or to an allowlist of networks,
+/// see [Builder::allow_networks](crate::Builder::allow_networks).
 pub struct Console<Services, A> {
     inner: Arc<Inner<Services>>,
     bind_address: Option<A>,
-    stop: Arc<Notify>,
+    /// Address the listener is bound to, known once the console is spawned.
+    local_addr: Option<SocketAddr>,
+    stop: CancellationToken,
+    /// Tracks the accepting task and all session tasks.
+    tasks: TaskTracker,
+}
+
+/// State shared by the console and its sessions.
+pub(crate) struct Inner<Services> {
+    /// Registered subscriptions, replaced as a whole when they change at runtime.
+    pub(crate) subscriptions: RwLock<Arc<Subscriptions<Services>>>,
+    pub(crate) policy: Policy<Services>,
+    pub(crate) settings: Settings,
+    /// Number of currently open sessions.
+    pub(crate) sessions: AtomicUsize,
+    /// Identifier of the next session.
+    pub(crate) next_session_id: AtomicU64,
+    /// Number of connections refused by the per-peer rate limit.
+    pub(crate) rate_limited: AtomicU64,
+    pub(crate) events: broadcast::Sender<ConsoleEvent>,
+    /// Push queues of the open sessions by session id, see [Console::broadcast].
+    pub(crate) pushes: Mutex<HashMap<u64, mpsc::Sender<Frame>>>,
+    pub(crate) topics: Topics,
+    /// Open sessions, see [Console::sessions].
+    pub(crate) registry: Registry,
+    /// Calls, errors and latencies per service.
+    pub(crate) stats: Stats,
+    /// When the console was built, for the `{uptime}` welcome placeholder.
+    pub(crate) started: Instant,
+    /// Whether subscriptions are still being registered, see [Builder::starting_up](crate::Builder::starting_up).
+    pub(crate) starting_up: AtomicBool,
+    /// Names of the services disabled with [ConsoleHandle::set_service_enabled].
+    pub(crate) disabled: Mutex<HashSet<String>>,
+    /// Whether the console is in [Mode::ReadOnly], see [ConsoleHandle::set_mode].
+    pub(crate) read_only: AtomicBool,
+}
+
+impl<Services> Inner<Services> {
+    /// Snapshot of the registered subscriptions.
+    pub(crate) fn subscriptions(&self) -> Arc<Subscriptions<Services>> {
+        self.subscriptions
+            .read()
+            .expect("Subscriptions lock is never poisoned")
+            .clone()
+    }
+
+    /// Ends the starting-up phase, see [Builder::starting_up](crate::Builder::starting_up).
+    pub(crate) fn ready(&self) {
+        if self.starting_up.swap(false, Ordering::AcqRel) {
+            debug!(target: DISPATCH, "Console is ready");
+        }
+    }
+
+    /// Current values of the application metrics.
+    pub(crate) fn host_metrics(&self) -> Vec<Metric> {
+        self.settings
+            .metrics_providers
+            .iter()
+            .flat_map(|provider| provider.metrics())
+            .collect()
+    }
+
+    pub(crate) fn is_disabled(&self, service: &str) -> bool {
+        self.disabled
+            .lock()
+            .expect("Disabled services lock is never poisoned")
+            .contains(service)
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        match self.read_only.load(Ordering::Acquire) {
+            true => Mode::ReadOnly,
+            false => Mode::Normal,
+        }
+    }
+
+    pub(crate) fn is_starting_up(&self) -> bool {
+        self.starting_up.load(Ordering::Acquire)
+    }
+
+    /// Queues `frame` to every open session, returns the number of sessions it was queued for.
+    pub(crate) fn broadcast(&self, frame: Frame) -> usize {
+        let pushes = self.pushes.lock().expect("Pushes lock is never poisoned");
+        pushes
+            .iter()
+            .filter(|(session_id, push)| match push.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(err) => {
+                    warn!(target: SESSION, "Failed to broadcast to session {session_id}: {err}");
+                    false
+                }
+            })
+            .count()
+    }
+}
+
+/// How free-form messages find the subscription answering them, see [Builder::weak_dispatch](crate::Builder::weak_dispatch).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WeakDispatch {
+    /// Offered to all subscriptions in random order until the first one answers.
+    #[default]
+    FirstSuccess,
+    /// Offered to subscriptions by descending priority, then in registration order,
+    /// until the first one answers, see [Builder::subscribe_with_priority](crate::Builder::subscribe_with_priority).
+    Ordered,
+    /// Offered only to the subscription whose service name is the first word of the message,
+    /// e.g. `status peers` goes to the service named `status`.
+    Prefix,
+    /// Offered to all subscriptions in the order of [WeakDispatch::Ordered], every answer is sent
+    /// in turn, e.g. for a `dump` command every subscription contributes to.
+    All,
+}
+
+/// What the sender of a message is told when the subscription handling it fails,
+/// see [Builder::error_reporting](crate::Builder::error_reporting).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorReporting {
+    /// Nothing, the failure is only logged. Requests still get a [HANDLER_FAILED](crate::HANDLER_FAILED) error.
+    #[default]
+    Silent,
+    /// Which service failed, without the error, which may reveal internals.
+    Summary,
+    /// Which service failed and the error it returned.
+    Full,
+}
+
+impl ErrorReporting {
+    /// Text telling the sender `service` failed with `err`, if any.
+    pub(crate) fn report(&self, service: &str, err: &SubscriptionError) -> Option<String> {
+        match self {
+            ErrorReporting::Silent => None,
+            ErrorReporting::Summary => {
+                Some(format!("Service {service} failed to handle the message"))
+            }
+            ErrorReporting::Full => Some(format!(
+                "Service {service} failed to handle the message: {err}"
+            )),
+        }
+    }
+}
+
+/// Whether the console accepts mutating commands, see [Console::set_mode].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Every command is handled.
+    #[default]
+    Normal,
+    /// Maintenance mode: status queries are still answered, messages for subscriptions
+    /// which [change state](crate::Subscription::is_mutating) are refused.
+    ReadOnly,
+}
+
+/// Fields recorded on the `session` span of each session and the `dispatch` span of each message,
+/// see [Builder::span_fields](crate::Builder::span_fields). All of them are recorded by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanFields {
+    /// `session_id`, as in [Context::session_id](crate::Context::session_id).
+    pub session_id: bool,
+    /// `peer`, the remote address of the session.
+    pub peer: bool,
+    /// `identity`, as in [Context::identity](crate::Context::identity).
+    pub identity: bool,
+    /// `service`, the name of the service a message is dispatched to.
+    pub service: bool,
+}
+
+impl Default for SpanFields {
+    fn default() -> Self {
+        Self {
+            session_id: true,
+            peer: true,
+            identity: true,
+            service: true,
+        }
+    }
+}
+
+/// Observer of bytes exchanged by sessions, see [Builder::frame_tap](crate::Builder::frame_tap).
+pub(crate) type FrameTap = Arc<dyn Fn(WireDirection, &Bytes) + Send + Sync>;
+
+/// Direction of bytes shown to a [Builder::frame_tap](crate::Builder::frame_tap), as seen by the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireDirection {
+    /// Bytes read from the peer.
+    Inbound,
+    /// Bytes written to the peer.
+    Outbound,
 }
 
-struct Inner<Services> {
-    subscriptions: HashMap<Services, BoxedSubscription>,
-    welcome: String,
-    accept_only_localhost: bool,
+/// Produces the welcome message of each session, see [Builder::welcome_with](crate::Builder::welcome_with).
+pub(crate) type WelcomeHook = Box<dyn Fn() -> String + Send + Sync>;
+
+/// Console knobs configured via [Builder](crate::Builder).
+#[derive(Default)]
+pub(crate) struct Settings {
+    /// Welcome message, possibly with placeholders expanded when a session opens.
+    pub(crate) welcome: String,
+    /// Replaces [Settings::welcome] when set.
+    pub(crate) welcome_with: Option<WelcomeHook>,
+    /// Whether sessions open without any welcome message.
+    pub(crate) no_welcome: bool,
+    /// Whether telnet negotiations are stripped from text and answered.
+    pub(crate) telnet: bool,
+    /// Sent to interactive sessions after the welcome and every free-form response.
+    pub(crate) prompt: Option<String>,
+    /// Answer to free-form messages no subscription answers.
+    pub(crate) unknown_command: Option<String>,
+    /// Networks peers must belong to, any peer is accepted if `None`.
+    pub(crate) allowed_networks: Option<Vec<IpNet>>,
+    pub(crate) weak_dispatch: WeakDispatch,
+    pub(crate) weak_dispatch_seed: Option<u64>,
+    /// What senders are told about failing subscriptions.
+    pub(crate) error_reporting: ErrorReporting,
+    pub(crate) quiet_messages: bool,
+    /// Fields of the tracing spans of sessions and dispatched messages.
+    pub(crate) span_fields: SpanFields,
+    pub(crate) max_connections: Option<usize>,
+    /// Typed requests handled concurrently per session, one at a time if `None`.
+    pub(crate) concurrent_requests: Option<usize>,
+    /// Frames queued per session for writing and what to do when the queue is full.
+    pub(crate) write_queue: Option<(usize, SlowConsumer)>,
+    /// Whether the console starts before all subscriptions are registered.
+    pub(crate) starting_up: bool,
+    /// Longest typed payload or text line accepted from sessions.
+    pub(crate) max_message_size: Option<usize>,
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Interval of blank lines sent to quiet interactive sessions.
+    pub(crate) keep_alive: Option<Duration>,
+    /// Interval of heartbeats sent to programmatic clients and how many may go unanswered.
+    pub(crate) heartbeat: Option<(Duration, u32)>,
+    /// Compressors offered to programmatic clients, by preference.
+    pub(crate) compressors: Vec<Arc<dyn Compressor>>,
+    /// Largest typed payload sent in one frame, larger ones are chunked.
+    pub(crate) chunk_size: Option<usize>,
+    /// Host dependencies exposed to handlers through their [Context](crate::Context).
+    pub(crate) extensions: Arc<Extensions>,
+    /// Shared secret sessions must present before dispatching commands.
+    pub(crate) token: Option<String>,
+    /// Grants elevated access requested with the `elevate <reason>` command.
+    pub(crate) approver: Option<Approver>,
+    /// Commands executed per identity, queried with the `audit <identity>` command.
+    pub(crate) history: Option<Arc<dyn HistoryStore>>,
+    /// Time each subscription has to answer the `selftest` command, which is disabled if `None`.
+    pub(crate) selftest: Option<Duration>,
+    /// Whether the `help` command is enabled.
+    pub(crate) help: bool,
+    /// Whether sessions may turn ANSI colors on and off with the `color on|off` command.
+    pub(crate) color_command: bool,
+    /// Whether the `stats [service]` command is enabled.
+    pub(crate) stats_command: bool,
+    /// Whether operators may list open sessions with the `sessions` command.
+    pub(crate) sessions_command: bool,
+    /// Whether the `runtime` command is enabled.
+    pub(crate) runtime_command: bool,
+    /// Whether sessions may switch to running a piped runbook with the `script` command.
+    pub(crate) script_command: bool,
+    /// How long subscriptions may take to handle a message, see [Builder::handler_timeout](crate::Builder::handler_timeout).
+    pub(crate) handler_timeout: Option<Duration>,
+    /// Hooks around the dispatch of every message, outermost first.
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
+    /// Application metrics reported next to the console's own.
+    pub(crate) metrics_providers: Vec<Arc<dyn MetricsProvider>>,
+    /// Whether sessions may choose the time zone of timestamps with the `tz [zone]` command.
+    pub(crate) time_zones: bool,
+    /// Whether sessions may subscribe to topics, see [Builder::topics](crate::Builder::topics).
+    pub(crate) topics: bool,
+    /// Whether sessions may ask for text replies as deltas, see [Builder::delta_updates](crate::Builder::delta_updates).
+    pub(crate) delta_updates: bool,
+    /// Called when a session opens, see [Builder::on_connect](crate::Builder::on_connect).
+    pub(crate) on_connect: Option<ConnectHook>,
+    /// Called when a session closes, see [Builder::on_disconnect](crate::Builder::on_disconnect).
+    pub(crate) on_disconnect: Option<DisconnectHook>,
+    /// Called for every frame received or sent by sessions.
+    pub(crate) frame_tap: Option<FrameTap>,
+    /// Connections allowed per peer in a burst and the period to regain them.
+    pub(crate) rate_limit: Option<(u32, Duration)>,
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fault_injector: Option<crate::FaultInjector>,
 }
 
 impl<Services, A> Console<Services, A> {
     pub(crate) fn new(
-        subscriptions: HashMap<Services, BoxedSubscription>,
+        subscriptions: Subscriptions<Services>,
+        policy: Policy<Services>,
         bind_address: A,
-        welcome: String,
-        accept_only_localhost: bool,
+        settings: Settings,
     ) -> Self {
         Self {
             inner: Arc::new(Inner {
-                subscriptions,
-                welcome,
-                accept_only_localhost,
+                subscriptions: RwLock::new(Arc::new(subscriptions)),
+                policy,
+                sessions: AtomicUsize::new(0),
+                next_session_id: AtomicU64::new(0),
+                rate_limited: AtomicU64::new(0),
+                events: broadcast::channel(EVENTS_CAPACITY).0,
+                pushes: Mutex::new(HashMap::new()),
+                topics: Topics::default(),
+                registry: Registry::default(),
+                stats: Stats::default(),
+                started: Instant::now(),
+                starting_up: AtomicBool::new(settings.starting_up),
+                disabled: Mutex::default(),
+                read_only: AtomicBool::new(false),
+                settings,
             }),
             bind_address: Some(bind_address),
-            stop: Arc::new(Notify::new()),
+            local_addr: None,
+            stop: CancellationToken::new(),
+            tasks: TaskTracker::new(),
         }
     }
+
+    /// Address the console listens on, `None` until it is spawned.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Serves sessions over outbound connections to `gateway`, for hosts unreachable from outside,
+    /// e.g., behind NAT. The console connects to the gateway, serves one session over the connection
+    /// and connects again once it closes, waiting according to `backoff` after failed attempts.
+    ///
+    /// Dialing stops when the console stops or `backoff` gives up.
+    /// It works alongside [Console::spawn], or alone for consoles built with
+    /// [Builder::build_outbound](crate::Builder::build_outbound).
+    pub fn dial<G>(&self, gateway: G, backoff: Backoff)
+    where
+        Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+        G: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        self.dial_with(gateway, backoff, false);
+    }
+
+    /// Same as [Console::dial], but every connection to the gateway carries any number of
+    /// concurrent sessions, opened by the gateway with a [Multiplexer](crate::Multiplexer).
+    pub fn dial_multiplexed<G>(&self, gateway: G, backoff: Backoff)
+    where
+        Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+        G: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        self.dial_with(gateway, backoff, true);
+    }
+
+    /// Opens a session over an in-memory pipe and returns a [Client](crate::Client) connected to it,
+    /// e.g. to test subscriptions without binding ports. The session behaves like any other,
+    /// with a loopback peer address, whether the console is spawned or not.
+    pub async fn connect_in_memory(&self) -> anyhow::Result<Client>
+    where
+        Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+    {
+        let Some(guard) = SessionGuard::acquire(&self.inner) else {
+            return Err(Error::SessionLimit.into());
+        };
+
+        let (client, server) = tokio::io::duplex(IN_MEMORY_BUFFER);
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let stop = self.stop.clone();
+        self.tasks.spawn(async move {
+            Session::run(server, peer, guard.inner.clone(), stop).await;
+            drop(guard);
+        });
+
+        Client::over(Box::new(client), ClientConfig::default()).await
+    }
+
+    fn dial_with<G>(&self, gateway: G, backoff: Backoff, multiplexed: bool)
+    where
+        Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+        G: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        let inner = self.inner.clone();
+        let stop = self.stop.clone();
+        let tasks = self.tasks.clone();
+
+        self.tasks.spawn(async move {
+            let mut failed = 0;
+            loop {
+                let connect = tokio::select! {
+                    _ = stop.cancelled() => return,
+                    connect = TcpStream::connect(gateway.clone()) => connect,
+                };
+
+                let (stream, peer) = match connect.and_then(|stream| {
+                    let peer = stream.peer_addr()?;
+                    Ok((stream, peer))
+                }) {
+                    Ok(connected) => connected,
+                    Err(err) => {
+                        failed += 1;
+                        if backoff.gives_up(failed) {
+                            warn!(target: ACCEPT, "Giving up connecting to the gateway after {failed} attempts: {err}");
+                            return;
+                        }
+
+                        let delay = backoff.delay(failed);
+                        warn!(target: ACCEPT, "Failed to connect to the gateway: {err}. Retrying in {delay:?}");
+                        tokio::select! {
+                            _ = stop.cancelled() => return,
+                            _ = time::sleep(delay) => continue,
+                        }
+                    }
+                };
+                failed = 0;
+                debug!(target: ACCEPT, "Connected to gateway {peer}");
+
+                if !multiplexed {
+                    let Some(guard) = SessionGuard::acquire(&inner) else {
+                        warn!(target: ACCEPT, "Maximum number of sessions is reached. Waiting before dialing the gateway again.");
+                        drop(stream);
+                        tokio::select! {
+                            _ = stop.cancelled() => return,
+                            _ = time::sleep(backoff.delay(1)) => continue,
+                        }
+                    };
+
+                    let span = info_span!(target: SESSION, "gateway", address = %peer);
+                    Session::run(stream, peer, guard.inner.clone(), stop.clone())
+                        .instrument(span)
+                        .await;
+                    drop(guard);
+                    continue;
+                }
+
+                let mut channels = mux::accept(stream);
+                loop {
+                    let channel = tokio::select! {
+                        _ = stop.cancelled() => break,
+                        channel = channels.recv() => match channel {
+                            Some(channel) => channel,
+                            None => break,
+                        },
+                    };
+
+                    let Some(guard) = SessionGuard::acquire(&inner) else {
+                        warn!(target: ACCEPT, "Maximum number of sessions is reached. Refusing a channel from {peer}.");
+                        continue;
+                    };
+
+                    let stop = stop.clone();
+                    let span = info_span!(target: SESSION, "gateway", address = %peer);
+                    let session = async move {
+                        Session::run(channel, peer, guard.inner.clone(), stop).await;
+                        drop(guard);
+                    };
+                    tasks.spawn(session.instrument(span));
+                }
+            }
+        });
+    }
+
+    /// Stop the console and break all the current connections.
+    ///
+    /// Sessions subscribed to [LIFECYCLE_TOPIC](crate::LIFECYCLE_TOPIC) are sent `stopping` first.
+    pub fn stop(&self) {
+        self.handle().stop_with("stopping");
+    }
+
+    /// Stops the console and waits up to `deadline` for all sessions to close.
+    ///
+    /// Sessions finish handling the message they are processing, if any, before closing.
+    /// Sessions subscribed to [LIFECYCLE_TOPIC](crate::LIFECYCLE_TOPIC) are sent `draining` first.
+    /// Fails with [Error::ShutdownTimeout] if some sessions are still open after the deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> Result<(), Error> {
+        self.handle().stop_with("draining");
+
+        time::timeout(deadline, self.tasks.wait())
+            .await
+            .map_err(|_| Error::ShutdownTimeout(self.session_count()))
+    }
+
+    /// Registers a subscription while the console is running, same as [ConsoleHandle::register].
+    pub fn subscribe<T>(&self, service_id: Services, subscription: T) -> Result<(), Error>
+    where
+        Services: ServiceId,
+        T: Subscription + Send + Sync + 'static,
+    {
+        self.handle().register(service_id, subscription)
+    }
+
+    /// Ends the starting-up phase once all subscriptions are registered, see [Builder::starting_up](crate::Builder::starting_up).
+    pub fn ready(&self) {
+        self.inner.ready();
+    }
+
+    /// A handle to register and unregister subscriptions while the console is running.
+    pub fn handle(&self) -> ConsoleHandle<Services> {
+        ConsoleHandle::new(self.inner.clone(), self.stop.clone(), self.tasks.clone())
+    }
+
+    /// Number of currently open sessions.
+    pub fn session_count(&self) -> usize {
+        self.inner.sessions.load(Ordering::Relaxed)
+    }
+
+    /// Sessions currently open, oldest first, e.g. to find one to [Console::kick].
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.inner.registry.list()
+    }
+
+    /// Closes the session `session_id`, see [SessionInfo::session_id], returns whether it was open.
+    ///
+    /// Like when the console stops, requests in progress are still answered,
+    /// then the session is told it is closed.
+    pub fn kick(&self, session_id: u64) -> bool {
+        self.inner.registry.kick(session_id)
+    }
+
+    /// Number of connections refused so far by the per-peer rate limit.
+    pub fn rate_limited_count(&self) -> u64 {
+        self.inner.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// Sends `text` to all open sessions, e.g. an alert when the node degrades,
+    /// returns the number of sessions it was queued for.
+    ///
+    /// The text is queued like texts pushed by handlers, see [Context::push_handle](crate::Context::push_handle),
+    /// so it never interleaves with a reply. Sessions with a full queue miss it.
+    pub fn broadcast(&self, text: &str) -> usize {
+        self.inner.broadcast(text_frame(text))
+    }
+
+    /// Sends a typed `message` to all open sessions, returns the number of sessions it was queued for.
+    /// [Client](crate::Client) receives it with [Client::recv_typed](crate::Client::recv_typed).
+    pub fn broadcast_typed<M: Serialize>(&self, message: &M) -> Result<usize, Error> {
+        Ok(self.inner.broadcast(typed_frame(message)?))
+    }
+
+    /// Sends `text` to all sessions subscribed to `topic`, see [Builder::topics](crate::Builder::topics),
+    /// returns the number of sessions it was queued for. Sessions with a full queue miss it.
+    pub fn publish(&self, topic: &str, text: &str) -> usize {
+        self.inner.topics.publish(topic, text_frame(text))
+    }
+
+    /// Sends a typed `message` to all sessions subscribed to `topic`,
+    /// returns the number of sessions it was queued for.
+    /// [Client](crate::Client) receives it with [Client::recv_typed](crate::Client::recv_typed).
+    pub fn publish_typed<M: Serialize>(&self, topic: &str, message: &M) -> Result<usize, Error> {
+        Ok(self.inner.topics.publish(topic, typed_frame(message)?))
+    }
+
+    /// Connections, sessions, messages and errors since the console started,
+    /// e.g. to export them along the host application's metrics.
+    pub fn metrics(&self) -> ConsoleMetrics {
+        self.handle().metrics()
+    }
+
+    /// Same as [ConsoleHandle::set_service_enabled].
+    pub fn set_service_enabled(&self, service_id: &Services, enabled: bool)
+    where
+        Services: ServiceId,
+    {
+        self.handle().set_service_enabled(service_id, enabled);
+    }
+
+    /// Same as [ConsoleHandle::set_mode].
+    pub fn set_mode(&self, mode: Mode)
+    where
+        Services: ServiceId,
+    {
+        self.handle().set_mode(mode);
+    }
+
+    /// Same as [ConsoleHandle::mode].
+    pub fn mode(&self) -> Mode {
+        self.inner.mode()
+    }
+
+    /// Calls, errors and latency histograms of every service called at least once, ordered by name.
+    pub fn service_stats(&self) -> Vec<ServiceStats> {
+        self.inner.stats.snapshot()
+    }
+
+    /// Subscribes to [ConsoleEvent]s, such as session summaries.
+    /// Events are only delivered to receivers subscribed before they happen;
+    /// a receiver lagging behind by more than 64 events misses the oldest ones.
+    pub fn events(&self) -> broadcast::Receiver<ConsoleEvent> {
+        self.inner.events.subscribe()
+    }
 }
+
 impl<Services, A> Console<Services, A>
 where
-    Services: DeserializeOwned + Eq + Hash + Debug + Send + Sync + 'static,
+    Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
     A: ToSocketAddrs + 'static,
 {
     /// Spawn the console by opening a TCP socket at the specified address.
-    pub async fn spawn(&mut self) -> Result<(), Error> {
+    ///
+    /// Returns the address the console actually listens on,
+    /// which tells the chosen port when binding to port 0.
+    pub async fn spawn(&mut self) -> Result<SocketAddr, Error> {
         let Some(bind_address) = self.bind_address.take() else {
-            warn!("Console has already started");
+            warn!(target: ACCEPT, "Console has already started");
             return Err(Error::AlreadyStarted);
         };
 
         let listener = TcpListener::bind(bind_address).await?;
+        let local_addr = listener.local_addr()?;
+        self.local_addr = Some(local_addr);
         let inner = self.inner.clone();
         let stop = self.stop.clone();
+        let tasks = self.tasks.clone();
+        let mut rate_limiter = inner
+            .settings
+            .rate_limit
+            .map(|(burst, period)| RateLimiter::new(burst, period));
 
-        tokio::spawn(async move {
-            debug!(
-                "Listening on {:?}",
-                listener.local_addr().expect("Local address must be known")
-            );
+        self.tasks.spawn(async move {
+            debug!(target: ACCEPT, "Listening on {local_addr}");
 
             loop {
                 // Keep accepting console sessions,
@@ -76,8 +675,8 @@ where
                 // if so, spawn a task to handle the session.
 
                 let stream = tokio::select! {
-                    _ = stop.notified() => {
-                        debug!("Stopping console");
+                    _ = stop.cancelled() => {
+                        debug!(target: ACCEPT, "Stopping console");
                         return;
                     }
                     Ok((stream, _)) = listener.accept() => {
@@ -85,154 +684,2996 @@ where
                     }
                 };
 
-                debug!("New console connection.");
+                debug!(target: ACCEPT, "New console connection.");
 
                 let Ok(addr) = stream.peer_addr() else {
-                    warn!("Could not get peer address. Closing the connection.");
+                    warn!(target: ACCEPT, "Could not get peer address. Closing the connection.");
                     continue;
                 };
-                if inner.accept_only_localhost && !addr.ip().is_loopback() {
-                    warn!("Only connection from the localhost are allowed. Connected peer address {addr}. Closing the connection.");
-                    continue;
+                if let Some(networks) = &inner.settings.allowed_networks {
+                    if !networks.iter().any(|network| network.contains(addr.ip())) {
+                        warn!(target: ACCEPT, "Only connections from allowed networks are accepted. Connected peer address {addr}. Closing the connection.");
+                        inner.stats.record_rejected();
+                        continue;
+                    }
+                }
+
+                if let Some(rate_limiter) = &mut rate_limiter {
+                    if !rate_limiter.allow(addr.ip()) {
+                        inner.rate_limited.fetch_add(1, Ordering::Relaxed);
+                        inner.stats.record_rejected();
+                        warn!(target: ACCEPT, "Peer {addr} opens connections too often. Closing the connection.");
+                        continue;
+                    }
                 }
 
-                tokio::spawn(Self::handle_console_session(
-                    stream,
-                    inner.clone(),
-                    stop.clone(),
-                ));
+                let Some(guard) = SessionGuard::acquire(&inner) else {
+                    warn!(
+                        target: ACCEPT,
+                        "Maximum number of sessions is reached. Refusing connection from {addr}."
+                    );
+                    tokio::spawn(async move {
+                        let mut stream = stream;
+                        let _ = stream
+                            .write_all(b"Console is busy, try again later\n")
+                            .await;
+                    });
+                    continue;
+                };
+
+                let stop = stop.clone();
+                tasks.spawn(async move {
+                    Session::run(stream, addr, guard.inner.clone(), stop).await;
+                    drop(guard);
+                });
             }
         });
 
-        Ok(())
+        Ok(local_addr)
     }
+}
 
-    /// Stop the console and break all the current connections.
-    pub fn stop(&self) {
-        self.stop.notify_waiters();
-    }
-
-    /// Internal function handling a remote console session.
-    async fn handle_console_session(
-        stream: TcpStream,
-        inner: Arc<Inner<Services>>,
-        stop: Arc<Notify>,
-    ) {
-        let Ok(addr) = stream.peer_addr() else {
-            warn!("Could not get peer address. Closing the session.");
-            return;
-        };
-
-        debug!("Connected to {addr}");
-
-        let mut bytes_stream = Framed::new(stream, BytesCodec::new());
-
-        debug!("Welcoming {addr}");
-        let bytes: Bytes = inner.welcome.as_bytes().to_vec().into();
-        let _ = bytes_stream.send(bytes).await;
-        debug!("Finished welcoming {addr}");
-
-        loop {
-            let bytes = tokio::select! {
-                _ = stop.notified() => {
-                    debug!("Stopping session for {addr}");
-                    return;
-                }
-                result = bytes_stream.next() => match result {
-                    Some(Ok(bytes)) => {
-                        bytes.freeze()
-                    }
-                    Some(Err(err)) => {
-                        warn!("Error while receiving bytes: {err}. Received bytes will not be processed");
-                        continue;
-                    }
-                    None => {
-                        // Connection closed.
-                        debug!("Connection closed by {addr}");
-                        return;
-                    }
-                }
-            };
-
-            match bcs::from_bytes::<Message<Services>>(bytes.as_ref()) {
-                Ok(Message { service_id, bytes }) => {
-                    // Message is strongly typed.
+/// Accounts for an open session for as long as it is alive.
+struct SessionGuard<Services> {
+    inner: Arc<Inner<Services>>,
+}
 
-                    debug!("Received message for {service_id:?}");
+impl<Services> SessionGuard<Services> {
+    /// Registers a new session unless the maximum number of sessions is reached.
+    fn acquire(inner: &Arc<Inner<Services>>) -> Option<Self> {
+        let max = inner.settings.max_connections.unwrap_or(usize::MAX);
+        inner
+            .sessions
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |sessions| {
+                (sessions < max).then_some(sessions + 1)
+            })
+            .map_err(|_| inner.stats.record_rejected())
+            .ok()?;
+        inner.stats.record_accepted();
 
-                    if let Some(subscription) = inner.subscriptions.get(&service_id) {
-                        debug!("Found subscription for service {service_id:?}");
+        Some(Self {
+            inner: inner.clone(),
+        })
+    }
+}
 
-                        match subscription.handle(bytes).await {
-                            Ok(None) => {}
-                            Ok(Some(bytes)) => {
-                                let _ = bytes_stream.send(bytes).await;
-                            }
-                            Err(err) => warn!("Error handling message: {err}"),
-                        }
-                    } else {
-                        warn!("No subscription found for service {service_id:?}. Ignoring the message.");
-                    }
-                }
-                Err(_err) => {
-                    // Message is not strongly typed and probably came from netcat or a similar client.
-                    // Try all subscriptions to make sense of it until the FIRST success.
-
-                    let text = String::from_utf8_lossy(bytes.as_ref()).trim().to_string();
-                    debug!("Received message is not typed. Treating it as text: {text}");
-
-                    for (service_id, subscription) in &inner.subscriptions {
-                        debug!("[{service_id:?}] request to process text message: `{text}`");
-
-                        match subscription.weak_handle(&text).await {
-                            Ok(None) => {
-                                continue;
-                            }
-                            Ok(Some(message)) => {
-                                debug!("[{service_id:?}] Message processed");
-                                let vec: Bytes = ensure_newline(message).as_bytes().to_vec().into();
-                                let _ = bytes_stream.send(vec).await;
-                                break;
-                            }
-                            Err(err) => {
-                                warn!("Service {service_id:?} failed to handle message: {err}");
-                                continue;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+impl<Services> Drop for SessionGuard<Services> {
+    fn drop(&mut self) {
+        self.inner.sessions.fetch_sub(1, Ordering::AcqRel);
     }
 }
 
 /// A wrapper struct to pass strongly-typed messages on [Console].
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Message<Services> {
-    service_id: Services,
-    bytes: Bytes,
+    /// Optional correlation id echoed back by [Console] in the [Reply].
+    pub(crate) id: Option<u64>,
+    pub(crate) service_id: Services,
+    pub(crate) bytes: Bytes,
+    /// Version of the service API the message is meant for, see [Builder::subscribe_version](crate::Builder::subscribe_version).
+    pub(crate) version: Option<u32>,
 }
 
 impl<Services> Message<Services> {
     /// Creates a new [Message] with any serializable payload.
-    pub(crate) fn new(service_id: Services, message: &impl Serialize) -> Result<Self, Error> {
+    pub(crate) fn new(
+        id: Option<u64>,
+        service_id: Services,
+        message: &impl Serialize,
+    ) -> Result<Self, Error> {
         Ok(Self {
+            id,
             service_id,
-            bytes: Bytes::from(bcs::to_bytes(message)?),
+            bytes: encode_payload(message)?,
+            version: None,
         })
     }
+
+    pub(crate) fn with_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
+}
+
+/// A wrapper struct to pass responses to strongly-typed messages back from [Console].
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Reply {
+    /// Correlation id of the [Message] this reply answers.
+    pub(crate) id: Option<u64>,
+    /// Response produced by the subscription, if any.
+    pub(crate) bytes: Option<Bytes>,
+    /// Why the message was not handled, for requests the client can act upon.
+    pub(crate) error: Option<ReplyError>,
+}
+
+/// Reason a [Message] carrying a correlation id was not handled, reported in its [Reply].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ReplyError {
+    /// No subscription is registered for the service of the given name.
+    UnknownService(String),
+    /// The console refused to dispatch the message, for the given reason: a
+    /// [Middleware](crate::Middleware) or an access rule denied it, the service is disabled or
+    /// under maintenance, the console is still starting up, or the message version is unsupported.
+    Refused(String),
+    /// The subscription failed with a [ConsoleError](crate::ConsoleError).
+    Failed(ConsoleError),
+    /// The subscription was still handling the message after the given timeout,
+    /// see [Builder::handler_timeout](crate::Builder::handler_timeout).
+    TimedOut(Duration),
+    /// The subscription panicked while handling the message, see [HandlerPanic](crate::HandlerPanic).
+    Panicked,
+}
+
+/// Frame carrying `text` as a line.
+pub(crate) fn text_frame(text: &str) -> Frame {
+    Frame::Text(ensure_newline(text.to_owned()).into_bytes().into())
+}
+
+/// Frame carrying a typed `message` which does not answer any request.
+pub(crate) fn typed_frame<M: Serialize>(message: &M) -> Result<Frame, Error> {
+    let reply = Reply {
+        id: None,
+        bytes: Some(encode_payload(message)?),
+        error: None,
+    };
+    Ok(Frame::Typed(bcs::to_bytes(&reply)?.into()))
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Subscription cannot be registered: service id `{0}` is already in use")]
     ServiceIdUsed(String),
+    #[error("Subscription cannot be replaced: service id `{0}` is not registered")]
+    ServiceNotRegistered(String),
+    #[error("Subscriptions failed validation: {}", .0.join("; "))]
+    InvalidSubscriptions(Vec<String>),
     #[error("Console bind address is not specified")]
     NoBindAddress,
     #[error("Console had already started")]
     AlreadyStarted,
+    #[error("Maximum number of sessions is reached")]
+    SessionLimit,
+    #[error("Console did not shut down in time: {0} sessions are still open")]
+    ShutdownTimeout(usize),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Serde error: {0}")]
     Serde(#[from] bcs::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Frame, FrameCodec, MACHINE_HELLO};
+    use crate::compression::tests::RunLength;
+    use crate::control::{Control, PROTOCOL_VERSIONS};
+    use crate::service::TestService;
+    use crate::{
+        Backoff, ClientError, ConsoleEvent, Context, Extensions, Multiplexer, SharedClient,
+        Subscription, SubscriptionError, WireDirection,
+    };
+    use async_trait::async_trait;
+    use bytes::{Bytes, BytesMut};
+    use futures_util::stream::{self, StreamExt};
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time;
+    use tokio_util::codec::Encoder;
+
+    #[tokio::test]
+    async fn seeded_weak_dispatch_is_reproducible() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("first"))?
+            .subscribe(TestService::Two, Name("second"))?
+            .subscribe(TestService::Three, Name("third"))?
+            .weak_dispatch_seed(42)
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut winners = Vec::new();
+        for _ in 0..2 {
+            let mut client = crate::Client::new(address, None).await?;
+            let mut session = Vec::new();
+            for _ in 0..16 {
+                client.weak_send("who").await?;
+                session.push(client.weak_read().await?);
+            }
+            winners.push(session);
+        }
+
+        assert_eq!(winners[0], winners[1]);
+        // Orders are reshuffled per message, so the same subscription does not always win.
+        assert!(winners[0].iter().any(|winner| winner != &winners[0][0]));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ordered_dispatch_follows_priorities() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("first"))?
+            .subscribe_with_priority(TestService::Two, -1, Name("second"))?
+            .subscribe_with_priority(TestService::Three, 1, Name("third"))?
+            .weak_dispatch(crate::WeakDispatch::Ordered)
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        for _ in 0..8 {
+            client.weak_send("who").await?;
+            assert_eq!(client.weak_read().await?, "third");
+        }
+
+        // Runtime registrations take priority 0, after those registered before.
+        let handle = console.handle();
+        handle.unregister(&TestService::Three);
+        handle.register(TestService::Three, Name("late"))?;
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("who").await?;
+        assert_eq!(client.weak_read().await?, "first");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefix_dispatch_routes_by_first_word() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("first"))?
+            .subscribe(TestService::Two, Name("second"))?
+            .weak_dispatch(crate::WeakDispatch::Prefix)
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        for _ in 0..8 {
+            client.weak_send("two please").await?;
+            assert_eq!(client.weak_read().await?, "second");
+        }
+
+        // Messages not starting with a service name are not offered to anyone.
+        client.weak_send("three").await?;
+        client.weak_send("one").await?;
+        assert_eq!(client.weak_read().await?, "first");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_are_limited() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .max_connections(1)
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut first = crate::Client::new(address, None).await?;
+        assert_eq!(console.session_count(), 1);
+
+        // The busy notice takes the place of the welcome message, then the connection is closed.
+        assert!(crate::Client::new(address, None).await.is_err());
+        assert_eq!(console.session_count(), 1);
+
+        first.send_recv(TestService::One, &()).await?;
+        let metrics = console.metrics();
+        assert_eq!(metrics.accepted_connections, 1);
+        assert_eq!(metrics.rejected_connections, 1);
+        assert_eq!(metrics.active_sessions, 1);
+        assert_eq!(metrics.messages, 1);
+        assert_eq!(metrics.handler_errors, 0);
+        assert_eq!(metrics.services[0].calls, 1);
+
+        drop(first);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(console.session_count(), 0);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_are_closed() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .idle_timeout(Duration::from_millis(100))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+
+        // Activity keeps the session open.
+        for _ in 0..3 {
+            time::sleep(Duration::from_millis(60)).await;
+            client.weak_send("ping").await?;
+            assert_eq!(client.weak_read().await?, "only");
+        }
+
+        assert_eq!(client.weak_read().await?, "Closing idle session");
+        assert!(client.weak_read().await.is_err());
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn interactive_sessions_are_prompted() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .prompt("node> ")
+            .subscribe(TestService::One, Name("only"))?
+            .build()?;
+        let address = console.spawn().await?;
+
+        async fn expect(stream: &mut TcpStream, expected: &str) -> anyhow::Result<()> {
+            let mut received = vec![0; expected.len()];
+            tokio::io::AsyncReadExt::read_exact(stream, &mut received).await?;
+            assert_eq!(String::from_utf8(received)?, expected);
+            Ok(())
+        }
+
+        let mut stream = TcpStream::connect(address).await?;
+        expect(&mut stream, "Welcome\nnode> ").await?;
+        stream.write_all(b"ping\n").await?;
+        expect(&mut stream, "only\nnode> ").await?;
+
+        // Programmatic clients never see the prompt.
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn telnet_negotiation_is_answered() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .telnet()
+            .subscribe(TestService::One, Name("only"))?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut stream = TcpStream::connect(address).await?;
+        // DO ECHO is refused, the client keeps echoing locally.
+        stream.write_all(b"\xFF\xFD\x01").await?;
+        let mut received = [0; 11];
+        tokio::io::AsyncReadExt::read_exact(&mut stream, &mut received).await?;
+        assert_eq!(&received, b"Welcome\n\xFF\xFC\x01");
+
+        // A negotiation within a line does not reach the subscription.
+        stream.write_all(b"pi\xFF\xF1ng\r\n").await?;
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("only"));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn colors_are_stripped_on_request() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .color_command()
+            .subscribe(TestService::One, Colorful)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(b"status\ncolor off\nstatus\n").await?;
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Welcome"));
+        assert_eq!(
+            lines.next_line().await?.as_deref(),
+            Some("db: \x1b[31mDOWN\x1b[0m")
+        );
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Colors disabled"));
+        assert_eq!(lines.next_line().await?.as_deref(), Some("db: DOWN"));
+
+        // Programmatic clients never see escapes.
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("status").await?;
+        assert_eq!(client.weak_read().await?, "db: DOWN");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quiet_sessions_get_keep_alives() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, Name("only"))?
+            .keep_alive(Duration::from_millis(30))
+            .idle_timeout(Duration::from_millis(200))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut lines = BufReader::new(TcpStream::connect(address).await?).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Welcome"));
+
+        // Keep-alives flow until the session is closed for being idle regardless.
+        let mut keep_alives = 0;
+        while let Some(line) = lines.next_line().await? {
+            match line.as_str() {
+                "" => keep_alives += 1,
+                "Closing idle session" => break,
+                line => panic!("Unexpected line {line:?}"),
+            }
+        }
+        assert!(keep_alives >= 3, "Only {keep_alives} keep-alives");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn silent_clients_miss_heartbeats() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .heartbeat(Duration::from_millis(20), 2)
+            .build()?;
+
+        // A client receiving frames answers the pings and stays connected.
+        let mut client = console.connect_in_memory().await?;
+        for _ in 0..10 {
+            assert!(client.ping().await? < Duration::from_secs(1));
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+
+        // A client going quiet is dropped after two unanswered pings.
+        let mut client = console.connect_in_memory().await?;
+        time::sleep(Duration::from_millis(200)).await;
+        assert!(client.send_recv(TestService::One, &()).await.is_err());
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_handlers() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Slow(Duration::from_millis(200)))?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("work").await?;
+        time::sleep(Duration::from_millis(50)).await;
+
+        console.shutdown(Duration::from_secs(1)).await?;
+        assert_eq!(console.session_count(), 0);
+
+        // The in-flight command completed and was answered before the session closed.
+        assert_eq!(client.weak_read().await?, "done");
+        assert!(client.weak_read().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_summary_is_emitted_on_close() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .welcome("hi")
+            .build()?;
+        let address = console.spawn().await?;
+        let mut events = console.events();
+
+        // The client skips the welcome message, the handshake is not accounted.
+        let mut client = crate::Client::new(address, None).await?;
+        for _ in 0..2 {
+            client.weak_send("ping").await?;
+            assert_eq!(client.weak_read().await?, "only");
+        }
+        drop(client);
+
+        let ConsoleEvent::SessionClosed(summary) =
+            time::timeout(Duration::from_secs(1), events.recv()).await??;
+        assert_eq!(summary.peer.ip(), address.ip());
+        assert_eq!(summary.commands, 2);
+        assert_eq!(summary.bytes_in, "ping\n".len() as u64 * 2);
+        assert_eq!(summary.bytes_out, "only\n".len() as u64 * 2);
+        assert_eq!(summary.errors, 0);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spawn_reports_ephemeral_port() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .build()?;
+        assert_eq!(console.local_addr(), None);
+
+        let address = console.spawn().await?;
+        assert_ne!(address.port(), 0);
+        assert_eq!(console.local_addr(), Some(address));
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handlers_see_extensions() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Greeter)?
+            .extensions(Extensions::new().with(Greeting("hello")))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("world").await?;
+        assert_eq!(client.weak_read().await?, "hello, world");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audit_lists_commands_per_identity() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .command_history(2)
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        for command in ["first", "second", "third"] {
+            client.weak_send(command).await?;
+            assert_eq!(client.weak_read().await?, "only");
+        }
+
+        // The audit command itself is recorded too, only the last two commands are kept.
+        client.weak_send("audit 127.0.0.1").await?;
+        let report = client.weak_read().await?;
+        let commands = report
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["third", "127.0.0.1"]);
+
+        client.weak_send("audit nobody").await?;
+        assert_eq!(client.weak_read().await?, "No commands recorded for nobody");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audit_renders_the_session_time_zone() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .command_history(1)
+            .time_zones()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("tz").await?;
+        assert_eq!(client.weak_read().await?, "Time zone is UTC");
+        client.weak_send("tz Mars/Olympus").await?;
+        assert_eq!(client.weak_read().await?, "Unknown time zone Mars/Olympus");
+        client.weak_send("tz +05:30").await?;
+        assert_eq!(client.weak_read().await?, "Time zone set to +05:30");
+
+        client.weak_send("audit 127.0.0.1").await?;
+        let report = client.weak_read().await?;
+        assert!(
+            report.ends_with(" +05:30 audit 127.0.0.1"),
+            "Unexpected report {report}"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_is_required_before_dispatch() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .require_token("secret")
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut anonymous = crate::Client::new(address, None).await?;
+        anonymous.weak_send("ping").await?;
+        assert_eq!(anonymous.weak_read().await?, "Authentication required");
+        assert_eq!(anonymous.send_recv(TestService::One, &()).await?, None);
+
+        let error = crate::Client::new(address, Some("wrong"))
+            .await
+            .err()
+            .expect("Wrong token must be refused");
+        assert!(matches!(
+            error.downcast_ref::<ClientError>(),
+            Some(ClientError::AuthenticationFailed(_))
+        ));
+
+        let mut client = crate::Client::new(address, Some("secret")).await?;
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorizer_restricts_services() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("one"))?
+            .subscribe(TestService::Two, Name("two"))?
+            .authorizer(|auth, service| {
+                auth.peer.ip().is_loopback() && *service != TestService::Two
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        for _ in 0..8 {
+            client.weak_send("who").await?;
+            assert_eq!(client.weak_read().await?, "one");
+        }
+        let denied = client.send_recv(TestService::Two, &()).await.unwrap_err();
+        assert!(matches!(
+            denied.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == "Access denied to two"
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn services_are_restricted_to_networks() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Name("two"))?
+            .restrict_service(TestService::One, &["10.0.3.0/24".parse()?])
+            .restrict_service(TestService::Two, &["10.0.3.0/24".parse()?])
+            .restrict_service(TestService::Three, &["127.0.0.1/32".parse()?])
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let denied = client.send_recv(TestService::One, &()).await.unwrap_err();
+        assert!(matches!(
+            denied.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == "Access denied from 127.0.0.1: one"
+        ));
+        client.weak_send("who").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Access denied from 127.0.0.1: one, two"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriptions_change_at_runtime() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("one"))?
+            .build()?;
+        let address = console.spawn().await?;
+        let handle = console.handle();
+
+        let mut client = crate::Client::new(address, None).await?;
+        let unknown = client.send_recv(TestService::Two, &()).await.unwrap_err();
+        assert!(matches!(
+            unknown.downcast_ref::<ClientError>(),
+            Some(ClientError::UnknownService(name)) if name == "two"
+        ));
+
+        handle.register(TestService::Two, Pong)?;
+        assert!(handle.register(TestService::Two, Pong).is_err());
+        assert_eq!(
+            client.send_recv(TestService::Two, &()).await?,
+            Some(Bytes::from_static(b"pong"))
+        );
+
+        assert!(handle.unregister(&TestService::Two));
+        assert!(!handle.unregister(&TestService::Two));
+        assert!(client.send_recv(TestService::Two, &()).await.is_err());
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn outbound_console_dials_gateway() -> anyhow::Result<()> {
+        let gateway = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let console = crate::Builder::new()
+            .subscribe(TestService::One, Name("only"))?
+            .welcome("hi")
+            .build_outbound()?;
+        console.dial(
+            gateway.local_addr()?,
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(100)),
+        );
+
+        // The console dials again after the gateway closes a session.
+        for _ in 0..2 {
+            let (stream, _) = gateway.accept().await?;
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            assert_eq!(lines.next_line().await?.as_deref(), Some("hi"));
+
+            write.write_all(b"ping\n").await?;
+            assert_eq!(lines.next_line().await?.as_deref(), Some("only"));
+        }
+
+        console.shutdown(Duration::from_secs(1)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multiplexed_sessions_share_a_connection() -> anyhow::Result<()> {
+        let gateway = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let console = crate::Builder::new()
+            .subscribe(TestService::One, Whoami)?
+            .welcome("hi")
+            .build_outbound()?;
+        console.dial_multiplexed(gateway.local_addr()?, Backoff::default());
+
+        let (stream, _) = gateway.accept().await?;
+        let multiplexer = Multiplexer::new(stream);
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let (read, write) = tokio::io::split(multiplexer.open()?);
+            let mut lines = BufReader::new(read).lines();
+            assert_eq!(lines.next_line().await?.as_deref(), Some("hi"));
+            sessions.push((lines, write));
+        }
+        assert_eq!(console.session_count(), 3);
+
+        // Sessions are independent: every channel is answered by its own session.
+        let mut answers = Vec::new();
+        for (lines, write) in sessions.iter_mut().rev() {
+            write.write_all(b"whoami\n").await?;
+            answers.push(lines.next_line().await?.unwrap_or_default());
+        }
+        answers.sort();
+        answers.dedup();
+        assert_eq!(answers.len(), 3);
+        assert!(answers.iter().all(|answer| answer.starts_with("session ")));
+
+        drop(sessions);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(console.session_count(), 0);
+
+        console.shutdown(Duration::from_secs(1)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn elevation_expires() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .authorizer(|auth, _| auth.is_elevated())
+            .elevation(|_, reason| (reason != "no reason").then_some(Duration::from_millis(200)))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        assert!(client.send_recv(TestService::One, &()).await.is_err());
+
+        client.weak_send("elevate no reason").await?;
+        assert_eq!(client.weak_read().await?, "Elevation denied");
+        client.weak_send("elevate incident 42").await?;
+        assert_eq!(client.weak_read().await?, "Elevated for 200ms");
+        assert_eq!(
+            client.send_recv(TestService::One, &()).await?,
+            Some(Bytes::from_static(b"pong"))
+        );
+
+        time::sleep(Duration::from_millis(250)).await;
+        assert!(client.send_recv(TestService::One, &()).await.is_err());
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Pong;
+
+    #[async_trait]
+    impl Subscription for Pong {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(Bytes::from_static(b"pong")))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_see_session_context() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Whoami)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        for session_id in 0..2 {
+            let mut client = crate::Client::new(address, None).await?;
+            client.weak_send("whoami").await?;
+            assert_eq!(
+                client.weak_read().await?,
+                format!("session {session_id} from 127.0.0.1")
+            );
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lifecycle_hooks_run_per_session() -> anyhow::Result<()> {
+        let closed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, Whoami)?
+            .on_connect(|context| Some(format!("Hello, session {}", context.session_id())))
+            .on_disconnect({
+                let closed = closed.clone();
+                move |context| closed.lock().unwrap().push(context.session_id())
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut lines = BufReader::new(TcpStream::connect(address).await?).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Welcome"));
+        assert_eq!(
+            lines.next_line().await?.as_deref(),
+            Some("Hello, session 0")
+        );
+        drop(lines);
+
+        time::timeout(Duration::from_secs(5), async {
+            while closed.lock().unwrap().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        assert_eq!(*closed.lock().unwrap(), [0]);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn machine_clients_skip_the_welcome() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let hello = [0xC0, 0xC1, 0, 0, 0, 0];
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(&hello).await?;
+        // The console acknowledges with the protocol versions it speaks.
+        let Frame::Typed(offer) = Control::Offer(PROTOCOL_VERSIONS.to_vec()).to_frame() else {
+            unreachable!("Handshakes are typed frames");
+        };
+        let mut ack = vec![0u8; 6 + offer.len()];
+        tokio::io::AsyncReadExt::read_exact(&mut stream, &mut ack).await?;
+        assert_eq!(ack[..2], hello[..2]);
+        assert_eq!(ack[2..6], (offer.len() as u32).to_be_bytes());
+        assert_eq!(ack[6..], offer);
+
+        let mut client = crate::Client::new(address, None).await?;
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn consoles_build_from_config() -> anyhow::Result<()> {
+        let config = crate::ConsoleConfig {
+            bind_address: Some((Ipv4Addr::LOCALHOST, 0).into()),
+            welcome: Some("Configured".to_owned()),
+            allowed_networks: Some(vec!["127.0.0.0/8".parse()?]),
+            max_connections: Some(1),
+            ..Default::default()
+        };
+        let mut console = crate::Builder::from_config(config)
+            .subscribe(TestService::One, Pong)?
+            .try_build()?;
+        let address = console.spawn().await?;
+
+        let mut lines = BufReader::new(TcpStream::connect(address).await?).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Configured"));
+        let mut busy = BufReader::new(TcpStream::connect(address).await?).lines();
+        assert_eq!(
+            busy.next_line().await?.as_deref(),
+            Some("Console is busy, try again later")
+        );
+        console.stop();
+
+        let unbound = crate::Builder::<TestService>::from_config(crate::ConsoleConfig::default())
+            .subscribe(TestService::One, Pong)?
+            .try_build();
+        assert!(matches!(unbound, Err(crate::Error::NoBindAddress)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn welcome_is_evaluated_per_session() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("v{version}, {services} services")
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut lines = BufReader::new(TcpStream::connect(address).await?).lines();
+        let expected = format!("v{}, 2 services", env!("CARGO_PKG_VERSION"));
+        assert_eq!(lines.next_line().await?, Some(expected));
+        console.stop();
+
+        let sessions = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome_with({
+                let sessions = sessions.clone();
+                move || {
+                    format!(
+                        "Session #{}",
+                        sessions.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                    )
+                }
+            })
+            .subscribe(TestService::One, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        for expected in ["Session #0", "Session #1"] {
+            let mut lines = BufReader::new(TcpStream::connect(address).await?).lines();
+            assert_eq!(lines.next_line().await?.as_deref(), Some(expected));
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_rejected() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .max_message_size(64)
+            .subscribe(TestService::One, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let notice = "Message exceeds the limit of 64 bytes";
+
+        let reply = client.send_recv(TestService::One, &vec![0u8; 100]).await?;
+        assert_eq!(reply, None);
+        assert_eq!(client.weak_read().await?, notice);
+
+        client.weak_send(&"x".repeat(100)).await?;
+        assert_eq!(client.weak_read().await?, notice);
+
+        // The session stays usable.
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_consumers_are_disconnected() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .write_queue(4, crate::SlowConsumer::Disconnect)
+            .subscribe(TestService::One, Flood)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(b"flood\n").await?;
+        // Not reading lets the socket buffers fill up, then the write queue.
+        time::sleep(Duration::from_millis(500)).await;
+
+        let mut received = Vec::new();
+        time::timeout(
+            Duration::from_secs(5),
+            tokio::io::AsyncReadExt::read_to_end(&mut stream, &mut received),
+        )
+        .await??;
+        assert!(received.len() < FLOOD_CHUNKS * FLOOD_CHUNK.len());
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn typed_messages_route_by_version() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe_version(TestService::One, 1, Name("v1"))?
+            .subscribe_version(TestService::One, 2, Name("v2"))?
+            .subscribe(TestService::Two, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        for (version, expected) in [(1, "v1"), (2, "v2")] {
+            let id = client
+                .send_versioned_request(TestService::One, version, &())
+                .await?;
+            let reply = client.recv_response(id).await?;
+            assert_eq!(reply.as_deref(), Some(expected.as_bytes()));
+        }
+        // Messages without a version go to the latest one.
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"v2"[..]));
+
+        for (service_id, version, expected) in [
+            (
+                TestService::One,
+                3,
+                "Service one does not support version 3, supported versions: 1, 2",
+            ),
+            (
+                TestService::Two,
+                1,
+                "Service two is not versioned, version 1 is not supported",
+            ),
+        ] {
+            let id = client
+                .send_versioned_request(service_id, version, &())
+                .await?;
+            let refused = client.recv_response(id).await.unwrap_err();
+            assert!(matches!(
+                refused.downcast_ref::<ClientError>(),
+                Some(ClientError::Refused(reason)) if reason == expected
+            ));
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn late_subscriptions_ask_to_retry() -> anyhow::Result<()> {
+        let mut console = crate::Builder::<TestService>::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .starting_up()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let refused = client.send_recv(TestService::One, &()).await.unwrap_err();
+        assert!(matches!(
+            refused.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == super::STARTING_UP
+        ));
+        client.weak_send("status").await?;
+        assert_eq!(client.weak_read().await?, super::STARTING_UP);
+
+        console.subscribe(TestService::One, Pong)?;
+        console.subscribe(TestService::Two, Name("two"))?;
+        console.ready();
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+        // Unknown services are no longer worth retrying.
+        assert!(client.send_recv(TestService::Three, &()).await.is_err());
+        client.weak_send("status").await?;
+        assert_eq!(client.weak_read().await?, "two");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handles_work_outside_the_runtime() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Name("two"))?
+            .weak_dispatch(crate::WeakDispatch::Ordered)
+            .build()?;
+        let address = console.spawn().await?;
+        let mut client = crate::Client::new(address, None).await?;
+
+        let handle = console.handle();
+        let metrics = std::thread::spawn(move || {
+            handle.set_service_enabled(&TestService::One, false);
+            handle.set_service_enabled(&TestService::Two, false);
+            handle.set_service_enabled(&TestService::Two, true);
+            handle.broadcast("Disabled one");
+            handle.metrics()
+        })
+        .join()
+        .expect("Thread must not panic");
+        assert_eq!(metrics.active_sessions, 1);
+
+        assert_eq!(client.weak_read().await?, "Disabled one");
+        let refused = client.send_recv(TestService::One, &()).await.unwrap_err();
+        assert!(matches!(
+            refused.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == "Service one is disabled"
+        ));
+        client.weak_send("who").await?;
+        assert_eq!(client.weak_read().await?, "two");
+
+        let handle = console.handle();
+        std::thread::spawn(move || handle.stop())
+            .join()
+            .expect("Thread must not panic");
+        time::timeout(Duration::from_secs(5), client.weak_read())
+            .await?
+            .expect_err("Session must be closed");
+
+        Ok(())
+    }
+
+    /// Logs every message it is offered, from within the dispatch span.
+    struct Chatty;
+
+    #[async_trait]
+    impl Subscription for Chatty {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            tracing::info!(target: "app", "Handling {message}");
+            Ok(Some("handled".to_owned()))
+        }
+    }
+
+    /// Buffer collecting the output of a `tracing_subscriber::fmt` subscriber.
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatches_are_logged_within_session_spans() -> anyhow::Result<()> {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let captured = captured.clone();
+                move || captured.clone()
+            })
+            .with_ansi(false)
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Chatty)?
+            .span_fields(crate::SpanFields {
+                peer: false,
+                ..Default::default()
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("hello").await?;
+        assert_eq!(client.weak_read().await?, "handled");
+        console.stop();
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone())?;
+        let line = logs
+            .lines()
+            .find(|line| line.contains("Handling hello"))
+            .expect("The subscription logs the message");
+        assert!(
+            line.contains("session{session_id=0 identity=\"127.0.0.1\"}:dispatch{service=\"one\"}"),
+            "{line}"
+        );
+
+        Ok(())
+    }
+
+    /// Adds two numbers, without decoding them by hand.
+    struct Sum;
+
+    #[async_trait]
+    impl crate::TypedSubscription for Sum {
+        type Request = (u32, u32);
+        type Response = u32;
+
+        async fn handle(&self, (a, b): (u32, u32)) -> Result<Option<u32>, SubscriptionError> {
+            Ok(Some(a + b))
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_subscriptions_decode_requests() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Sum)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let sum: Option<u32> = client.request(TestService::One, &(2u32, 3u32)).await?;
+        assert_eq!(sum, Some(5));
+        // Clients sharing the handler type are type-checked against it.
+        let sum = client.call::<Sum, _>(TestService::One, &(4, 5)).await?;
+        assert_eq!(sum, Some(9));
+
+        // A request of the wrong type fails, and is counted as an error.
+        let failed = client
+            .request::<_, _, u32>(TestService::One, &"2 + 3")
+            .await;
+        assert!(matches!(
+            failed.unwrap_err().downcast_ref::<ClientError>(),
+            Some(ClientError::Failed(error)) if error.code == crate::HANDLER_FAILED
+        ));
+        assert_eq!(console.metrics().handler_errors, 1);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_memory_sessions_need_no_socket() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("memory"))?
+            .max_connections(1)
+            .build()?;
+
+        let mut client = console.connect_in_memory().await?;
+        client.weak_send("who").await?;
+        assert_eq!(client.weak_read().await?, "memory");
+        let reply = client.send_recv(TestService::One, &"who").await?;
+        assert_eq!(reply, Some(Bytes::from_static(b"memory")));
+        assert_eq!(console.session_count(), 1);
+
+        let refused = console.connect_in_memory().await.map(|_| ());
+        assert!(matches!(
+            refused.unwrap_err().downcast_ref::<crate::Error>(),
+            Some(crate::Error::SessionLimit)
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Answers a typed number of milliseconds after sleeping that long.
+    struct Sleepy;
+
+    #[async_trait]
+    impl Subscription for Sleepy {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            let millis: u64 = bcs::from_bytes(&message)?;
+            time::sleep(Duration::from_millis(millis)).await;
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_do_not_wait_for_slow_ones() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Sleepy)?
+            .concurrent_requests(4)
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let slow = client.send_request(TestService::One, &300u64).await?;
+        let quick = client.send_request(TestService::One, &0u64).await?;
+        let started = std::time::Instant::now();
+        let reply = client.recv_response(quick).await?;
+        assert_eq!(reply, Some(bcs::to_bytes(&0u64)?.into()));
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        let reply = client.recv_response(slow).await?;
+        assert_eq!(reply, Some(bcs::to_bytes(&300u64)?.into()));
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Fails every message.
+    struct Failing;
+
+    #[async_trait]
+    impl Subscription for Failing {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Err("Disk is full".into())
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Err("Disk is full".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_errors_are_reported_to_the_sender() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Failing)?
+            .error_reporting(crate::ErrorReporting::Full)
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        client.weak_send("save").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Service one failed to handle the message: Disk is full"
+        );
+        let failed = client
+            .send_recv(TestService::One, &"save")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            failed.downcast_ref::<ClientError>(),
+            Some(ClientError::Failed(error)) if error.code == crate::HANDLER_FAILED
+                && error.message == "Service one failed to handle the message: Disk is full"
+        ));
+        console.stop();
+
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Failing)?
+            .error_reporting(crate::ErrorReporting::Summary)
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+        client.weak_send("save").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Service one failed to handle the message"
+        );
+        console.stop();
+
+        // Requests learn about the failure even if nothing is reported.
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Failing)?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+        let failed = client
+            .send_recv(TestService::One, &"save")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            failed.downcast_ref::<ClientError>(),
+            Some(ClientError::Failed(error)) if error.code == crate::HANDLER_FAILED
+                && error.message == "Service one failed to handle the message"
+        ));
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Picky;
+
+    #[async_trait]
+    impl Subscription for Picky {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            let height: u64 = bcs::from_bytes(&message)?;
+            Err(
+                crate::ConsoleError::new("block_not_found", "Block is unknown")
+                    .with_details(height.to_string())
+                    .into(),
+            )
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn structured_errors_reach_clients() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Picky)?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let failed = client
+            .send_recv(TestService::One, &42u64)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            failed.downcast_ref::<ClientError>(),
+            Some(ClientError::Failed(error)) if *error
+                == crate::ConsoleError::new("block_not_found", "Block is unknown").with_details("42")
+        ));
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unanswered_commands_get_the_fallback() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .unknown_command("Unknown command, try `help`")
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        client.weak_send("reboot").await?;
+        assert_eq!(client.weak_read().await?, "Unknown command, try `help`");
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_subscriptions_answer_under_broadcast_dispatch() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("one"))?
+            .subscribe(TestService::Two, Pong)?
+            .subscribe(TestService::Three, Name("three"))?
+            .weak_dispatch(crate::WeakDispatch::All)
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        client.weak_send("dump").await?;
+        // Answers may arrive in one read or several.
+        let mut answers = client.weak_read().await?;
+        while answers.lines().count() < 2 {
+            answers = format!("{answers}\n{}", client.weak_read().await?);
+        }
+        assert_eq!(answers, "one\nthree");
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn default_subscription_gets_unclaimed_messages() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .default_subscription(Name("legacy"))
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply, Some(Bytes::from_static(b"pong")));
+        let reply = client.send_recv(TestService::Two, &()).await?;
+        assert_eq!(reply, Some(Bytes::from_static(b"legacy")));
+        client.weak_send("uptime").await?;
+        assert_eq!(client.weak_read().await?, "legacy");
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clients_list_registered_services() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .subscribe_version(TestService::Three, 2, Name("v2"))?
+            .subscribe_version(TestService::Three, 1, Name("v1"))?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let services = client.list_services().await?;
+        assert_eq!(
+            services,
+            [
+                crate::ServiceInfo {
+                    name: "one".to_owned(),
+                    versions: vec![],
+                    description: None,
+                },
+                crate::ServiceInfo {
+                    name: "two".to_owned(),
+                    versions: vec![],
+                    description: Some("Tells who you are".to_owned()),
+                },
+                crate::ServiceInfo {
+                    name: "three".to_owned(),
+                    versions: vec![1, 2],
+                    description: None,
+                },
+            ]
+        );
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Dump(usize);
+
+    #[async_trait]
+    impl Subscription for Dump {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(vec![b'x'; self.0].into()))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_compressed_when_negotiated() -> anyhow::Result<()> {
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Dump(64 << 10))?
+            .compressor(RunLength)
+            .frame_tap({
+                let received = received.clone();
+                move |direction, bytes| {
+                    if direction == WireDirection::Outbound {
+                        received.lock().unwrap().push(bytes.len());
+                    }
+                }
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        for compressors in [vec![], vec![std::sync::Arc::new(RunLength) as _]] {
+            let compressed = !compressors.is_empty();
+            let config = crate::ClientConfig {
+                compressors,
+                ..Default::default()
+            };
+            let mut client = crate::Client::with_config(address, config).await?;
+            let reply = client.send_recv(TestService::One, &()).await?;
+            assert_eq!(reply.map(|bytes| bytes.len()), Some(64 << 10));
+
+            let largest = received.lock().unwrap().drain(..).max().unwrap_or_default();
+            assert_eq!(
+                largest < 1 << 10,
+                compressed,
+                "Largest frame sent is {largest} bytes"
+            );
+        }
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_sent_in_chunks() -> anyhow::Result<()> {
+        let sizes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Dump(10_000))?
+            .chunk_size(1024)
+            .frame_tap({
+                let sizes = sizes.clone();
+                move |direction, bytes| {
+                    if direction == WireDirection::Outbound {
+                        sizes.lock().unwrap().push(bytes.len());
+                    }
+                }
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply, Some(Bytes::from(vec![b'x'; 10_000])));
+        let reply = tokio::task::spawn_blocking(move || {
+            crate::blocking::Client::new(address)?.send_recv(TestService::One, &())
+        })
+        .await??;
+        assert_eq!(reply.map(|bytes| bytes.len()), Some(10_000));
+
+        let sizes = sizes.lock().unwrap();
+        assert!(
+            sizes.iter().all(|size| *size <= 1032),
+            "Frame sizes {sizes:?}"
+        );
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_are_listed_and_kicked() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .sessions_command()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut operator = crate::Client::new(address, None).await?;
+        let mut intruder = crate::Client::new(address, None).await?;
+        intruder.send_recv(TestService::One, &()).await?;
+
+        let sessions = console.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions
+            .iter()
+            .all(|session| session.peer.ip().is_loopback()));
+        assert!(sessions[1].bytes_in > 0 && sessions[1].bytes_out > 0);
+
+        operator.weak_send("sessions").await?;
+        let listed = operator.weak_read().await?;
+        let lines = listed.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("#{} ", sessions[0].session_id)));
+        assert!(lines[0].ends_with(" (this session)"));
+        assert!(!lines[1].ends_with(" (this session)"));
+
+        assert!(console.kick(sessions[1].session_id));
+        assert_eq!(intruder.weak_read().await?, "Session closed by the console");
+        assert!(intruder.weak_read().await.is_err());
+        assert!(!console.kick(sessions[1].session_id));
+        assert_eq!(
+            console
+                .sessions()
+                .iter()
+                .map(|session| session.session_id)
+                .collect::<Vec<_>>(),
+            [sessions[0].session_id]
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Records the hooks it sees in a log shared with other layers, refuses messages for `refused`.
+    struct Recording {
+        name: &'static str,
+        refused: Option<&'static str>,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl crate::Middleware for Recording {
+        async fn before(
+            &self,
+            _context: &Context,
+            message: &crate::Incoming,
+        ) -> Result<(), SubscriptionError> {
+            let target = match message {
+                crate::Incoming::Typed { service, .. } => service.as_str(),
+                crate::Incoming::Text(text) => text.as_str(),
+            };
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} before {target}", self.name));
+            match self.refused {
+                Some(refused) if refused == target => Err(format!("{target} is off limits").into()),
+                _ => Ok(()),
+            }
+        }
+
+        async fn after(
+            &self,
+            _context: &Context,
+            _message: &crate::Incoming,
+            outcome: &crate::Outcome,
+        ) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} after {}", self.name, outcome.answered));
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_wraps_dispatch() -> anyhow::Result<()> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .layer(Recording {
+                name: "outer",
+                refused: None,
+                log: log.clone(),
+            })
+            .layer(Recording {
+                name: "inner",
+                refused: Some("three"),
+                log: log.clone(),
+            })
+            .subscribe(TestService::Three, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        assert_eq!(
+            client.send_recv(TestService::One, &()).await?,
+            Some(Bytes::from_static(b"pong"))
+        );
+        client.weak_send("whoami").await?;
+        assert!(client.weak_read().await?.starts_with("session "));
+        let refused = client.send_recv(TestService::Three, &()).await.unwrap_err();
+        assert!(matches!(
+            refused.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == "three is off limits"
+        ));
+        // Built-in commands bypass middleware.
+        client.list_services().await?;
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "outer before one",
+                "inner before one",
+                "inner after true",
+                "outer after true",
+                "outer before whoami",
+                "inner before whoami",
+                "inner after true",
+                "outer after true",
+                "outer before three",
+                "inner before three",
+            ]
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Audited(Arc<Mutex<Vec<crate::AuditRecord>>>);
+
+    impl crate::AuditSink for Audited {
+        fn record(&self, record: &crate::AuditRecord) {
+            self.0.lock().unwrap().push(record.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatched_messages_are_audited() -> anyhow::Result<()> {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .audit(Audited(records.clone()))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.send_recv(TestService::One, &"secret").await?;
+        client.weak_send("whoami").await?;
+        client.weak_read().await?;
+
+        let records = records.lock().unwrap().clone();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.service.as_deref(), record.payload.as_str()))
+                .collect::<Vec<_>>(),
+            [(Some("one"), "<7 bytes>"), (None, "whoami")]
+        );
+        assert!(records
+            .iter()
+            .all(|record| record.peer.ip().is_loopback() && record.identity == "127.0.0.1"));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Never answers free-form messages, within a timeout of its own.
+    struct Hanging;
+
+    #[async_trait]
+    impl Subscription for Hanging {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            std::future::pending().await
+        }
+
+        fn handler_timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(50))
+        }
+    }
+
+    #[tokio::test]
+    async fn hung_handlers_time_out() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Sleepy)?
+            .subscribe(TestService::Two, Hanging)?
+            .handler_timeout(Duration::from_millis(100))
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let timed_out = client
+            .send_recv(TestService::One, &10_000u64)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            timed_out.downcast_ref::<ClientError>(),
+            Some(ClientError::HandlerTimeout(timeout)) if *timeout == Duration::from_millis(100)
+        ));
+        assert!(client.send_recv(TestService::One, &0u64).await?.is_some());
+
+        client.weak_send("anything").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Service two timed out after 50ms"
+        );
+        assert_eq!(console.service_stats()[0].errors, 1);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Panicking;
+
+    #[async_trait]
+    impl Subscription for Panicking {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            panic!("typed boom")
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            let value: u32 = message.parse().expect("a number");
+            Ok(Some(value.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_keep_the_session_alive() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Panicking)?
+            .error_reporting(crate::ErrorReporting::Full)
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let failed = client.send_recv(TestService::One, &()).await.unwrap_err();
+        assert!(matches!(
+            failed.downcast_ref::<ClientError>(),
+            Some(ClientError::HandlerPanicked)
+        ));
+        client.weak_send("many").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Service one failed to handle the message: Handler panicked: a number: ParseIntError { kind: InvalidDigit }"
+        );
+        client.weak_send("7").await?;
+        assert_eq!(client.weak_read().await?, "7");
+        assert_eq!(console.session_count(), 1);
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_client_handles_send_concurrently() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Sleepy)?
+            .concurrent_requests(4)
+            .build()?;
+        let shared = SharedClient::new(console.connect_in_memory().await?);
+
+        let slow = tokio::spawn({
+            let shared = shared.clone();
+            async move { shared.request::<_, _, u64>(TestService::One, &300u64).await }
+        });
+        let started = std::time::Instant::now();
+        let quick = shared.request::<_, _, u64>(TestService::One, &0u64).await?;
+        assert_eq!(quick, Some(0));
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(slow.await??, Some(300));
+
+        let err = shared.send_recv(TestService::Two, &()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::UnknownService(_))
+        ));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sessions_open_without_welcome() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .no_welcome()
+            .prompt("node> ")
+            .subscribe(TestService::One, Name("only"))?
+            .build()?;
+        let address = console.spawn().await?;
+
+        // Interactive sessions go straight to the prompt.
+        let mut stream = TcpStream::connect(address).await?;
+        let mut received = vec![0; "node> ".len()];
+        tokio::io::AsyncReadExt::read_exact(&mut stream, &mut received).await?;
+        assert_eq!(received, b"node> ");
+
+        // The handshake of programmatic clients does not depend on the welcome.
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Restarts on `restart`, changing state.
+    struct Restart;
+
+    #[async_trait]
+    impl Subscription for Restart {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(Bytes::from_static(b"restarting")))
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok((message == "restart").then(|| "restarting".to_owned()))
+        }
+
+        fn is_mutating(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_refuses_mutating_commands() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Restart)?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        console.set_mode(crate::Mode::ReadOnly);
+        assert_eq!(console.mode(), crate::Mode::ReadOnly);
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+        let refused = client.send_recv(TestService::Two, &()).await.unwrap_err();
+        assert!(matches!(
+            refused.downcast_ref::<ClientError>(),
+            Some(ClientError::Refused(reason)) if reason == super::MAINTENANCE
+        ));
+        client.weak_send("restart").await?;
+        assert_eq!(client.weak_read().await?, super::MAINTENANCE);
+
+        console.set_mode(crate::Mode::Normal);
+        client.weak_send("restart").await?;
+        assert_eq!(client.weak_read().await?, "restarting");
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_replaced_without_dropping_sessions() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("old"))?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+        let handle = console.handle();
+
+        client.weak_send("who").await?;
+        assert_eq!(client.weak_read().await?, "old");
+
+        handle.replace(TestService::One, Name("new"))?;
+        client.weak_send("who").await?;
+        assert_eq!(client.weak_read().await?, "new");
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"new"[..]));
+
+        assert!(matches!(
+            handle.replace(TestService::Two, Pong),
+            Err(super::Error::ServiceNotRegistered(name)) if name == "two"
+        ));
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Answers every free-form message with the text it was handed.
+    struct Echo;
+
+    #[async_trait]
+    impl Subscription for Echo {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(message.to_owned()))
+        }
+    }
+
+    #[tokio::test]
+    async fn namespaced_services_are_routed_and_grouped() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe("network".to_owned(), Name("network"))?
+            .subscribe("network.peers".to_owned(), Echo)?
+            .subscribe("status".to_owned(), Whoami)?
+            .subscribe("storage.compaction".to_owned(), Echo)?
+            .weak_dispatch(crate::WeakDispatch::Prefix)
+            .with_help()
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        // Segments may be separate words, handlers see the name with dots.
+        client.weak_send("network peers list").await?;
+        assert_eq!(client.weak_read().await?, "network.peers list");
+        client.weak_send("network.peers").await?;
+        assert_eq!(client.weak_read().await?, "network.peers");
+        client.weak_send("network status").await?;
+        assert_eq!(client.weak_read().await?, "network");
+
+        let reply = client
+            .send_recv("storage.compaction".to_owned(), &7u8)
+            .await?;
+        assert_eq!(reply.as_deref(), Some(&[7u8][..]));
+
+        client.weak_send("help").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Services:\n  network\n  status - Tells who you are\n\
+             \x20 network:\n    peers\n\
+             \x20 storage:\n    compaction\n\
+             Built-in commands:\n  help"
+        );
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batches_are_handled_in_order() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Picky)?
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        let batch = crate::Batch::new()
+            .with(TestService::One, &())?
+            .with(TestService::Three, &())?
+            .with(TestService::Two, &42u64)?
+            .with(TestService::One, &())?;
+        let results = client.send_batch(batch).await?;
+        assert_eq!(results.len(), 4);
+        assert!(matches!(&results[0], Ok(Some(reply)) if reply.as_ref() == b"pong"));
+        assert!(matches!(&results[1], Err(ClientError::UnknownService(name)) if name == "three"));
+        assert!(
+            matches!(&results[2], Err(ClientError::Failed(error)) if error.code == "block_not_found")
+        );
+        assert!(matches!(&results[3], Ok(Some(reply)) if reply.as_ref() == b"pong"));
+
+        // The connection goes on serving single requests.
+        let reply = client.send_recv(TestService::One, &()).await?;
+        assert_eq!(reply.as_deref(), Some(&b"pong"[..]));
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Streams a countdown on `countdown`, one line at a time.
+    struct Countdown;
+
+    #[async_trait]
+    impl Subscription for Countdown {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_stream(
+            &self,
+            _context: &Context,
+            message: &str,
+        ) -> Result<Option<crate::ReplyStream>, SubscriptionError> {
+            if message != "countdown" {
+                return Ok(None);
+            }
+            let lines = stream::iter((1..=3).rev()).then(|i| async move {
+                time::sleep(Duration::from_millis(20)).await;
+                Bytes::from(format!("{i}\n"))
+            });
+            Ok(Some(lines.boxed()))
+        }
+    }
+
+    #[tokio::test]
+    async fn piped_runbooks_run_in_script_mode() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .prompt("node> ")
+            .subscribe(TestService::One, Countdown)?
+            .subscribe(TestService::Two, Name("only"))?
+            .weak_dispatch(crate::WeakDispatch::Ordered)
+            .script_command()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(b"script\n# Count down, then ask\n\ncountdown\nwho\n")
+            .await?;
+        stream.shutdown().await?;
+        let mut output = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut output).await?;
+        assert_eq!(
+            output,
+            "Welcome\nnode> Running the script\n> countdown\n3\n2\n1\n> who\nonly\n"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn runtime_command_reports_the_process() -> anyhow::Result<()> {
+        let console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .runtime_command()
+            .build()?;
+        let mut client = console.connect_in_memory().await?;
+
+        client.weak_send("runtime").await?;
+        let report = client.weak_read().await?;
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[..3], ["Uptime: 0s", "Sessions: 1", "Workers: 1"]);
+        assert!(lines[3].starts_with("Alive tasks: "));
+        assert!(lines[4].starts_with("Global queue depth: "));
+        if cfg!(target_os = "linux") {
+            assert!(lines[5].starts_with("Resident memory: "));
+        }
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn frame_tap_sees_both_directions() -> anyhow::Result<()> {
+        let frames = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Name("only"))?
+            .frame_tap({
+                let frames = frames.clone();
+                move |direction, bytes| frames.lock().unwrap().push((direction, bytes.clone()))
+            })
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("ping").await?;
+        assert_eq!(client.weak_read().await?, "only");
+
+        let latest = *PROTOCOL_VERSIONS.last().unwrap();
+        assert_eq!(client.protocol_version(), latest);
+
+        // The machine handshake and the protocol negotiation go both ways before the command,
+        // as they are on the wire, however the bytes read are split.
+        let wire = |frames: Vec<Frame>| {
+            let mut bytes = BytesMut::new();
+            for frame in frames {
+                FrameCodec::client().encode(frame, &mut bytes).unwrap();
+            }
+            bytes.freeze()
+        };
+        let frames = frames.lock().unwrap().clone();
+        let direction = |wanted| {
+            let bytes: Vec<u8> = frames
+                .iter()
+                .filter(|(direction, _)| *direction == wanted)
+                .flat_map(|(_, bytes)| bytes.to_vec())
+                .collect();
+            Bytes::from(bytes)
+        };
+        assert_eq!(
+            direction(WireDirection::Inbound),
+            wire(vec![
+                MACHINE_HELLO,
+                Control::Select(latest).to_frame(),
+                Frame::Text(Bytes::from_static(b"ping\n")),
+            ])
+        );
+        assert_eq!(
+            direction(WireDirection::Outbound),
+            wire(vec![
+                Control::Offer(PROTOCOL_VERSIONS.to_vec()).to_frame(),
+                Frame::Text(Bytes::from_static(b"only\n")),
+            ])
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delta_updates_reconstruct_replies() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Counters::default())?
+            .delta_updates()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.enable_delta_updates().await?;
+        for polled in 1..=3 {
+            client.weak_send("counters").await?;
+            let expected = (0..20)
+                .map(|i| format!("counter {i}: {}", if i == 7 { polled } else { 0 }))
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert_eq!(client.weak_read().await?, expected);
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct Counters {
+        polled: std::sync::atomic::AtomicU64,
+    }
+
+    #[async_trait]
+    impl Subscription for Counters {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            if message != "counters" {
+                return Ok(None);
+            }
+
+            let polled = self
+                .polled
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                + 1;
+            Ok(Some(
+                (0..20)
+                    .map(|i| format!("counter {i}: {}\n", if i == 7 { polled } else { 0 }))
+                    .collect(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_push_to_their_session() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, Progress)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let (reader, mut writer) = TcpStream::connect(address).await?.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Welcome"));
+
+        writer.write_all(b"progress\n").await?;
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Started"));
+        for step in 1..=3 {
+            assert_eq!(
+                lines.next_line().await?,
+                Some(format!("Step {step} of 3 done"))
+            );
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Number and size of the chunks streamed by [Flood], more than socket buffers hold.
+    const FLOOD_CHUNKS: usize = 4096;
+    static FLOOD_CHUNK: [u8; 64 << 10] = [b'x'; 64 << 10];
+
+    /// Streams output much faster than a client reads it.
+    struct Flood;
+
+    #[async_trait]
+    impl Subscription for Flood {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_stream(
+            &self,
+            _context: &Context,
+            _message: &str,
+        ) -> Result<Option<crate::ReplyStream>, SubscriptionError> {
+            let chunk = Bytes::from_static(&FLOOD_CHUNK);
+            Ok(Some(stream::repeat(chunk).take(FLOOD_CHUNKS).boxed()))
+        }
+    }
+
+    struct Progress;
+
+    #[async_trait]
+    impl Subscription for Progress {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_with(
+            &self,
+            context: &Context,
+            _message: &str,
+        ) -> Result<Option<String>, SubscriptionError> {
+            let push = context.push_handle().clone();
+            tokio::spawn(async move {
+                for step in 1..=3 {
+                    push.push(format!("Step {step} of 3 done")).await?;
+                }
+                Ok::<_, crate::SessionClosed>(())
+            });
+
+            Ok(Some("Started".to_owned()))
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcast_reaches_all_sessions() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut clients = [
+            crate::Client::new(address, None).await?,
+            crate::Client::new(address, None).await?,
+        ];
+        // A round trip makes sure both sessions are served.
+        for client in &mut clients {
+            client.send_recv(TestService::One, &()).await?;
+        }
+
+        assert_eq!(console.broadcast("Node is degraded"), 2);
+        assert_eq!(console.handle().broadcast_typed(&42u32)?, 2);
+        for client in &mut clients {
+            assert_eq!(client.weak_read().await?, "Node is degraded");
+            let alert = client.recv_typed().await?;
+            assert_eq!(bcs::from_bytes::<u32>(alert.as_ref())?, 42);
+        }
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn selftest_reports_unhealthy_services() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Unhealthy { hang: false })?
+            .subscribe(TestService::Three, Unhealthy { hang: true })?
+            .selftest(Duration::from_millis(100))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("selftest").await?;
+        let report = client.weak_read().await?;
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "1 of 3 services healthy");
+        assert!(lines[1].starts_with("one: ok in "));
+        assert_eq!(lines[2], "two: failed: Database is unreachable");
+        assert_eq!(lines[3], "three: no answer within 100ms");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Unhealthy {
+        hang: bool,
+    }
+
+    #[async_trait]
+    impl Subscription for Unhealthy {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn health(&self) -> Result<(), SubscriptionError> {
+            if self.hang {
+                std::future::pending::<()>().await;
+            }
+            Err("Database is unreachable".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_subscriptions_are_validated_and_probed() -> anyhow::Result<()> {
+        let invalid = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Ledger { configured: false })?
+            .build();
+        assert!(invalid.is_err());
+
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Ledger { configured: true })?
+            .selftest(Duration::from_millis(100))
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("selftest").await?;
+        let report = client.weak_read().await?;
+        assert_eq!(
+            report.lines().collect::<Vec<_>>(),
+            [
+                "0 of 1 services healthy",
+                "one: failed: Ledger is unreachable"
+            ]
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Ledger {
+        configured: bool,
+    }
+
+    #[async_trait]
+    impl crate::TypedSubscription for Ledger {
+        type Request = ();
+        type Response = ();
+
+        async fn handle(&self, _request: ()) -> Result<Option<()>, SubscriptionError> {
+            Ok(None)
+        }
+
+        fn validate(&self) -> Result<(), String> {
+            match self.configured {
+                true => Ok(()),
+                false => Err("Missing ledger path".to_owned()),
+            }
+        }
+
+        async fn health(&self) -> Result<(), SubscriptionError> {
+            Err("Ledger is unreachable".into())
+        }
+    }
+
+    #[test]
+    fn build_reports_all_invalid_subscriptions() -> anyhow::Result<()> {
+        let result = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(
+                TestService::One,
+                Misconfigured("Missing file /etc/app.toml"),
+            )?
+            .subscribe(TestService::Two, Pong)?
+            .subscribe(TestService::Three, Misconfigured("Bad pattern `[`"))?
+            .build();
+
+        let Err(err) = result else {
+            panic!("Misconfigured subscriptions must fail the build");
+        };
+        assert_eq!(
+            err.to_string(),
+            "Subscriptions failed validation: one: Missing file /etc/app.toml; three: Bad pattern `[`"
+        );
+
+        Ok(())
+    }
+
+    struct Misconfigured(&'static str);
+
+    #[async_trait]
+    impl Subscription for Misconfigured {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        fn validate(&self) -> Result<(), String> {
+            Err(self.0.to_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn published_events_reach_topic_subscribers() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .topics()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut subscriber = crate::Client::new(address, None).await?;
+        subscriber.subscribe_topic("alerts").await?;
+        let mut bystander = crate::Client::new(address, None).await?;
+        bystander.subscribe_topic("metrics").await?;
+
+        assert_eq!(console.publish("alerts", "Disk is almost full"), 1);
+        assert_eq!(console.publish_typed("alerts", &7u8)?, 1);
+        assert_eq!(console.publish("deploys", "Nobody listens"), 0);
+        assert_eq!(subscriber.weak_read().await?, "Disk is almost full");
+        assert_eq!(
+            bcs::from_bytes::<u8>(subscriber.recv_typed().await?.as_ref())?,
+            7
+        );
+
+        bystander.weak_send("unsubscribe metrics").await?;
+        assert_eq!(bystander.weak_read().await?, "Unsubscribed from metrics");
+        assert_eq!(console.publish("metrics", "cpu: 12%"), 0);
+
+        drop(subscriber);
+        time::timeout(Duration::from_secs(5), async {
+            while console.publish("alerts", "Anyone?") > 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_announced_on_the_lifecycle_topic() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .topics()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.subscribe_topic(crate::LIFECYCLE_TOPIC).await?;
+
+        console.shutdown(Duration::from_secs(1)).await?;
+        assert_eq!(client.weak_read().await?, "draining");
+        assert!(client.weak_read().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stats_count_calls_per_service() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .stats_command()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.send_recv(TestService::One, &()).await?;
+        client.send_recv(TestService::One, &()).await?;
+        client.weak_send("whoami").await?;
+        client.weak_read().await?;
+
+        let stats = console.service_stats();
+        assert_eq!(
+            stats
+                .iter()
+                .map(|stats| (stats.service.as_str(), stats.calls, stats.errors))
+                .collect::<Vec<_>>(),
+            [("one", 2, 0), ("two", 1, 0)]
+        );
+        assert_eq!(stats[0].latency.count(), 2);
+
+        client.weak_send("stats two").await?;
+        assert!(client
+            .weak_read()
+            .await?
+            .starts_with("two: 1 calls, 0 errors, mean "));
+        client.weak_send("stats three").await?;
+        assert_eq!(client.weak_read().await?, "No calls recorded for three");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stats_include_host_metrics() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .stats_command()
+            .metrics_provider(|| vec![crate::Metric::gauge("mempool_transactions", 42.0)])
+            .metrics_provider(|| vec![crate::Metric::counter("blocks_processed", 7.0)])
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("stats").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "No calls recorded\nMetrics:\n  mempool_transactions: 42\n  blocks_processed: 7"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prometheus_exporter_reports_console_metrics() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .metrics_provider(|| vec![crate::Metric::gauge("mempool_transactions", 42.0)])
+            .build()?;
+        let address = console.spawn().await?;
+        console.subscribe(
+            TestService::Two,
+            crate::PrometheusExporter::new(console.handle()),
+        )?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.send_recv(TestService::One, &()).await?;
+        client.weak_send("metrics").await?;
+        let text = client.weak_read().await?;
+        for line in [
+            "# TYPE tcp_console_accepted_connections counter",
+            "tcp_console_active_sessions 1",
+            "tcp_console_service_calls{service=\"one\"} 1",
+            "# TYPE mempool_transactions gauge",
+            "mempool_transactions 42",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{line} missing in {text}");
+        }
+
+        let reply = client
+            .send_recv(TestService::Two, &())
+            .await?
+            .expect("Exporter must reply");
+        assert!(bcs::from_bytes::<String>(&reply)?.contains("tcp_console_messages 3"));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streamed_replies_are_forwarded_as_they_arrive() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .welcome("Welcome")
+            .subscribe(TestService::One, LogDump)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let (reader, mut writer) = TcpStream::connect(address).await?.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        assert_eq!(lines.next_line().await?.as_deref(), Some("Welcome"));
+
+        // The dump never ends, yet its lines arrive.
+        writer.write_all(b"dump\n").await?;
+        assert_eq!(lines.next_line().await?.as_deref(), Some("line 1"));
+        assert_eq!(lines.next_line().await?.as_deref(), Some("line 2"));
+
+        console.shutdown(Duration::from_secs(5)).await?;
+        assert_eq!(lines.next_line().await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_interrupting_a_stream_are_answered() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, LogDump)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("dump").await?;
+        assert_eq!(client.weak_read().await?, "line 1");
+
+        // The request ends the never ending dump and is answered nonetheless.
+        let reply = time::timeout(
+            Duration::from_secs(5),
+            client.send_recv(TestService::One, &"ping"),
+        )
+        .await??;
+        assert_eq!(reply, Some(bcs::to_bytes("ping")?.into()));
+
+        console.stop();
+
+        Ok(())
+    }
+
+    /// Reports a colored status.
+    struct Colorful;
+
+    #[async_trait]
+    impl Subscription for Colorful {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(format!("db: {}", crate::Styled::red("DOWN"))))
+        }
+    }
+
+    struct LogDump;
+
+    #[async_trait]
+    impl Subscription for LogDump {
+        async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(message))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_stream(
+            &self,
+            _context: &Context,
+            _message: &str,
+        ) -> Result<Option<crate::ReplyStream>, SubscriptionError> {
+            let lines = stream::iter(1..=2)
+                .then(|i| async move {
+                    time::sleep(Duration::from_millis(10)).await;
+                    Bytes::from(format!("line {i}\n"))
+                })
+                .chain(stream::pending());
+            Ok(Some(lines.boxed()))
+        }
+    }
+
+    #[tokio::test]
+    async fn help_lists_services_and_builtins() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Pong)?
+            .subscribe(TestService::Two, Whoami)?
+            .subscribe(TestService::Three, Progress)?
+            .restrict_service(TestService::Three, &["10.0.0.0/8".parse()?])
+            .stats_command()
+            .with_help()
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        client.weak_send("help").await?;
+        assert_eq!(
+            client.weak_read().await?,
+            "Services:\n  one\n  two - Tells who you are\n\
+             Built-in commands:\n  stats [service]\n  help"
+        );
+
+        console.stop();
+
+        Ok(())
+    }
+
+    struct Whoami;
+
+    #[async_trait]
+    impl Subscription for Whoami {
+        fn description(&self) -> Option<&str> {
+            Some("Tells who you are")
+        }
+
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_with(
+            &self,
+            context: &Context,
+            _message: &str,
+        ) -> Result<Option<String>, SubscriptionError> {
+            assert!(context.started_at() <= std::time::SystemTime::now());
+            Ok(Some(format!(
+                "session {} from {}",
+                context.session_id(),
+                context.identity()
+            )))
+        }
+    }
+
+    struct Greeting(&'static str);
+
+    struct Greeter;
+
+    #[async_trait]
+    impl Subscription for Greeter {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle_with(
+            &self,
+            context: &Context,
+            message: &str,
+        ) -> Result<Option<String>, SubscriptionError> {
+            let greeting = context.get::<Greeting>().ok_or("No greeting provided")?;
+            Ok(Some(format!("{}, {message}", greeting.0)))
+        }
+    }
+
+    struct Slow(Duration);
+
+    #[async_trait]
+    impl Subscription for Slow {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            time::sleep(self.0).await;
+            Ok(Some("done".to_owned()))
+        }
+    }
+
+    struct Name(&'static str);
+
+    #[async_trait]
+    impl Subscription for Name {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(Bytes::from_static(self.0.as_bytes())))
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(self.0.to_owned()))
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::auth::AuthContext;
+use crate::push::PushHandle;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Session-level information handed to [Subscription](crate::Subscription) handlers,
+/// see [Subscription::handle_with](crate::Subscription::handle_with).
+#[derive(Clone, Debug)]
+pub struct Context {
+    session_id: u64,
+    started_at: SystemTime,
+    pub(crate) auth: AuthContext,
+    pub(crate) protocol_version: Option<u32>,
+    extensions: Arc<Extensions>,
+    push: PushHandle,
+}
+
+impl Context {
+    pub(crate) fn new(
+        session_id: u64,
+        peer: SocketAddr,
+        extensions: Arc<Extensions>,
+        push: PushHandle,
+    ) -> Self {
+        Self {
+            session_id,
+            started_at: SystemTime::now(),
+            auth: AuthContext::new(peer),
+            protocol_version: None,
+            extensions,
+            push,
+        }
+    }
+
+    /// Identifier of the session, unique within the [Console](crate::Console).
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Address of the remote end of the session.
+    pub fn peer(&self) -> SocketAddr {
+        self.auth.peer
+    }
+
+    /// Identity the session acts as, see [AuthContext::identity].
+    pub fn identity(&self) -> &str {
+        &self.auth.identity
+    }
+
+    /// When the session was opened.
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// Protocol version negotiated with a programmatic client,
+    /// `None` for humans and clients predating negotiation.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.protocol_version
+    }
+
+    /// Authentication state of the session.
+    pub fn auth(&self) -> &AuthContext {
+        &self.auth
+    }
+
+    /// Dependencies provided by the host via [Builder::extensions](crate::Builder::extensions).
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Handle sending texts to the session at any time, independent of request and response.
+    /// Clone it into a spawned task to keep pushing updates after the handler returns.
+    pub fn push_handle(&self) -> &PushHandle {
+        &self.push
+    }
+
+    /// Shortcut for `self.extensions().get::<T>()`.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+}
+
+/// Called when a session opens, returns an optional greeting sent after the welcome message.
+pub(crate) type ConnectHook = Box<dyn Fn(&Context) -> Option<String> + Send + Sync>;
+
+/// Called when a session closes.
+pub(crate) type DisconnectHook = Box<dyn Fn(&Context) + Send + Sync>;
+
+/// A map holding at most one value of each type,
+/// used to share host dependencies (app handles, connection pools) with all handlers.
+#[derive(Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value, returning the previously stored value of the same type, if any.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Chainable variant of [Extensions::insert].
+    pub fn with<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.insert(value);
+        self
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
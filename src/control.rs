@@ -0,0 +1,128 @@
+//! Frames between a console and programmatic clients which are not content: protocol version
+//! negotiation, following [MACHINE_HELLO](crate::codec::MACHINE_HELLO), and heartbeats.
+//!
+//! The console acknowledges the hello with the versions it speaks, the client answers with the one
+//! it picked. Consoles predating negotiation acknowledge with the bare hello, which means version 1.
+
+use crate::client::ClientError;
+use crate::codec::Frame;
+use crate::compression::Compressor;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Protocol versions this build speaks, in ascending order.
+pub(crate) const PROTOCOL_VERSIONS: &[u32] = &[1, 2, 3, 4, 5];
+
+/// First version with [Control::Ping] and [Control::Pong].
+pub(crate) const HEARTBEAT_VERSION: u32 = 2;
+
+/// First version with [Control::Accept] and [Control::Compress].
+pub(crate) const COMPRESSION_VERSION: u32 = 3;
+
+/// First version with chunked typed frames, see [Builder::chunk_size](crate::Builder::chunk_size).
+pub(crate) const CHUNKED_VERSION: u32 = 4;
+
+/// First version with batches, see [Client::send_batch](crate::Client::send_batch).
+pub(crate) const BATCH_VERSION: u32 = 5;
+
+/// Version spoken by peers predating negotiation.
+pub(crate) const LEGACY_VERSION: u32 = 1;
+
+/// First byte of control frames. A message envelope starts with the tag of its optional id,
+/// which is never this.
+const MARKER: u8 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Control {
+    /// Versions the console speaks, in ascending order.
+    Offer(Vec<u32>),
+    /// Version the client picked.
+    Select(u32),
+    /// Asks the other end to answer with [Control::Pong], see [Builder::heartbeat](crate::Builder::heartbeat).
+    Ping,
+    Pong,
+    /// Names of the compressors the client can decompress with, by preference,
+    /// see [Builder::compressor](crate::Builder::compressor).
+    Accept(Vec<String>),
+    /// Name of the compressor the console compresses typed frames with from now on.
+    Compress(String),
+}
+
+impl Control {
+    pub(crate) fn to_frame(&self) -> Frame {
+        let mut bytes = vec![MARKER];
+        bytes.extend(bcs::to_bytes(self).expect("Control is always serializable"));
+        Frame::Typed(bytes.into())
+    }
+
+    /// Whether `frame` is a control frame, which is not content and thus not accounted.
+    pub(crate) fn is_control(frame: &Frame) -> bool {
+        matches!(frame, Frame::Typed(bytes) if bytes.first() == Some(&MARKER))
+    }
+
+    /// Control carried by `frame`, `None` if it is anything else.
+    pub(crate) fn from_frame(frame: &Frame) -> Option<Self> {
+        match frame {
+            Frame::Typed(bytes) if Self::is_control(frame) => bcs::from_bytes(&bytes[1..]).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// What a client speaking `version` announces after [Control::Select], if anything.
+pub(crate) fn accept(version: u32, compressors: &[Arc<dyn Compressor>]) -> Option<Control> {
+    if version < COMPRESSION_VERSION || compressors.is_empty() {
+        return None;
+    }
+    let names = compressors
+        .iter()
+        .map(|compressor| compressor.name().to_owned());
+    Some(Control::Accept(names.collect()))
+}
+
+/// Compressor named by [Control::Compress], among those the client accepted.
+pub(crate) fn negotiated(
+    name: &str,
+    compressors: &[Arc<dyn Compressor>],
+) -> Option<Arc<dyn Compressor>> {
+    compressors
+        .iter()
+        .find(|compressor| compressor.name() == name)
+        .cloned()
+}
+
+/// Highest version both the console offering `offered` and this build speak.
+pub(crate) fn choose(offered: &[u32]) -> Result<u32, ClientError> {
+    offered
+        .iter()
+        .filter(|version| PROTOCOL_VERSIONS.contains(version))
+        .max()
+        .copied()
+        .ok_or_else(|| ClientError::ProtocolMismatch {
+            offered: offered.to_vec(),
+            supported: PROTOCOL_VERSIONS.to_vec(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose, Control, PROTOCOL_VERSIONS};
+    use crate::client::ClientError;
+    use crate::codec::{Frame, MACHINE_HELLO};
+
+    #[test]
+    fn versions_are_negotiated() {
+        let offer = Control::Offer(vec![1, 7]);
+        assert_eq!(Control::from_frame(&offer.to_frame()), Some(offer));
+        assert_eq!(Control::from_frame(&MACHINE_HELLO), None);
+        assert_eq!(Control::from_frame(&Frame::Typed(vec![0, 1].into())), None);
+
+        assert_eq!(choose(&[1, 2, 7]).unwrap(), 2);
+        assert_eq!(choose(&[1, 2, 3]).unwrap(), 3);
+        assert_eq!(choose(PROTOCOL_VERSIONS).unwrap(), 5);
+        assert!(matches!(
+            choose(&[7, 8]),
+            Err(ClientError::ProtocolMismatch { offered, .. }) if offered == [7, 8]
+        ));
+    }
+}
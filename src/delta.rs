@@ -0,0 +1,178 @@
+//! Line-based delta encoding of text replies, see [Builder::delta_updates](crate::Builder::delta_updates).
+//!
+//! Once a session sends [DELTA_ON], every text reply may be sent as a delta against the previous one:
+//! the [DELTA_MARKER] line followed by one operation per line, `=<n>` to keep the next `n` lines
+//! of the previous reply, `-<n>` to skip them and `+<line>` to insert a line.
+//! Deltas are only sent when shorter than the reply itself.
+
+/// Command enabling delta updates for the session.
+pub(crate) const DELTA_ON: &str = "delta on";
+/// Command disabling delta updates for the session.
+pub(crate) const DELTA_OFF: &str = "delta off";
+/// Answer to [DELTA_ON].
+pub(crate) const DELTA_ENABLED: &str = "Delta updates enabled";
+/// Answer to [DELTA_OFF].
+pub(crate) const DELTA_DISABLED: &str = "Delta updates disabled";
+/// First line of a delta.
+pub(crate) const DELTA_MARKER: &str = "@delta";
+
+/// Replies with more lines than this are always sent in full, bounding the cost of diffing.
+const MAX_LINES: usize = 2048;
+
+/// Encodes `next` as a delta against `previous`, returns `None` if the delta would not be shorter.
+pub(crate) fn diff(previous: &str, next: &str) -> Option<String> {
+    let (old, new) = (lines(previous), lines(next));
+    if old.len() > MAX_LINES || new.len() > MAX_LINES {
+        return None;
+    }
+
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Ops::default();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.keep();
+            (i, j) = (i + 1, j + 1);
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            ops.insert(new[j]);
+            j += 1;
+        } else {
+            ops.skip();
+            i += 1;
+        }
+    }
+
+    let delta = ops.finish();
+    (delta.len() < next.len()).then_some(delta)
+}
+
+/// Reconstructs the reply encoded as `delta` against `previous`,
+/// returns `None` if `delta` is not a delta or does not match `previous`.
+pub(crate) fn apply(previous: &str, delta: &str) -> Option<String> {
+    let mut operations = lines(delta).into_iter();
+    if operations.next()? != DELTA_MARKER {
+        return None;
+    }
+
+    let old = lines(previous);
+    let mut position = 0;
+    let mut new = Vec::new();
+    for operation in operations {
+        if let Some(line) = operation.strip_prefix('+') {
+            new.push(line);
+        } else if let Some(count) = operation.strip_prefix('=') {
+            let end = position + count.parse::<usize>().ok()?;
+            new.extend_from_slice(old.get(position..end)?);
+            position = end;
+        } else {
+            position += operation.strip_prefix('-')?.parse::<usize>().ok()?;
+        }
+    }
+
+    (position == old.len()).then(|| new.join("\n") + "\n")
+}
+
+/// Lines of a reply, which always ends with a newline.
+fn lines(text: &str) -> Vec<&str> {
+    text.strip_suffix('\n')
+        .unwrap_or(text)
+        .split('\n')
+        .collect()
+}
+
+/// Accumulates delta operations, merging runs of kept and skipped lines.
+#[derive(Default)]
+struct Ops {
+    encoded: Vec<String>,
+    kept: usize,
+    skipped: usize,
+}
+
+impl Ops {
+    fn keep(&mut self) {
+        self.flush_skipped();
+        self.kept += 1;
+    }
+
+    fn skip(&mut self) {
+        self.flush_kept();
+        self.skipped += 1;
+    }
+
+    fn insert(&mut self, line: &str) {
+        self.flush_kept();
+        self.flush_skipped();
+        self.encoded.push(format!("+{line}"));
+    }
+
+    fn flush_kept(&mut self) {
+        if self.kept > 0 {
+            self.encoded.push(format!("={}", self.kept));
+            self.kept = 0;
+        }
+    }
+
+    fn flush_skipped(&mut self) {
+        if self.skipped > 0 {
+            self.encoded.push(format!("-{}", self.skipped));
+            self.skipped = 0;
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_kept();
+        self.flush_skipped();
+
+        let mut delta = DELTA_MARKER.to_owned();
+        for operation in self.encoded {
+            delta.push('\n');
+            delta.push_str(&operation);
+        }
+        delta.push('\n');
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, diff};
+
+    #[test]
+    fn delta_reconstructs_reply() {
+        let previous: String = (0..20).map(|i| format!("counter {i}: 0\n")).collect();
+        let next = previous
+            .replace("counter 3: 0", "counter 3: 1")
+            .replace("counter 17: 0\n", "")
+            + "counter 20: 0\n";
+
+        let delta = diff(&previous, &next).expect("Delta must be shorter than the reply");
+        assert_eq!(
+            delta,
+            "@delta\n=3\n+counter 3: 1\n-1\n=13\n-1\n=2\n+counter 20: 0\n"
+        );
+        assert_eq!(apply(&previous, &delta).as_deref(), Some(next.as_str()));
+    }
+
+    #[test]
+    fn unrelated_replies_are_sent_in_full() {
+        assert_eq!(diff("status: ok\n", "uptime: 5s\n"), None);
+    }
+
+    #[test]
+    fn mismatching_delta_is_rejected() {
+        assert_eq!(apply("a\nb\n", "@delta\n=3\n"), None);
+        assert_eq!(apply("a\nb\n", "@delta\n=1\n"), None);
+        assert_eq!(apply("a\nb\n", "=2\n"), None);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Notable events happening on [Console](crate::Console),
+/// see [Console::events](crate::Console::events).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConsoleEvent {
+    /// A session has been closed.
+    SessionClosed(SessionSummary),
+}
+
+/// Statistics of a closed session.
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub peer: SocketAddr,
+    pub duration: Duration,
+    /// Number of messages dispatched to subscriptions.
+    pub commands: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Number of malformed frames, failed handlers and failed sends.
+    pub errors: u64,
+}
+
+impl Display for SessionSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "peer {}, {:.3}s, {} commands, {} bytes in, {} bytes out, {} errors",
+            self.peer,
+            self.duration.as_secs_f64(),
+            self.commands,
+            self.bytes_in,
+            self.bytes_out,
+            self.errors
+        )
+    }
+}
@@ -0,0 +1,126 @@
+use crate::codec::Frame;
+use crate::targets::SESSION;
+use bytes::BytesMut;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+/// Faults applied to frames sent by [Console](crate::Console).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Drop every N-th frame.
+    pub drop_every: Option<u64>,
+    /// Corrupt every N-th frame by flipping the bits of a byte in its middle.
+    pub corrupt_every: Option<u64>,
+    /// Delay every frame by this duration.
+    pub delay: Option<Duration>,
+}
+
+/// Fault-injection layer to test tooling against a misbehaving console link.
+///
+/// The injector is a cheap-to-clone handle: keep a clone around to change [Faults] at runtime.
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    faults: Arc<Mutex<Faults>>,
+    frames: Arc<AtomicU64>,
+}
+
+impl FaultInjector {
+    /// Creates an injector which does not inject any faults until [FaultInjector::set] is called.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the currently injected faults.
+    pub fn set(&self, faults: Faults) {
+        *self
+            .faults
+            .lock()
+            .expect("Faults lock must not be poisoned") = faults;
+    }
+
+    /// Stops injecting faults.
+    pub fn clear(&self) {
+        self.set(Faults::default());
+    }
+
+    /// Currently injected faults.
+    pub fn faults(&self) -> Faults {
+        self.faults
+            .lock()
+            .expect("Faults lock must not be poisoned")
+            .clone()
+    }
+
+    /// Applies the configured faults to an outgoing frame,
+    /// returns `None` if the frame must be dropped.
+    pub(crate) async fn apply(&self, frame: Frame) -> Option<Frame> {
+        let faults = self.faults();
+        let n = self.frames.fetch_add(1, Ordering::Relaxed) + 1;
+        let hits = |every: Option<u64>| every.is_some_and(|every| n.is_multiple_of(every));
+
+        if let Some(delay) = faults.delay {
+            time::sleep(delay).await;
+        }
+
+        if hits(faults.drop_every) {
+            debug!(target: SESSION, "Fault injection: dropping frame #{n}");
+            return None;
+        }
+
+        if hits(faults.corrupt_every) {
+            debug!(target: SESSION, "Fault injection: corrupting frame #{n}");
+            return Some(match frame {
+                Frame::Typed(bytes) => Frame::Typed(corrupt(&bytes)),
+                Frame::Text(bytes) => Frame::Text(corrupt(&bytes)),
+            });
+        }
+
+        Some(frame)
+    }
+}
+
+fn corrupt(bytes: &[u8]) -> bytes::Bytes {
+    let mut corrupted = BytesMut::from(bytes);
+    if let Some(byte) = corrupted.get_mut(bytes.len() / 2) {
+        *byte ^= 0xFF;
+    }
+    corrupted.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::Frame;
+    use crate::{FaultInjector, Faults};
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn faults_follow_runtime_configuration() {
+        let injector = FaultInjector::new();
+        let frame = Frame::Text(Bytes::from_static(b"abc"));
+
+        injector.set(Faults {
+            drop_every: Some(2),
+            corrupt_every: Some(3),
+            delay: None,
+        });
+
+        let mut applied = Vec::new();
+        for _ in 0..3 {
+            applied.push(injector.apply(frame.clone()).await);
+        }
+        assert_eq!(
+            applied,
+            [
+                Some(frame.clone()),
+                None,
+                Some(Frame::Text(Bytes::from_static(b"a\x9dc")))
+            ]
+        );
+
+        injector.clear();
+        assert_eq!(injector.apply(frame.clone()).await, Some(frame));
+    }
+}
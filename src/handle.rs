@@ -0,0 +1,212 @@
+use crate::console::{text_frame, typed_frame, Error, Inner, Mode};
+use crate::metrics::Metric;
+use crate::registry::SessionInfo;
+use crate::service::ServiceId;
+use crate::stats::ConsoleMetrics;
+use crate::subscription::Subscription;
+use crate::targets::DISPATCH;
+use crate::topics::LIFECYCLE_TOPIC;
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::debug;
+
+/// A cloneable handle to a [Console](crate::Console), obtained with [Console::handle](crate::Console::handle).
+///
+/// Subscriptions registered or unregistered through the handle take effect for the next message
+/// of every session, messages being handled meanwhile complete with the subscriptions they started with.
+///
+/// None of its methods is async or needs to run on the console's runtime, so synchronous parts of
+/// the host application, such as signal handlers or FFI callbacks, can drive the console from any thread.
+pub struct ConsoleHandle<Services> {
+    inner: Arc<Inner<Services>>,
+    stop: CancellationToken,
+    /// Tracks the accepting task and all session tasks of the console.
+    tasks: TaskTracker,
+}
+
+impl<Services> ConsoleHandle<Services> {
+    pub(crate) fn new(
+        inner: Arc<Inner<Services>>,
+        stop: CancellationToken,
+        tasks: TaskTracker,
+    ) -> Self {
+        Self { inner, stop, tasks }
+    }
+
+    /// Same as [Console::stop](crate::Console::stop).
+    pub fn stop(&self) {
+        self.stop_with("stopping");
+    }
+
+    /// Publishes the lifecycle `event`, unless already stopped, and stops.
+    pub(crate) fn stop_with(&self, event: &str) {
+        if !self.stop.is_cancelled() {
+            self.publish(LIFECYCLE_TOPIC, event);
+        }
+        self.stop.cancel();
+        self.tasks.close();
+    }
+
+    /// Same as [Console::metrics](crate::Console::metrics).
+    pub fn metrics(&self) -> ConsoleMetrics {
+        self.inner
+            .stats
+            .metrics(self.inner.sessions.load(Ordering::Relaxed))
+    }
+
+    /// Metrics of every [Builder::metrics_provider](crate::Builder::metrics_provider).
+    pub(crate) fn host_metrics(&self) -> Vec<Metric> {
+        self.inner.host_metrics()
+    }
+
+    /// Same as [Console::sessions](crate::Console::sessions).
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.inner.registry.list()
+    }
+
+    /// Same as [Console::kick](crate::Console::kick).
+    pub fn kick(&self, session_id: u64) -> bool {
+        self.inner.registry.kick(session_id)
+    }
+
+    /// Same as [Console::broadcast](crate::Console::broadcast).
+    pub fn broadcast(&self, text: &str) -> usize {
+        self.inner.broadcast(text_frame(text))
+    }
+
+    /// Same as [Console::broadcast_typed](crate::Console::broadcast_typed).
+    pub fn broadcast_typed<M: Serialize>(&self, message: &M) -> Result<usize, Error> {
+        Ok(self.inner.broadcast(typed_frame(message)?))
+    }
+
+    /// Same as [Console::publish](crate::Console::publish).
+    pub fn publish(&self, topic: &str, text: &str) -> usize {
+        self.inner.topics.publish(topic, text_frame(text))
+    }
+
+    /// Same as [Console::publish_typed](crate::Console::publish_typed).
+    pub fn publish_typed<M: Serialize>(&self, topic: &str, message: &M) -> Result<usize, Error> {
+        Ok(self.inner.topics.publish(topic, typed_frame(message)?))
+    }
+
+    /// Same as [Console::ready](crate::Console::ready).
+    pub fn ready(&self) {
+        self.inner.ready();
+    }
+}
+
+impl<Services> ConsoleHandle<Services>
+where
+    Services: ServiceId,
+{
+    /// Registers a subscription while the console is running,
+    /// failing with [Error::ServiceIdUsed] if `service_id` is already registered
+    /// and with [Error::InvalidSubscriptions] if the subscription fails [Subscription::validate].
+    pub fn register<T>(&self, service_id: Services, subscription: T) -> Result<(), Error>
+    where
+        T: Subscription + Send + Sync + 'static,
+    {
+        let mut subscriptions = self
+            .inner
+            .subscriptions
+            .write()
+            .expect("Subscriptions lock is never poisoned");
+        if subscriptions.contains(&service_id) {
+            return Err(Error::ServiceIdUsed(service_id.name().to_owned()));
+        }
+
+        subscription.validate().map_err(|reason| {
+            Error::InvalidSubscriptions(vec![format!("{}: {reason}", service_id.name())])
+        })?;
+
+        debug!(target: DISPATCH, "Registering service {}", service_id.name());
+        Arc::make_mut(&mut subscriptions).push(service_id, 0, Box::new(subscription));
+
+        Ok(())
+    }
+
+    /// Swaps the subscription of `service_id` for `subscription` while the console is running,
+    /// e.g. when a feature flag selects another backend. Sessions stay open: messages received
+    /// from now on go to `subscription`, requests in progress are still answered by the previous one.
+    /// Fails with [Error::ServiceNotRegistered] if `service_id` has no subscription
+    /// and with [Error::InvalidSubscriptions] if `subscription` fails [Subscription::validate].
+    pub fn replace<T>(&self, service_id: Services, subscription: T) -> Result<(), Error>
+    where
+        T: Subscription + Send + Sync + 'static,
+    {
+        let mut subscriptions = self
+            .inner
+            .subscriptions
+            .write()
+            .expect("Subscriptions lock is never poisoned");
+        if !subscriptions.contains(&service_id) {
+            return Err(Error::ServiceNotRegistered(service_id.name().to_owned()));
+        }
+
+        subscription.validate().map_err(|reason| {
+            Error::InvalidSubscriptions(vec![format!("{}: {reason}", service_id.name())])
+        })?;
+
+        debug!(target: DISPATCH, "Replacing service {}", service_id.name());
+        Arc::make_mut(&mut subscriptions).replace(service_id, Box::new(subscription));
+
+        Ok(())
+    }
+
+    /// Disables or re-enables the subscription of `service_id` without unregistering it, e.g. while
+    /// the subsystem behind it is unavailable. Requests for a disabled service are refused with
+    /// "Service <name> is disabled", free-form messages are not offered to it.
+    pub fn set_service_enabled(&self, service_id: &Services, enabled: bool) {
+        let mut disabled = self
+            .inner
+            .disabled
+            .lock()
+            .expect("Disabled services lock is never poisoned");
+        match enabled {
+            true => disabled.remove(service_id.name()),
+            false => disabled.insert(service_id.name().to_owned()),
+        };
+        debug!(target: DISPATCH, "Service {} is {}", service_id.name(), if enabled { "enabled" } else { "disabled" });
+    }
+
+    /// Switches the console to `mode` for messages received from now on, e.g. [Mode::ReadOnly]
+    /// during an upgrade. Typed messages for a [mutating](Subscription::is_mutating) subscription
+    /// are then refused with "Console is in maintenance mode", free-form messages are not offered
+    /// to such subscriptions and get that answer if no other subscription answers.
+    pub fn set_mode(&self, mode: Mode) {
+        self.inner
+            .read_only
+            .store(mode == Mode::ReadOnly, Ordering::Release);
+        debug!(target: DISPATCH, "Console is in {mode:?} mode");
+    }
+
+    /// Mode set with [ConsoleHandle::set_mode], [Mode::Normal] initially.
+    pub fn mode(&self) -> Mode {
+        self.inner.mode()
+    }
+
+    /// Unregisters the subscription of `service_id`, returns `false` if there was none.
+    pub fn unregister(&self, service_id: &Services) -> bool {
+        let mut subscriptions = self
+            .inner
+            .subscriptions
+            .write()
+            .expect("Subscriptions lock is never poisoned");
+
+        debug!(target: DISPATCH, "Unregistering service {}", service_id.name());
+        Arc::make_mut(&mut subscriptions).remove(service_id)
+    }
+}
+
+impl<Services> Clone for ConsoleHandle<Services> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            stop: self.stop.clone(),
+            tasks: self.tasks.clone(),
+        }
+    }
+}
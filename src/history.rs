@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A command executed on [Console](crate::Console).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub at: SystemTime,
+    /// Free-form text as received, or `<service> (typed)` for strongly typed messages.
+    pub command: String,
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", format_utc(self.at), self.command)
+    }
+}
+
+/// Storage of commands executed per identity, see [Builder::history_store](crate::Builder::history_store).
+pub trait HistoryStore: Send + Sync {
+    /// Records a command executed by `identity`.
+    fn record(&self, identity: &str, entry: HistoryEntry);
+
+    /// Recent commands executed by `identity`, oldest first.
+    fn recent(&self, identity: &str) -> Vec<HistoryEntry>;
+}
+
+/// [HistoryStore] keeping the last `capacity` commands of every identity in memory.
+pub struct MemoryHistory {
+    capacity: usize,
+    entries: Mutex<HashMap<String, VecDeque<HistoryEntry>>>,
+}
+
+impl MemoryHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl HistoryStore for MemoryHistory {
+    fn record(&self, identity: &str, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().expect("History lock is never poisoned");
+        let history = entries.entry(identity.to_owned()).or_default();
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(entry);
+    }
+
+    fn recent(&self, identity: &str) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().expect("History lock is never poisoned");
+        entries
+            .get(identity)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Formats `at` as `YYYY-MM-DD HH:MM:SS UTC`.
+pub(crate) fn format_utc(at: SystemTime) -> String {
+    format_local(unix_secs(at), "UTC")
+}
+
+/// Formats `secs` of local time since the epoch as `YYYY-MM-DD HH:MM:SS <zone>`.
+pub(crate) fn format_local(secs: i64, zone: &str) -> String {
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} {zone}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Seconds since the epoch, clamping earlier times to the epoch.
+pub(crate) fn unix_secs(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian date.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Shift the epoch to 0000-03-01, so that leap days end 400-year eras.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_utc, HistoryEntry, HistoryStore, MemoryHistory};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn memory_history_is_bounded_per_identity() {
+        let history = MemoryHistory::new(2);
+        for command in ["a", "b", "c"] {
+            history.record(
+                "alice",
+                HistoryEntry {
+                    at: UNIX_EPOCH,
+                    command: command.to_owned(),
+                },
+            );
+        }
+        history.record(
+            "bob",
+            HistoryEntry {
+                at: UNIX_EPOCH,
+                command: "d".to_owned(),
+            },
+        );
+
+        let commands = |identity| {
+            history
+                .recent(identity)
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands("alice"), ["b", "c"]);
+        assert_eq!(commands("bob"), ["d"]);
+        assert!(commands("carol").is_empty());
+    }
+
+    #[test]
+    fn utc_formatting() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+        assert_eq!(
+            format_utc(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            "2000-02-29 12:34:56 UTC"
+        );
+    }
+}
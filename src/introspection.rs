@@ -0,0 +1,103 @@
+//! Reserved command listing the services of a console, see [Client::list_services](crate::Client::list_services).
+
+/// Command answered with the services the session may use, one per line, followed by [END].
+/// The `@` keeps it apart from the commands of subscriptions.
+pub(crate) const SERVICES_COMMAND: &str = "@services";
+
+/// Last line of the answer to [SERVICES_COMMAND].
+const END: &str = "@end";
+
+/// A service registered on a console, as listed by [Client::list_services](crate::Client::list_services).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// Name of the service, see [ServiceId::name](crate::ServiceId::name).
+    pub name: String,
+    /// Versions registered with [Builder::subscribe_version](crate::Builder::subscribe_version), in ascending order.
+    pub versions: Vec<u32>,
+    /// See [Subscription::description](crate::Subscription::description).
+    pub description: Option<String>,
+}
+
+/// Answer to [SERVICES_COMMAND]: `name<TAB>versions<TAB>description` per service.
+pub(crate) fn render(services: &[ServiceInfo]) -> String {
+    let mut text = String::new();
+    for service in services {
+        let versions = service
+            .versions
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        // Descriptions are single lines, tabs would shift the columns.
+        let description = service
+            .description
+            .as_deref()
+            .unwrap_or_default()
+            .replace(['\t', '\n'], " ");
+        text.push_str(&format!("{}\t{versions}\t{description}\n", service.name));
+    }
+    text.push_str(END);
+    text
+}
+
+/// Services listed in `text`, `Ok(None)` until the whole answer is received,
+/// `Err` if `text` is not an answer to [SERVICES_COMMAND].
+pub(crate) fn parse(text: &str) -> Result<Option<Vec<ServiceInfo>>, ()> {
+    let mut services = Vec::new();
+    // The last piece is an incomplete line, or empty if `text` ends with a newline.
+    let mut lines = text.split('\n').collect::<Vec<_>>();
+    let partial = lines.pop().unwrap_or_default();
+    for line in lines.into_iter().chain((partial == END).then_some(partial)) {
+        if line == END {
+            return Ok(Some(services));
+        }
+
+        let mut columns = line.splitn(3, '\t');
+        let (Some(name), Some(versions), Some(description)) =
+            (columns.next(), columns.next(), columns.next())
+        else {
+            return Err(());
+        };
+        let versions = versions
+            .split(',')
+            .filter(|version| !version.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| ())?;
+        services.push(ServiceInfo {
+            name: name.to_owned(),
+            versions,
+            description: (!description.is_empty()).then(|| description.to_owned()),
+        });
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, render, ServiceInfo};
+
+    #[test]
+    fn listings_round_trip() {
+        let services = vec![
+            ServiceInfo {
+                name: "status".to_owned(),
+                versions: vec![],
+                description: Some("Reports\tthe status".to_owned()),
+            },
+            ServiceInfo {
+                name: "exec".to_owned(),
+                versions: vec![1, 2],
+                description: None,
+            },
+        ];
+        let text = render(&services);
+        let (head, tail) = text.split_at(10);
+        assert_eq!(parse(head), Ok(None));
+        let mut expected = services;
+        expected[0].description = Some("Reports the status".to_owned());
+        assert_eq!(parse(&format!("{head}{tail}")), Ok(Some(expected)));
+        assert_eq!(parse(&render(&[])), Ok(Some(vec![])));
+        assert_eq!(parse("Unknown command\n"), Err(()));
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An IP network in CIDR notation, e.g. `10.0.3.0/24` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IpNetError {
+    #[error("Network `{0}` is not in the `address/prefix` form")]
+    Malformed(String),
+    #[error("Prefix length {0} is too long for the address family")]
+    PrefixTooLong(u8),
+}
+
+impl IpNet {
+    /// Creates a network of addresses sharing the first `prefix_len` bits with `addr`.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, IpNetError> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return Err(IpNetError::PrefixTooLong(prefix_len));
+        }
+
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Loopback networks of both address families.
+    pub(crate) fn loopback() -> [Self; 2] {
+        [
+            Self {
+                addr: Ipv4Addr::new(127, 0, 0, 0).into(),
+                prefix_len: 8,
+            },
+            Self {
+                addr: Ipv6Addr::LOCALHOST.into(),
+                prefix_len: 128,
+            },
+        ]
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Checks whether `ip` belongs to the network.
+    /// IPv4-mapped IPv6 addresses are matched against IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpNetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || IpNetError::Malformed(s.to_owned());
+
+        let (addr, prefix_len) = s.split_once('/').ok_or_else(malformed)?;
+        let addr = addr.parse().map_err(|_| malformed())?;
+        let prefix_len = prefix_len.parse().map_err(|_| malformed())?;
+
+        Self::new(addr, prefix_len)
+    }
+}
+
+/// Serialized in CIDR notation, as in configuration files.
+impl Serialize for IpNet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IpNet, IpNetError};
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains() {
+        let net: IpNet = "10.0.3.0/24".parse().unwrap();
+        assert!(net.contains(ip("10.0.3.1")));
+        assert!(net.contains(ip("10.0.3.255")));
+        assert!(!net.contains(ip("10.0.4.1")));
+        assert!(net.contains(ip("::ffff:10.0.3.7")));
+        assert!(!net.contains(ip("fd00::1")));
+
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("192.168.1.1")));
+
+        let v6: IpNet = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+        assert!(IpNet::loopback().iter().any(|net| net.contains(ip("::1"))));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "10.0.3.0/24".parse::<IpNet>().unwrap().to_string(),
+            "10.0.3.0/24"
+        );
+        assert_eq!(
+            "10.0.3.0/33".parse::<IpNet>(),
+            Err(IpNetError::PrefixTooLong(33))
+        );
+        assert!(matches!(
+            "10.0.3.0".parse::<IpNet>(),
+            Err(IpNetError::Malformed(_))
+        ));
+
+        let deserializer =
+            serde::de::value::StrDeserializer::<serde::de::value::Error>::new("fd00::/8");
+        assert_eq!(
+            serde::Deserialize::deserialize(deserializer),
+            Ok("fd00::/8".parse::<IpNet>().unwrap())
+        );
+    }
+}
@@ -1,14 +1,143 @@
+pub mod targets;
+
 mod client;
-pub use client::Client;
+pub use client::{Client, ClientConfig, ClientError};
+
+pub mod blocking;
+
+pub mod testing;
+
+mod shared;
+pub use shared::SharedClient;
+
+mod batch;
+pub use batch::Batch;
+
+mod reconnect;
+pub use reconnect::{Backoff, ReconnectingClient};
+
+mod transcript;
+pub use transcript::{
+    CipherError, Direction, Entry, Mismatch, Recorder, SealedEntries, Transcript, TranscriptCipher,
+    TranscriptError,
+};
+
+#[cfg(feature = "fault-injection")]
+mod fault;
+#[cfg(feature = "fault-injection")]
+pub use fault::{FaultInjector, Faults};
+
+#[cfg(feature = "log-tail")]
+mod log_tail;
+#[cfg(feature = "log-tail")]
+pub use log_tail::{LogBuffer, LogTail, LogTailLayer};
+
+mod codec;
+
+mod control;
+
+mod chunked;
+
+mod compression;
+pub use compression::Compressor;
+
+mod telnet;
+
+mod rate_limit;
+
+mod ip_net;
+pub use ip_net::{IpNet, IpNetError};
+
+mod rng;
 
 mod console;
-pub use console::{Console, Error};
+pub use console::{Console, Error, ErrorReporting, Mode, SpanFields, WeakDispatch, WireDirection};
+
+mod mux;
+pub use mux::Multiplexer;
+
+mod handle;
+pub use handle::ConsoleHandle;
+
+mod session;
+
+mod registry;
+pub use registry::SessionInfo;
+
+mod outbox;
+pub use outbox::SlowConsumer;
+
+mod delta;
+
+mod topics;
+pub use topics::LIFECYCLE_TOPIC;
+
+mod timezone;
+
+mod welcome;
+
+mod runtime;
+
+mod introspection;
+pub use introspection::ServiceInfo;
+
+mod command;
+pub use command::{split_words, Arguments, Command, SplitError};
+
+mod style;
+pub use style::{strip_ansi, Styled};
+
+mod middleware;
+pub use middleware::{Incoming, Middleware, Outcome};
+
+mod audit;
+pub use audit::{AuditRecord, AuditSink, FileAudit};
+
+mod metrics;
+pub use metrics::{Metric, MetricKind, MetricsProvider};
+
+pub mod prometheus;
+pub use prometheus::PrometheusExporter;
+
+mod stats;
+pub use stats::{ConsoleMetrics, LatencyHistogram, ServiceStats};
+
+mod auth;
+pub use auth::AuthContext;
+
+mod event;
+pub use event::{ConsoleEvent, SessionSummary};
 
 mod builder;
-pub use builder::Builder;
+pub use builder::{Builder, NoAddress, NoSubscriptions, Subscribed};
+
+mod config;
+pub use config::{ConsoleConfig, RateLimitConfig};
+
+mod service;
+pub use service::ServiceId;
+
+mod macros;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::macros::all_distinct;
+}
+
+mod history;
+pub use history::{HistoryEntry, HistoryStore, MemoryHistory};
+
+mod context;
+pub use context::{Context, Extensions};
+
+mod push;
+pub use push::{PushHandle, SessionClosed};
 
 mod subscription;
-pub use subscription::{Subscription, SubscriptionError};
+pub use subscription::{
+    ConsoleError, HandlerPanic, HandlerTimeout, ReplyStream, Subscription, SubscriptionError,
+    TypedError, TypedSubscription, HANDLER_FAILED,
+};
 
 fn ensure_newline(mut input: String) -> String {
     if !input.ends_with('\n') {
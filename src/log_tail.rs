@@ -0,0 +1,234 @@
+//! Recent application logs served over the console.
+//!
+//! [LogBuffer::layer] plugs into a `tracing_subscriber` registry and keeps the most recent events,
+//! [LogTail] serves them: `tail [lines]` sends the last lines, `tail -f` keeps streaming new ones.
+
+use crate::context::Context;
+use crate::history::format_utc;
+use crate::subscription::{ReplyStream, Subscription, SubscriptionError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::{self, StreamExt};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Write};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+
+/// Lines sent by `tail` without an explicit count.
+const DEFAULT_LINES: usize = 20;
+
+/// New lines buffered for each `tail -f` follower before it starts missing them.
+const FOLLOW_CAPACITY: usize = 256;
+
+/// Ring buffer of the most recent log lines, shared by a [LogTailLayer] and [LogTail] subscriptions.
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    followers: broadcast::Sender<String>,
+}
+
+impl LogBuffer {
+    /// Keeps the last `capacity` lines.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            followers: broadcast::channel(FOLLOW_CAPACITY).0,
+        }
+    }
+
+    /// A layer recording every event it sees into this buffer.
+    pub fn layer(&self) -> LogTailLayer {
+        LogTailLayer {
+            buffer: self.clone(),
+        }
+    }
+
+    /// Up to `count` most recent lines, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().expect("Log lock is never poisoned");
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, line: String) {
+        {
+            let mut lines = self.lines.lock().expect("Log lock is never poisoned");
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            if self.capacity > 0 {
+                lines.push_back(line.clone());
+            }
+        }
+        // Nobody may be following, which is fine.
+        let _ = self.followers.send(line);
+    }
+}
+
+/// `tracing_subscriber` layer feeding a [LogBuffer], see [LogBuffer::layer].
+pub struct LogTailLayer {
+    buffer: LogBuffer,
+}
+
+impl<S: Subscriber> Layer<S> for LogTailLayer {
+    fn on_event(&self, event: &Event<'_>, _context: LayerContext<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = LineVisitor::default();
+        event.record(&mut line);
+
+        self.buffer.push(format!(
+            "{} {} {}: {}{}",
+            format_utc(SystemTime::now()),
+            metadata.level(),
+            metadata.target(),
+            line.message,
+            line.fields
+        ));
+    }
+}
+
+/// Renders the message of an event followed by its other fields as `name=value`.
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+/// Subscription answering `tail [lines]` with the most recent log lines
+/// and `tail -f` by streaming new lines until the session closes.
+pub struct LogTail {
+    buffer: LogBuffer,
+}
+
+impl LogTail {
+    pub fn new(buffer: LogBuffer) -> Self {
+        Self { buffer }
+    }
+}
+
+#[async_trait]
+impl Subscription for LogTail {
+    async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        Ok(None)
+    }
+
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        let Some(argument) = tail_argument(message) else {
+            return Ok(None);
+        };
+
+        let count = match argument {
+            "" => DEFAULT_LINES,
+            count => match count.parse() {
+                Ok(count) => count,
+                Err(_) => return Ok(Some("Usage: tail [lines] | tail -f".to_owned())),
+            },
+        };
+
+        let lines = self.buffer.recent(count);
+        Ok(Some(if lines.is_empty() {
+            "No logs recorded".to_owned()
+        } else {
+            lines.join("\n")
+        }))
+    }
+
+    async fn weak_handle_stream(
+        &self,
+        context: &Context,
+        message: &str,
+    ) -> Result<Option<ReplyStream>, SubscriptionError> {
+        if tail_argument(message) != Some("-f") {
+            return Ok(self
+                .weak_handle_with(context, message)
+                .await?
+                .map(|reply| stream::once(async move { Bytes::from(reply + "\n") }).boxed()));
+        }
+
+        // Subscribe before reading the recent lines, so that no line falls in between.
+        let followers = self.buffer.followers.subscribe();
+        let recent = stream::iter(self.buffer.recent(DEFAULT_LINES));
+        let new = stream::unfold(followers, |mut followers| async move {
+            let line = match followers.recv().await {
+                Ok(line) => line,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    format!("... {missed} lines skipped")
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((line, followers))
+        });
+
+        Ok(Some(
+            recent
+                .chain(new)
+                .map(|line| Bytes::from(line + "\n"))
+                .boxed(),
+        ))
+    }
+}
+
+/// Argument of a `tail` command, `None` if `message` is not one.
+fn tail_argument(message: &str) -> Option<&str> {
+    let argument = message.strip_prefix("tail")?;
+    if !argument.is_empty() && !argument.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(argument.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogBuffer, LogTail};
+    use crate::Subscription;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test]
+    async fn tail_shows_recent_events() {
+        let buffer = LogBuffer::new(2);
+        let subscriber = tracing_subscriber::registry().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "app", "Starting");
+            tracing::warn!(target: "app", peers = 3, "Degraded");
+            tracing::error!(target: "app", "Disk full");
+        });
+
+        let tail = LogTail::new(buffer);
+        let lines = tail.weak_handle("tail").await.unwrap().unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" WARN app: Degraded peers=3"));
+        assert!(lines[1].ends_with(" ERROR app: Disk full"));
+
+        let last = tail.weak_handle("tail 1").await.unwrap().unwrap();
+        assert!(last.ends_with(" ERROR app: Disk full"));
+        assert_eq!(tail.weak_handle("tailor").await.unwrap(), None);
+    }
+}
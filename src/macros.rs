@@ -0,0 +1,137 @@
+/// Declares a service enum together with the subscriptions handling its services.
+///
+/// Every variant lists its [ServiceId](crate::ServiceId) name, optional aliases accepted by
+/// [ServiceId::from_name](crate::ServiceId::from_name), and the subscription to register for it.
+/// Duplicate names or aliases are rejected at compile time.
+/// The enum gains `subscribe_all`, registering all subscriptions on a [Builder](crate::Builder)
+/// without the runtime [Error::ServiceIdUsed](crate::Error::ServiceIdUsed) check.
+///
+/// The enum derives `serde::{Serialize, Deserialize}`, so `serde` must be a dependency of the caller.
+///
+/// ```
+/// # use async_trait::async_trait;
+/// # use bytes::Bytes;
+/// # use tcp_console::{Subscription, SubscriptionError};
+/// # struct Logger;
+/// # struct Status;
+/// # #[async_trait]
+/// # impl Subscription for Logger {
+/// #     async fn handle(&self, _: Bytes) -> Result<Option<Bytes>, SubscriptionError> { Ok(None) }
+/// #     async fn weak_handle(&self, _: &str) -> Result<Option<String>, SubscriptionError> { Ok(None) }
+/// # }
+/// # #[async_trait]
+/// # impl Subscription for Status {
+/// #     async fn handle(&self, _: Bytes) -> Result<Option<Bytes>, SubscriptionError> { Ok(None) }
+/// #     async fn weak_handle(&self, _: &str) -> Result<Option<String>, SubscriptionError> { Ok(None) }
+/// # }
+/// tcp_console::console_services! {
+///     enum Services {
+///         Logger = "logger" | "log" => Logger,
+///         Status = "status" => Status,
+///     }
+/// }
+///
+/// let console = Services::subscribe_all(tcp_console::Builder::new())
+///     .bind_address("127.0.0.1:3838")
+///     .build();
+/// ```
+///
+/// ```compile_fail
+/// # use async_trait::async_trait;
+/// # use bytes::Bytes;
+/// # use tcp_console::{Subscription, SubscriptionError};
+/// # struct Logger;
+/// # #[async_trait]
+/// # impl Subscription for Logger {
+/// #     async fn handle(&self, _: Bytes) -> Result<Option<Bytes>, SubscriptionError> { Ok(None) }
+/// #     async fn weak_handle(&self, _: &str) -> Result<Option<String>, SubscriptionError> { Ok(None) }
+/// # }
+/// tcp_console::console_services! {
+///     enum Services {
+///         Logger = "logger" => Logger,
+///         Audit = "audit" | "logger" => Logger,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! console_services {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $ty:ident {
+            $($variant:ident = $name:literal $(| $alias:literal)* => $subscription:expr),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ::serde::Serialize, ::serde::Deserialize)]
+        $vis enum $ty {
+            $($variant,)+
+        }
+
+        const _: () = assert!(
+            $crate::__private::all_distinct(&[$($name, $($alias,)*)+]),
+            "Service names and aliases must be unique"
+        );
+
+        impl $crate::ServiceId for $ty {
+            fn name(&self) -> &str {
+                match self {
+                    $($ty::$variant => $name,)+
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name $(| $alias)* => Some($ty::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl $ty {
+            /// Registers the subscriptions of all services.
+            #[allow(dead_code)]
+            $vis fn subscribe_all<A, S>(
+                builder: $crate::Builder<Self, A, S>,
+            ) -> $crate::Builder<Self, A, $crate::Subscribed> {
+                $(
+                    let builder = builder
+                        .subscribe($ty::$variant, $subscription)
+                        .expect("Service ids are distinct enum variants");
+                )+
+                builder
+            }
+        }
+    };
+}
+
+/// Checks whether all `names` are pairwise distinct, usable in constant evaluation.
+pub const fn all_distinct(names: &[&str]) -> bool {
+    let mut i = 0;
+    while i < names.len() {
+        let mut j = i + 1;
+        while j < names.len() {
+            if str_eq(names[i], names[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
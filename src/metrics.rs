@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter};
+
+/// Application-specific numbers reported by the console next to its own,
+/// e.g. by the `stats` command, see [Builder::metrics_provider](crate::Builder::metrics_provider).
+pub trait MetricsProvider: Send + Sync {
+    /// Current values, read whenever metrics are reported, so it must be quick.
+    fn metrics(&self) -> Vec<Metric>;
+}
+
+impl<F> MetricsProvider for F
+where
+    F: Fn() -> Vec<Metric> + Send + Sync,
+{
+    fn metrics(&self) -> Vec<Metric> {
+        self()
+    }
+}
+
+/// A named value of a [MetricsProvider].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    /// Name in the Prometheus style, e.g. `mempool_transactions`.
+    pub name: String,
+    pub value: f64,
+    pub kind: MetricKind,
+    /// What the metric measures, for exporters.
+    pub help: Option<String>,
+    /// Dimensions telling apart metrics of the same name, e.g. `service="exec"`.
+    pub labels: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// A value going up and down, e.g. a queue depth.
+    Gauge,
+    /// A total only ever increasing, e.g. processed blocks.
+    Counter,
+}
+
+impl Metric {
+    pub fn gauge(name: impl Into<String>, value: f64) -> Self {
+        Self::new(name, value, MetricKind::Gauge)
+    }
+
+    pub fn counter(name: impl Into<String>, value: f64) -> Self {
+        Self::new(name, value, MetricKind::Counter)
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    fn new(name: impl Into<String>, value: f64, kind: MetricKind) -> Self {
+        Self {
+            name: name.into(),
+            value,
+            kind,
+            help: None,
+            labels: Vec::new(),
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (index, (name, value)) in self.labels.iter().enumerate() {
+            let separator = if index == 0 { "{" } else { "," };
+            write!(f, "{separator}{name}={value:?}")?;
+        }
+        if !self.labels.is_empty() {
+            write!(f, "}}")?;
+        }
+        write!(f, ": {}", self.value)
+    }
+}
@@ -0,0 +1,46 @@
+//! Hooks around the dispatch of every message, see [Builder::layer](crate::Builder::layer).
+
+use crate::context::Context;
+use crate::subscription::SubscriptionError;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// Cross-cutting behavior, such as audit logging, rate limiting or metrics, applied around
+/// the dispatch of typed and free-form messages to subscriptions, see [Builder::layer](crate::Builder::layer).
+///
+/// Built-in commands, such as `help`, are answered by the console itself and bypass middleware.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Called before `message` is dispatched. Failing refuses the message: no subscription sees it,
+    /// the sender is told why and the [Middleware::after] hooks are not called.
+    async fn before(&self, context: &Context, message: &Incoming) -> Result<(), SubscriptionError> {
+        let _ = (context, message);
+        Ok(())
+    }
+
+    /// Called once `message` is handled, with what came out of it.
+    async fn after(&self, context: &Context, message: &Incoming, outcome: &Outcome) {
+        let _ = (context, message, outcome);
+    }
+}
+
+/// Message dispatched to subscriptions, as seen by [Middleware].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incoming {
+    /// Typed message for the service named `service`.
+    Typed { service: String, payload: Bytes },
+    /// Free-form text, offered to subscriptions according to [WeakDispatch](crate::WeakDispatch).
+    Text(String),
+}
+
+/// What came out of handling an [Incoming] message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// Time subscriptions took to handle the message, hooks excluded.
+    pub elapsed: Duration,
+    /// Whether a subscription answered.
+    pub answered: bool,
+    /// Error of the subscription failing to handle the message, if any.
+    pub error: Option<String>,
+}
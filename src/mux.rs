@@ -0,0 +1,361 @@
+//! Several independent sessions over one connection.
+//!
+//! Every mux frame is a big-endian `u32` channel id, a one-byte [Kind] and a big-endian `u32`
+//! payload length followed by the payload. The side opening channels, e.g. an operator gateway
+//! reached via [Console::dial_multiplexed](crate::Console::dial_multiplexed), uses [Multiplexer];
+//! the console accepts the channels and serves a session over each of them.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// Size of a mux frame header: channel id, kind and payload length.
+const HEADER_LEN: usize = 4 + 1 + 4;
+
+/// Largest payload accepted in a single mux frame.
+const MAX_PAYLOAD: usize = 1 << 20;
+
+/// Capacity of the in-memory pipe between a channel and its session.
+const CHANNEL_BUFFER: usize = 64 * 1024;
+
+/// Payloads queued for a channel whose session is not reading, beyond which the channel is closed.
+const CHANNEL_QUEUE: usize = 64;
+
+/// Frames queued for the connection, and channels queued to be accepted.
+const OUTGOING_QUEUE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Opens a channel.
+    Open = 0,
+    /// Carries bytes of a channel.
+    Data = 1,
+    /// Closes a channel.
+    Close = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MuxFrame {
+    channel: u32,
+    kind: Kind,
+    payload: Bytes,
+}
+
+impl MuxFrame {
+    fn control(channel: u32, kind: Kind) -> Self {
+        Self {
+            channel,
+            kind,
+            payload: Bytes::new(),
+        }
+    }
+}
+
+struct MuxCodec;
+
+impl Decoder for MuxCodec {
+    type Item = MuxFrame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let kind = match src[4] {
+            0 => Kind::Open,
+            1 => Kind::Data,
+            2 => Kind::Close,
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown mux frame kind {kind}"),
+                ))
+            }
+        };
+        let len = u32::from_be_bytes([src[5], src[6], src[7], src[8]]) as usize;
+        if len > MAX_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Mux frame of {len} bytes exceeds the limit of {MAX_PAYLOAD} bytes"),
+            ));
+        }
+        if src.len() < HEADER_LEN + len {
+            src.reserve(HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+
+        let channel = src.get_u32();
+        src.advance(1 + 4);
+        Ok(Some(MuxFrame {
+            channel,
+            kind,
+            payload: src.split_to(len).freeze(),
+        }))
+    }
+}
+
+impl Encoder<MuxFrame> for MuxCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: MuxFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(HEADER_LEN + frame.payload.len());
+        dst.put_u32(frame.channel);
+        dst.put_u8(frame.kind as u8);
+        dst.put_u32(frame.payload.len() as u32);
+        dst.extend_from_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+/// Opens channels over a connection to a console serving multiplexed sessions.
+///
+/// Every channel is an in-memory stream carrying a regular console session,
+/// starting with the welcome message unless the machine handshake skips it.
+pub struct Multiplexer {
+    next_channel: AtomicU32,
+    opens: mpsc::UnboundedSender<(u32, DuplexStream)>,
+    /// Keeps the connection driven while no channel is open.
+    _outgoing: mpsc::Sender<MuxFrame>,
+}
+
+impl Multiplexer {
+    /// Takes over `io`, which is driven in a background task until the connection closes,
+    /// or the multiplexer and all its channels are dropped.
+    pub fn new<T>(io: T) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (outgoing, frames) = mpsc::channel(OUTGOING_QUEUE);
+        let (opens, opened) = mpsc::unbounded_channel();
+        tokio::spawn(drive(io, outgoing.downgrade(), frames, opened, None));
+
+        Self {
+            next_channel: AtomicU32::new(0),
+            opens,
+            _outgoing: outgoing,
+        }
+    }
+
+    /// Opens a new channel. Dropping the returned stream closes the channel.
+    pub fn open(&self) -> io::Result<DuplexStream> {
+        let channel = self.next_channel.fetch_add(1, Ordering::Relaxed);
+        let (local, remote) = tokio::io::duplex(CHANNEL_BUFFER);
+
+        self.opens
+            .send((channel, remote))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closed"))?;
+
+        Ok(local)
+    }
+}
+
+/// Accepts channels opened by the remote end of `io`, until the connection closes.
+/// Channels opened while [OUTGOING_QUEUE] of them wait to be accepted are closed right away.
+pub(crate) fn accept<T>(io: T) -> mpsc::Receiver<DuplexStream>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (outgoing, frames) = mpsc::channel(OUTGOING_QUEUE);
+    let (accepted, channels) = mpsc::channel(OUTGOING_QUEUE);
+    tokio::spawn(async move {
+        // Channels are opened remotely, so keep accepting them even without open channels.
+        let keep_alive = outgoing;
+        let (_, opened) = mpsc::unbounded_channel();
+        drive(io, keep_alive.downgrade(), frames, opened, Some(accepted)).await;
+    });
+
+    channels
+}
+
+/// Routes frames between `io` and the channels until the connection closes
+/// or no one is left to send frames.
+async fn drive<T>(
+    io: T,
+    outgoing: mpsc::WeakSender<MuxFrame>,
+    mut frames: mpsc::Receiver<MuxFrame>,
+    mut opened: mpsc::UnboundedReceiver<(u32, DuplexStream)>,
+    accepted: Option<mpsc::Sender<DuplexStream>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut framed = Framed::new(io, MuxCodec);
+    // Queues of the channels, fed with bytes from the remote end. Dropping one ends its channel.
+    let mut channels: HashMap<u32, mpsc::Sender<Bytes>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            frame = framed.next() => {
+                let Some(Ok(frame)) = frame else {
+                    break;
+                };
+
+                match frame.kind {
+                    Kind::Open => {
+                        let (Some(accepted), Some(outgoing)) = (&accepted, outgoing.upgrade()) else {
+                            continue;
+                        };
+
+                        let (local, remote) = tokio::io::duplex(CHANNEL_BUFFER);
+                        channels.insert(frame.channel, attach(frame.channel, remote, outgoing));
+                        // A channel which is not accepted closes as its end is dropped.
+                        let _ = accepted.try_send(local);
+                    }
+                    Kind::Data => {
+                        if let Some(queue) = channels.get(&frame.channel) {
+                            // Waiting for a session which does not keep up would stall all others.
+                            if queue.try_send(frame.payload).is_err() {
+                                channels.remove(&frame.channel);
+                            }
+                        }
+                    }
+                    Kind::Close => {
+                        channels.remove(&frame.channel);
+                    }
+                }
+            }
+            Some((channel, remote)) = opened.recv() => {
+                let Some(outgoing) = outgoing.upgrade() else {
+                    break;
+                };
+                channels.insert(channel, attach(channel, remote, outgoing));
+                if framed.send(MuxFrame::control(channel, Kind::Open)).await.is_err() {
+                    break;
+                }
+            }
+            frame = frames.recv() => match frame {
+                None => break,
+                Some(frame) => {
+                    if frame.kind == Kind::Close {
+                        channels.remove(&frame.channel);
+                    }
+                    if framed.send(frame).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Forwards bytes written to the channel to the remote end, and bytes queued from
+/// the remote end to the channel. Returns the queue, which ends the channel once dropped.
+fn attach(
+    channel: u32,
+    pipe: DuplexStream,
+    outgoing: mpsc::Sender<MuxFrame>,
+) -> mpsc::Sender<Bytes> {
+    let (mut reader, mut writer) = tokio::io::split(pipe);
+    let (queue, mut incoming) = mpsc::channel::<Bytes>(CHANNEL_QUEUE);
+
+    tokio::spawn(async move {
+        while let Some(bytes) = incoming.recv().await {
+            if writer.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+        // Signals the end of the stream to the reader of the channel.
+        let _ = writer.shutdown().await;
+    });
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 8 * 1024];
+        loop {
+            match reader.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let frame = MuxFrame {
+                        channel,
+                        kind: Kind::Data,
+                        payload: Bytes::copy_from_slice(&buffer[..n]),
+                    };
+                    if outgoing.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = outgoing.send(MuxFrame::control(channel, Kind::Close)).await;
+    });
+
+    queue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept, Kind, Multiplexer, MuxCodec, MuxFrame};
+    use bytes::{Bytes, BytesMut};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[tokio::test]
+    async fn stalled_channels_do_not_block_others() -> anyhow::Result<()> {
+        let (gateway, console) = tokio::io::duplex(64 * 1024);
+        let multiplexer = Multiplexer::new(gateway);
+        let mut channels = accept(console);
+
+        let mut stalled = multiplexer.open()?;
+        let mut other = multiplexer.open()?;
+        // The session of the first channel never reads.
+        let _unread = channels.recv().await.expect("Channel is accepted");
+        let mut served = channels.recv().await.expect("Channel is accepted");
+
+        tokio::spawn(async move {
+            let chunk = vec![b'x'; 64 * 1024];
+            while stalled.write_all(&chunk).await.is_ok() {}
+        });
+        time::sleep(Duration::from_millis(50)).await;
+
+        other.write_all(b"status\n").await?;
+        let mut line = [0; 7];
+        time::timeout(Duration::from_secs(5), served.read_exact(&mut line)).await??;
+        assert_eq!(&line, b"status\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let frames = [
+            MuxFrame::control(7, Kind::Open),
+            MuxFrame {
+                channel: 7,
+                kind: Kind::Data,
+                payload: Bytes::from_static(b"status\n"),
+            },
+            MuxFrame::control(7, Kind::Close),
+        ];
+
+        let mut buffer = BytesMut::new();
+        for frame in frames.clone() {
+            MuxCodec.encode(frame, &mut buffer).unwrap();
+        }
+
+        // Frames are only decoded once complete.
+        let mut partial = buffer.split_to(12);
+        assert_eq!(
+            MuxCodec.decode(&mut partial).unwrap(),
+            Some(frames[0].clone())
+        );
+        assert_eq!(MuxCodec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buffer);
+
+        assert_eq!(
+            MuxCodec.decode(&mut partial).unwrap(),
+            Some(frames[1].clone())
+        );
+        assert_eq!(
+            MuxCodec.decode(&mut partial).unwrap(),
+            Some(frames[2].clone())
+        );
+        assert!(partial.is_empty());
+    }
+}
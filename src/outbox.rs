@@ -0,0 +1,191 @@
+//! Frames queued for writing to a session's connection, see [Builder::write_queue](crate::Builder::write_queue).
+
+use crate::codec::{Frame, FrameCodec};
+use crate::targets::SESSION;
+use futures_util::SinkExt;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::io::AsyncWrite;
+use tokio::sync::Notify;
+use tokio_util::codec::FramedWrite;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// Frames queued per session by default.
+pub(crate) const WRITE_QUEUE_CAPACITY: usize = 64;
+
+/// What a session does when its write queue is full, i.e. when the client reads slower than
+/// subscriptions produce output, see [Builder::write_queue](crate::Builder::write_queue).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowConsumer {
+    /// Waits for room in the queue, holding up the subscription producing the output.
+    #[default]
+    Block,
+    /// Drops the oldest queued frame to make room, which may be a reply.
+    DropOldest,
+    /// Closes the session.
+    Disconnect,
+}
+
+/// Why a frame was not queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rejected {
+    /// The queue no longer accepts frames, e.g. writing to the connection failed.
+    Closed,
+    /// The queue is full and [SlowConsumer::Disconnect] applies, the queue is closed now.
+    Overflow,
+}
+
+/// Bounded queue between a session and the task writing to its connection.
+pub(crate) struct Outbox {
+    state: Mutex<State>,
+    capacity: usize,
+    policy: SlowConsumer,
+    /// Signalled when a frame is queued or the queue is closed.
+    readable: Notify,
+    /// Signalled when a frame is taken out of the queue.
+    writable: Notify,
+    /// Cancelled when the connection is to be dropped without writing what is queued.
+    aborted: CancellationToken,
+}
+
+#[derive(Default)]
+struct State {
+    frames: VecDeque<Frame>,
+    closed: bool,
+}
+
+impl Outbox {
+    pub(crate) fn new(capacity: usize, policy: SlowConsumer) -> Self {
+        Self {
+            state: Mutex::default(),
+            capacity: capacity.max(1),
+            policy,
+            readable: Notify::new(),
+            writable: Notify::new(),
+            aborted: CancellationToken::new(),
+        }
+    }
+
+    /// Queues `frame`, applying the [SlowConsumer] policy if the queue is full.
+    pub(crate) async fn push(&self, frame: Frame) -> Result<(), Rejected> {
+        let mut frame = Some(frame);
+        loop {
+            {
+                let mut state = self.state.lock().expect("Outbox lock is never poisoned");
+                if state.closed {
+                    return Err(Rejected::Closed);
+                }
+                if state.frames.len() >= self.capacity {
+                    match self.policy {
+                        SlowConsumer::Block => {}
+                        SlowConsumer::DropOldest => {
+                            state.frames.pop_front();
+                        }
+                        SlowConsumer::Disconnect => {
+                            drop(state);
+                            self.abort();
+                            return Err(Rejected::Overflow);
+                        }
+                    }
+                }
+                if state.frames.len() < self.capacity {
+                    state.frames.extend(frame.take());
+                    self.readable.notify_one();
+                    return Ok(());
+                }
+            }
+            // The writer is the only one taking frames, so a single permit is enough to wake the session.
+            self.writable.notified().await;
+        }
+    }
+
+    /// Takes the next frame to write, `None` once the queue is closed and empty.
+    async fn pop(&self) -> Option<Frame> {
+        loop {
+            {
+                let mut state = self.state.lock().expect("Outbox lock is never poisoned");
+                if let Some(frame) = state.frames.pop_front() {
+                    self.writable.notify_one();
+                    return Some(frame);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    /// Stops accepting frames, those already queued are still written.
+    pub(crate) fn close(&self) {
+        self.state
+            .lock()
+            .expect("Outbox lock is never poisoned")
+            .closed = true;
+        self.readable.notify_one();
+    }
+
+    /// Stops accepting frames and drops those queued, interrupting a write in progress.
+    pub(crate) fn abort(&self) {
+        let mut state = self.state.lock().expect("Outbox lock is never poisoned");
+        state.closed = true;
+        state.frames.clear();
+        self.aborted.cancel();
+        self.readable.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state
+            .lock()
+            .expect("Outbox lock is never poisoned")
+            .closed
+    }
+
+    /// Writes queued frames to `sink` until the queue is closed and empty, or aborted.
+    pub(crate) async fn write_to<W: AsyncWrite + Unpin>(
+        &self,
+        mut sink: FramedWrite<W, FrameCodec>,
+    ) {
+        while let Some(frame) = self.pop().await {
+            let result = tokio::select! {
+                result = sink.send(frame) => result,
+                _ = self.aborted.cancelled() => return,
+            };
+            if let Err(err) = result {
+                debug!(target: SESSION, "Failed to write frame: {err}");
+                self.abort();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outbox, Rejected, SlowConsumer};
+    use crate::codec::Frame;
+    use bytes::Bytes;
+
+    fn text(text: &'static str) -> Frame {
+        Frame::Text(Bytes::from_static(text.as_bytes()))
+    }
+
+    #[tokio::test]
+    async fn full_queues_follow_the_policy() {
+        let outbox = Outbox::new(2, SlowConsumer::DropOldest);
+        for frame in ["one", "two", "three"] {
+            outbox.push(text(frame)).await.unwrap();
+        }
+        outbox.close();
+        assert_eq!(outbox.pop().await, Some(text("two")));
+        assert_eq!(outbox.pop().await, Some(text("three")));
+        assert_eq!(outbox.pop().await, None);
+        assert_eq!(outbox.push(text("four")).await, Err(Rejected::Closed));
+
+        let outbox = Outbox::new(1, SlowConsumer::Disconnect);
+        outbox.push(text("one")).await.unwrap();
+        assert_eq!(outbox.push(text("two")).await, Err(Rejected::Overflow));
+        assert_eq!(outbox.pop().await, None);
+    }
+}
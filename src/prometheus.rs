@@ -0,0 +1,133 @@
+//! Metrics in the Prometheus text exposition format.
+
+use crate::codec::encode_payload;
+use crate::handle::ConsoleHandle;
+use crate::metrics::{Metric, MetricKind};
+use crate::subscription::{Subscription, SubscriptionError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Text command the exporter answers.
+const METRICS_COMMAND: &str = "metrics";
+
+/// Subscription answering the `metrics` text command, and any typed message, with the
+/// [Console::metrics](crate::Console::metrics) and the metrics of every
+/// [Builder::metrics_provider](crate::Builder::metrics_provider) in the Prometheus text format.
+/// Typed replies carry the text as a BCS-encoded `String`.
+///
+/// It needs a handle of the console, so it is registered once the console is built:
+/// `console.subscribe(Services::Metrics, PrometheusExporter::new(console.handle()))`.
+pub struct PrometheusExporter<Services> {
+    handle: ConsoleHandle<Services>,
+}
+
+impl<Services> PrometheusExporter<Services> {
+    pub fn new(handle: ConsoleHandle<Services>) -> Self {
+        Self { handle }
+    }
+
+    fn render(&self) -> String {
+        let mut metrics = self.handle.metrics().to_metrics();
+        metrics.extend(self.handle.host_metrics());
+        render(&metrics)
+    }
+}
+
+#[async_trait]
+impl<Services> Subscription for PrometheusExporter<Services>
+where
+    Services: Send + Sync + 'static,
+{
+    async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        Ok(Some(encode_payload(&self.render())?))
+    }
+
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        Ok((message.trim() == METRICS_COMMAND).then(|| self.render()))
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some("`metrics` reports console and host metrics in the Prometheus format")
+    }
+}
+
+/// Renders `metrics` in the Prometheus text format, with `HELP` and `TYPE` lines
+/// before the first sample of every name.
+pub fn render(metrics: &[Metric]) -> String {
+    let mut text = String::new();
+    let mut described = HashSet::new();
+    for metric in metrics {
+        if described.insert(metric.name.as_str()) {
+            if let Some(help) = &metric.help {
+                let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+                let _ = writeln!(text, "# HELP {} {help}", metric.name);
+            }
+            let kind = match metric.kind {
+                MetricKind::Gauge => "gauge",
+                MetricKind::Counter => "counter",
+            };
+            let _ = writeln!(text, "# TYPE {} {kind}", metric.name);
+        }
+
+        text.push_str(&metric.name);
+        if !metric.labels.is_empty() {
+            let labels = metric
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+                .collect::<Vec<_>>();
+            let _ = write!(text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(text, " {}", format_value(metric.value));
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        value if value.is_nan() => "NaN".to_owned(),
+        f64::INFINITY => "+Inf".to_owned(),
+        f64::NEG_INFINITY => "-Inf".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::Metric;
+
+    #[test]
+    fn metrics_render_in_text_format() {
+        let metrics = [
+            Metric::counter("calls", 3.0)
+                .with_help("Calls")
+                .with_label("service", "exec"),
+            Metric::counter("calls", 1.0)
+                .with_help("Calls")
+                .with_label("service", "say \"hi\""),
+            Metric::gauge("load", 0.5),
+            Metric::gauge("lag", f64::INFINITY),
+        ];
+        assert_eq!(
+            render(&metrics),
+            "# HELP calls Calls\n\
+             # TYPE calls counter\n\
+             calls{service=\"exec\"} 3\n\
+             calls{service=\"say \\\"hi\\\"\"} 1\n\
+             # TYPE load gauge\n\
+             load 0.5\n\
+             # TYPE lag gauge\n\
+             lag +Inf\n"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use crate::codec::Frame;
+use crate::console::text_frame;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// Texts queued per session before [PushHandle::push] waits for the session to send them.
+pub(crate) const PUSH_CAPACITY: usize = 64;
+
+/// Sends texts to a session on the console's own initiative, e.g. progress updates or alerts
+/// from a task spawned by a subscription, see [Context::push_handle](crate::Context::push_handle).
+#[derive(Debug, Clone)]
+pub struct PushHandle {
+    pub(crate) frames: mpsc::Sender<Frame>,
+}
+
+/// The session a [PushHandle] belongs to has been closed.
+#[derive(Debug, Error)]
+#[error("Session is closed")]
+pub struct SessionClosed;
+
+impl PushHandle {
+    pub(crate) fn new(frames: mpsc::Sender<Frame>) -> Self {
+        Self { frames }
+    }
+
+    /// Queues `text` to be sent to the session as is, waiting while the queue is full.
+    pub async fn push(&self, text: impl Into<String>) -> Result<(), SessionClosed> {
+        self.frames
+            .send(text_frame(&text.into()))
+            .await
+            .map_err(|_| SessionClosed)
+    }
+
+    /// Whether the session has been closed, so pushing is pointless.
+    pub fn is_closed(&self) -> bool {
+        self.frames.is_closed()
+    }
+}
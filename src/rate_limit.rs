@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+/// Number of tracked peers above which the least recently seen ones are forgotten.
+const MAX_PEERS: usize = 1024;
+
+/// Per-peer token bucket limiting how often a peer may open connections.
+pub(crate) struct RateLimiter {
+    /// Maximum number of connections a peer may open in a burst.
+    burst: f64,
+    /// Tokens regained per second.
+    refill: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allows `burst` connections per `period` from every peer.
+    pub(crate) fn new(burst: u32, period: Duration) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            burst,
+            refill: burst / period.as_secs_f64().max(f64::EPSILON),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token for a connection attempt from `ip`, returns `false` if the peer exhausted its budget.
+    pub(crate) fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let peer = peer(ip);
+
+        if self.buckets.len() >= MAX_PEERS && !self.buckets.contains_key(&peer) {
+            let (burst, refill) = (self.burst, self.refill);
+            self.buckets
+                .retain(|_, bucket| bucket.refilled(now, burst, refill) < burst);
+
+            // Peers cycling through addresses could otherwise grow the map without bound.
+            if self.buckets.len() >= MAX_PEERS {
+                let oldest = self
+                    .buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(peer, _)| *peer);
+                if let Some(oldest) = oldest {
+                    self.buckets.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(now, self.burst, self.refill);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Key of the bucket of `ip`: IPv6 peers get a whole /64, which is what a single host is usually assigned.
+fn peer(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(Ipv6Addr::from(ip.to_bits() & !u128::from(u64::MAX))),
+        },
+        ip => ip,
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, burst: f64, refill: f64) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * refill).min(burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, MAX_PEERS};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_are_per_peer() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(3600));
+        let noisy = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let quiet = IpAddr::V4(Ipv4Addr::new(10, 0, 3, 1));
+
+        assert!(limiter.allow(noisy));
+        assert!(limiter.allow(noisy));
+        assert!(!limiter.allow(noisy));
+        assert!(limiter.allow(quiet));
+    }
+
+    #[test]
+    fn ipv6_peers_share_a_bucket_per_prefix() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(3600));
+        let host = |suffix| IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, suffix));
+
+        assert!(limiter.allow(host(1)));
+        assert!(!limiter.allow(host(2)));
+        assert!(limiter.allow(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 2, 0, 0, 0, 1))));
+    }
+
+    #[test]
+    fn peers_are_bounded() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(3600));
+        let noisy = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(limiter.allow(noisy));
+
+        for n in 0..2 * MAX_PEERS as u32 {
+            limiter.allow(IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n)));
+            assert!(limiter.buckets.len() <= MAX_PEERS);
+        }
+        // The least recently seen peer was forgotten first.
+        assert!(limiter.allow(noisy));
+    }
+}
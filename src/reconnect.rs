@@ -0,0 +1,217 @@
+use crate::targets::CLIENT;
+use crate::Client;
+use bytes::Bytes;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::net::ToSocketAddrs;
+use tokio::time;
+use tracing::{debug, warn};
+
+/// Exponential backoff policy used to (re-)establish connections to [Console](crate::Console).
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    factor: u32,
+    max_attempts: Option<usize>,
+}
+
+impl Backoff {
+    /// Creates a policy starting at `initial` delay and doubling it up to `max`, retrying forever.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            factor: 2,
+            max_attempts: None,
+        }
+    }
+
+    /// Sets the factor the delay is multiplied by after every failed attempt.
+    pub fn factor(mut self, factor: u32) -> Self {
+        self.factor = factor.max(1);
+        self
+    }
+
+    /// Gives up after `attempts` failed connection attempts.
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Whether to stop after `failed` failed attempts.
+    pub(crate) fn gives_up(&self, failed: usize) -> bool {
+        self.max_attempts.is_some_and(|max| failed >= max)
+    }
+
+    /// Delay to wait before the attempt following `failed` failed ones.
+    pub(crate) fn delay(&self, failed: usize) -> Duration {
+        let factor = self
+            .factor
+            .saturating_pow(u32::try_from(failed.saturating_sub(1)).unwrap_or(u32::MAX));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), Duration::from_secs(10))
+    }
+}
+
+impl Client {
+    /// Connects to [Console](crate::Console), retrying according to `backoff`.
+    pub async fn connect_with_retry<A: ToSocketAddrs + Clone>(
+        address: A,
+        backoff: &Backoff,
+    ) -> anyhow::Result<Self> {
+        let mut failed = 0;
+        loop {
+            match Client::new(address.clone(), None).await {
+                Ok(client) => return Ok(client),
+                Err(err) => {
+                    failed += 1;
+                    if backoff.gives_up(failed) {
+                        return Err(err.context(format!("Giving up after {failed} attempts")));
+                    }
+
+                    let delay = backoff.delay(failed);
+                    warn!(target: CLIENT, "Failed to connect to console: {err}. Retrying in {delay:?}");
+                    time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+/// A [Client] which transparently re-establishes its connection,
+/// e.g., when the application hosting [Console](crate::Console) restarts.
+///
+/// The connection is (re-)opened lazily on the next call after a failure.
+/// Sends failing on a broken connection are retried once over a fresh connection.
+pub struct ReconnectingClient<A> {
+    address: A,
+    backoff: Backoff,
+    client: Option<Client>,
+}
+
+impl<A: ToSocketAddrs + Clone> ReconnectingClient<A> {
+    /// Creates a client, the connection is established on the first call.
+    pub fn new(address: A, backoff: Backoff) -> Self {
+        Self {
+            address,
+            backoff,
+            client: None,
+        }
+    }
+
+    /// See [Client::send].
+    pub async fn send<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<()> {
+        if let Err(err) = self.client().await?.send(&service_id, message).await {
+            self.disconnect(&err);
+            self.client().await?.send(&service_id, message).await?;
+        }
+
+        Ok(())
+    }
+
+    /// See [Client::send_recv].
+    pub async fn send_recv<S: Serialize, M: Serialize>(
+        &mut self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let result = self.client().await?.send_recv(service_id, message).await;
+        result.inspect_err(|err| self.disconnect(err))
+    }
+
+    /// See [Client::weak_send].
+    pub async fn weak_send(&mut self, message: &str) -> anyhow::Result<()> {
+        if let Err(err) = self.client().await?.weak_send(message).await {
+            self.disconnect(&err);
+            self.client().await?.weak_send(message).await?;
+        }
+
+        Ok(())
+    }
+
+    /// See [Client::weak_read].
+    pub async fn weak_read(&mut self) -> anyhow::Result<String> {
+        let result = self.client().await?.weak_read().await;
+        result.inspect_err(|err| self.disconnect(err))
+    }
+
+    /// Returns the current connection, establishing it if necessary.
+    async fn client(&mut self) -> anyhow::Result<&mut Client> {
+        if self.client.is_none() {
+            let client = Client::connect_with_retry(self.address.clone(), &self.backoff).await?;
+            debug!(target: CLIENT, "Connection to console (re-)established");
+            self.client = Some(client);
+        }
+
+        Ok(self
+            .client
+            .as_mut()
+            .expect("Connection must be established"))
+    }
+
+    fn disconnect(&mut self, err: &anyhow::Error) {
+        warn!(target: CLIENT, "Connection to console is lost: {err}");
+        self.client = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::TestService;
+    use crate::{Backoff, ReconnectingClient, Subscription, SubscriptionError};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn reconnects_after_console_restart() -> anyhow::Result<()> {
+        // A free port, the console binds it again after every restart.
+        let address = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+        let mut client = ReconnectingClient::new(address, backoff);
+
+        for generation in ["first", "second"] {
+            let mut console = crate::Builder::new()
+                .bind_address(address)
+                .subscribe(TestService::One, Generation(generation))?
+                .build()?;
+            console.spawn().await?;
+
+            client.weak_send("generation").await?;
+            assert_eq!(client.weak_read().await?, generation);
+
+            console.stop();
+            time::sleep(Duration::from_millis(100)).await;
+
+            // The first read after the restart notices the connection is gone.
+            assert!(client.weak_read().await.is_err());
+        }
+
+        Ok(())
+    }
+
+    struct Generation(&'static str);
+
+    #[async_trait]
+    impl Subscription for Generation {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(self.0.to_owned()))
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Sessions currently open, see [Console::sessions](crate::Console::sessions).
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio_util::sync::CancellationToken;
+
+/// Snapshot of an open session, see [Console::sessions](crate::Console::sessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Identifier to [Console::kick](crate::Console::kick) the session with,
+    /// the same as [Context::session_id](crate::Context::session_id).
+    pub session_id: u64,
+    pub peer: SocketAddr,
+    pub connected_at: SystemTime,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Display for SessionInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} {}, {} bytes in, {} bytes out",
+            self.session_id, self.peer, self.bytes_in, self.bytes_out
+        )
+    }
+}
+
+/// State of an open session shared with the [Registry].
+pub(crate) struct Registered {
+    peer: SocketAddr,
+    connected_at: SystemTime,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    kicked: AtomicBool,
+    /// Stops the session, cancelled when the console stops or the session is kicked.
+    stop: CancellationToken,
+}
+
+impl Registered {
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Whether the session is stopping because it was kicked rather than because the console stops.
+    pub(crate) fn is_kicked(&self) -> bool {
+        self.kicked.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stop(&self) -> &CancellationToken {
+        &self.stop
+    }
+}
+
+/// Open sessions by session id.
+#[derive(Default)]
+pub(crate) struct Registry {
+    sessions: Mutex<HashMap<u64, Arc<Registered>>>,
+}
+
+impl Registry {
+    /// Registers a session, stopped along with `stop`.
+    pub(crate) fn register(
+        &self,
+        session_id: u64,
+        peer: SocketAddr,
+        stop: &CancellationToken,
+    ) -> Arc<Registered> {
+        let registered = Arc::new(Registered {
+            peer,
+            connected_at: SystemTime::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            kicked: AtomicBool::new(false),
+            stop: stop.child_token(),
+        });
+        self.lock().insert(session_id, registered.clone());
+        registered
+    }
+
+    pub(crate) fn remove(&self, session_id: u64) {
+        self.lock().remove(&session_id);
+    }
+
+    /// Open sessions, oldest first.
+    pub(crate) fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions = self
+            .lock()
+            .iter()
+            .map(|(&session_id, registered)| SessionInfo {
+                session_id,
+                peer: registered.peer,
+                connected_at: registered.connected_at,
+                bytes_in: registered.bytes_in(),
+                bytes_out: registered.bytes_out(),
+            })
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.session_id);
+        sessions
+    }
+
+    /// Stops the session `session_id`, returns whether it was open.
+    pub(crate) fn kick(&self, session_id: u64) -> bool {
+        let Some(registered) = self.lock().get(&session_id).cloned() else {
+            return false;
+        };
+        registered.kicked.store(true, Ordering::Relaxed);
+        registered.stop.cancel();
+        true
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<Registered>>> {
+        self.sessions
+            .lock()
+            .expect("Registry lock is never poisoned")
+    }
+}
@@ -0,0 +1,41 @@
+//! Introspection of the process embedding the console, see [Builder::runtime_command](crate::Builder::runtime_command).
+
+use crate::welcome::format_uptime;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+/// Text command reporting on the runtime.
+pub(crate) const RUNTIME_COMMAND: &str = "runtime";
+
+/// Answer to [RUNTIME_COMMAND] of a console up for `uptime` with `sessions` open sessions.
+pub(crate) fn report(uptime: Duration, sessions: usize) -> String {
+    let metrics = Handle::current().metrics();
+    let mut report = format!(
+        "Uptime: {}\nSessions: {sessions}\nWorkers: {}\nAlive tasks: {}\nGlobal queue depth: {}",
+        format_uptime(uptime),
+        metrics.num_workers(),
+        metrics.num_alive_tasks(),
+        metrics.global_queue_depth()
+    );
+    if let Some(bytes) = resident_memory() {
+        report.push_str(&format!(
+            "\nResident memory: {:.1} MiB",
+            bytes as f64 / (1 << 20) as f64
+        ));
+    }
+    report
+}
+
+/// Resident set size of the process in bytes, on platforms exposing `/proc`.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
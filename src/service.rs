@@ -0,0 +1,105 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Identifier of a service a [Subscription](crate::Subscription) is registered for.
+///
+/// Every service id has a stable string name, used wherever services are addressed by humans:
+/// free-form routing, help output, logs and error reports.
+/// For enums, implement this trait with [service_id!](crate::service_id).
+///
+/// Names may be namespaced by subsystem with dots, e.g. `network.peers` and `storage.compaction`:
+/// `help` lists services grouped by namespace and [WeakDispatch::Prefix](crate::WeakDispatch::Prefix)
+/// routes both `network.peers` and `network peers` to the former.
+pub trait ServiceId: Eq + Hash + Debug + Sized {
+    /// Stable name of the service id.
+    fn name(&self) -> &str;
+
+    /// Inverse of [ServiceId::name].
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Namespace of the service, its name up to the last dot, e.g. `network` for `network.peers`.
+    fn namespace(&self) -> Option<&str> {
+        self.name().rsplit_once('.').map(|(namespace, _)| namespace)
+    }
+}
+
+/// `text` with the service name spelled with dots, if its leading words address the service `name`
+/// either as is or with its namespace segments as separate words, e.g. `network peers list`.
+pub(crate) fn addressed(name: &str, text: &str) -> Option<String> {
+    let mut rest = text.trim_start();
+    for (index, segment) in name.split('.').enumerate() {
+        if index > 0 {
+            // Segments are joined either by the dot itself or by whitespace.
+            rest = match rest.strip_prefix('.') {
+                Some(rest) => rest,
+                None if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+                None => return None,
+            };
+        }
+        rest = rest.strip_prefix(segment)?;
+    }
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(format!("{name}{rest}"))
+}
+
+impl ServiceId for String {
+    fn name(&self) -> &str {
+        self
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(name.to_owned())
+    }
+}
+
+/// Implements [ServiceId] for a fieldless enum, mapping every variant to its name.
+///
+/// ```
+/// #[derive(Debug, PartialEq, Eq, Hash)]
+/// enum Services {
+///     Logger,
+///     Status,
+/// }
+///
+/// tcp_console::service_id!(Services {
+///     Logger => "logger",
+///     Status => "status",
+/// });
+/// ```
+#[macro_export]
+macro_rules! service_id {
+    ($ty:ident { $($variant:ident => $name:literal),* $(,)? }) => {
+        impl $crate::ServiceId for $ty {
+            fn name(&self) -> &str {
+                match self {
+                    $($ty::$variant => $name,)*
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some($ty::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+/// Service ids used throughout the crate's tests.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub(crate) enum TestService {
+    One,
+    Two,
+    Three,
+}
+
+#[cfg(test)]
+service_id!(TestService {
+    One => "one",
+    Two => "two",
+    Three => "three",
+});
@@ -0,0 +1,1849 @@
+use crate::auth::{parse_auth, tokens_match, AUTH_FAILED, AUTH_OK, AUTH_REQUIRED};
+use crate::batch::{self, Envelope};
+use crate::chunked::{self, CHUNK_SIZE};
+use crate::codec::{is_machine_hello, is_wakeup, Frame, FrameCodec, Oversized};
+use crate::compression::{self, Compressor};
+use crate::console::{
+    text_frame, Inner, Message, Mode, Reply, ReplyError, WeakDispatch, MAINTENANCE, STARTING_UP,
+};
+use crate::context::Context;
+use crate::control::{Control, CHUNKED_VERSION, HEARTBEAT_VERSION, PROTOCOL_VERSIONS};
+use crate::delta::{self, DELTA_DISABLED, DELTA_ENABLED, DELTA_OFF, DELTA_ON};
+use crate::ensure_newline;
+use crate::event::{ConsoleEvent, SessionSummary};
+use crate::history::HistoryEntry;
+use crate::introspection::{self, ServiceInfo, SERVICES_COMMAND};
+use crate::middleware::{Incoming, Outcome};
+use crate::outbox::{Outbox, Rejected, SlowConsumer, WRITE_QUEUE_CAPACITY};
+use crate::push::{PushHandle, PUSH_CAPACITY};
+use crate::registry::Registered;
+use crate::rng::Rng;
+use crate::runtime::{self, RUNTIME_COMMAND};
+use crate::service::{self, ServiceId};
+use crate::style::{strip_ansi_bytes, COLOR_COMMAND, ESC};
+use crate::subscription::{
+    ConsoleError, HandlerPanic, HandlerTimeout, ReplyStream, SubscriptionError, Subscriptions,
+    HANDLER_FAILED,
+};
+use crate::targets::{DISPATCH, SESSION};
+use crate::timezone::{TimeZone, TZ_COMMAND};
+use crate::topics::{SUBSCRIBE_COMMAND, UNSUBSCRIBE_COMMAND};
+use crate::welcome;
+use bytes::Bytes;
+use futures_util::future::join_all;
+use futures_util::{FutureExt, StreamExt};
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf};
+use tokio::sync::mpsc;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+/// Per-message debug log, silenced by [Builder::quiet_messages](crate::Builder::quiet_messages).
+macro_rules! message_debug {
+    ($session:expr, $($arg:tt)+) => {
+        if !$session.inner.settings.quiet_messages {
+            debug!(target: DISPATCH, $($arg)+);
+        }
+    };
+}
+
+/// A remote console session over a byte stream `T`,
+/// a TCP connection or a channel of a multiplexed one.
+pub(crate) struct Session<Services, T> {
+    inner: Arc<Inner<Services>>,
+    stream: FramedRead<ReadHalf<T>, FrameCodec>,
+    /// Frames to write to the connection.
+    outbox: Arc<Outbox>,
+    peer: SocketAddr,
+    context: Context,
+    /// Frames pushed through the [PushHandle] of the session.
+    pushed: mpsc::Receiver<Frame>,
+    /// Shuffles the order free-form messages are offered to subscriptions in.
+    rng: Rng,
+    started: Instant,
+    /// When the last frame was received, sessions idle since then are closed.
+    last_received: Instant,
+    /// When the last frame was sent, sessions quiet since then get a keep-alive.
+    last_sent: Instant,
+    /// When the last heartbeat was sent, see [Builder::heartbeat](crate::Builder::heartbeat).
+    last_ping: Instant,
+    /// Heartbeats sent since the last frame was received.
+    missed_pongs: u32,
+    /// Compressor of typed frames negotiated with the client, see [Builder::compressor](crate::Builder::compressor).
+    compressor: Option<Arc<dyn Compressor>>,
+    /// Traffic of the session and whether it was kicked, shared with [Console::sessions](crate::Console::sessions).
+    registered: Arc<Registered>,
+    commands: u64,
+    errors: u64,
+    /// Whether the remote end is a programmatic client, see [MACHINE_HELLO](crate::codec::MACHINE_HELLO).
+    machine: bool,
+    /// Zone timestamps of built-in commands are rendered in, see [TZ_COMMAND].
+    time_zone: TimeZone,
+    /// Topics the session is subscribed to.
+    topics: HashSet<String>,
+    /// Whether ANSI escapes are kept in text sent to an interactive session, see [COLOR_COMMAND].
+    colors: bool,
+    /// Whether text replies are sent as deltas, enabled by the remote end with [DELTA_ON].
+    deltas: bool,
+    /// Whether the session runs a piped runbook, enabled with [SCRIPT_COMMAND].
+    script: bool,
+    /// Frame which interrupted a streamed response, dispatched next.
+    interrupted: Option<Frame>,
+    /// Last text frame sent since enabling deltas, the base of the next delta.
+    last_text: Option<String>,
+    /// Typed requests handled in their own tasks, see [Builder::concurrent_requests](crate::Builder::concurrent_requests).
+    running: JoinSet<Handled<Services>>,
+}
+
+/// Outcome of a typed request handled in its own task.
+struct Handled<Services> {
+    id: Option<u64>,
+    service_id: Services,
+    incoming: Incoming,
+    started: Instant,
+    result: Result<Option<Bytes>, SubscriptionError>,
+}
+
+impl<Services, T> Session<Services, T>
+where
+    Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Handles a remote console session with `peer` until it is closed by either side.
+    pub(crate) async fn run(
+        stream: T,
+        peer: SocketAddr,
+        inner: Arc<Inner<Services>>,
+        stop: CancellationToken,
+    ) {
+        let session_id = inner.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let fields = inner.settings.span_fields;
+        let span = info_span!(
+            target: SESSION,
+            "session",
+            session_id = field::Empty,
+            peer = field::Empty,
+            identity = field::Empty
+        );
+        if fields.session_id {
+            span.record("session_id", session_id);
+        }
+        if fields.peer {
+            span.record("peer", field::display(peer));
+        }
+        Self::open(stream, peer, session_id, inner, stop)
+            .instrument(span)
+            .await;
+    }
+
+    /// Body of [Session::run], within the span of the session.
+    async fn open(
+        stream: T,
+        peer: SocketAddr,
+        session_id: u64,
+        inner: Arc<Inner<Services>>,
+        stop: CancellationToken,
+    ) {
+        debug!(target: SESSION, "Connected to {peer}");
+
+        let rng = match inner.settings.weak_dispatch_seed {
+            Some(seed) => Rng::new(seed),
+            None => Rng::from_entropy(),
+        };
+        let (push, pushed) = mpsc::channel(PUSH_CAPACITY);
+        inner
+            .pushes
+            .lock()
+            .expect("Pushes lock is never poisoned")
+            .insert(session_id, push.clone());
+        let registered = inner.registry.register(session_id, peer, &stop);
+        let context = Context::new(
+            session_id,
+            peer,
+            inner.settings.extensions.clone(),
+            PushHandle::new(push),
+        );
+        if inner.settings.span_fields.identity {
+            Span::current().record("identity", context.identity());
+        }
+        let mut codec = FrameCodec::console();
+        if inner.settings.telnet {
+            codec = codec.with_telnet();
+        }
+        if let Some(max) = inner.settings.max_message_size {
+            codec = codec.with_max_message_size(max);
+        }
+        let mut encoder = FrameCodec::console();
+        if let Some(tap) = &inner.settings.frame_tap {
+            codec = codec.with_tap(tap.clone());
+            encoder = encoder.with_tap(tap.clone());
+        }
+        let (capacity, policy) = inner
+            .settings
+            .write_queue
+            .unwrap_or((WRITE_QUEUE_CAPACITY, SlowConsumer::Block));
+        let outbox = Arc::new(Outbox::new(capacity, policy));
+        let (reader, writer) = tokio::io::split(stream);
+        let mut session = Session {
+            inner,
+            stream: FramedRead::new(reader, codec),
+            outbox: outbox.clone(),
+            peer,
+            context,
+            pushed,
+            rng,
+            started: Instant::now(),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            last_ping: Instant::now(),
+            missed_pongs: 0,
+            compressor: None,
+            registered,
+            commands: 0,
+            errors: 0,
+            machine: false,
+            time_zone: TimeZone::utc(),
+            topics: HashSet::new(),
+            colors: true,
+            deltas: false,
+            script: false,
+            interrupted: None,
+            last_text: None,
+            running: JoinSet::new(),
+        };
+
+        // Frames are written as the session queues them, and those queued when it ends are still delivered.
+        let serve = async {
+            session.serve(session.registered.stop().clone()).await;
+            session.outbox.close();
+        };
+        tokio::join!(serve, outbox.write_to(FramedWrite::new(writer, encoder)));
+        session
+            .inner
+            .pushes
+            .lock()
+            .expect("Pushes lock is never poisoned")
+            .remove(&session_id);
+        session.inner.registry.remove(session_id);
+        for topic in &session.topics {
+            session.inner.topics.unsubscribe(topic, session_id);
+        }
+        if let Some(on_disconnect) = &session.inner.settings.on_disconnect {
+            on_disconnect(&session.context);
+        }
+        session.summarize();
+    }
+
+    async fn serve(&mut self, stop: CancellationToken) {
+        let peer = self.peer;
+
+        // Programmatic clients open with a hello, give it a moment to arrive to skip the welcome.
+        let mut pending = match time::timeout(HELLO_GRACE, self.stream.next()).await {
+            Ok(Some(Ok(frame))) => Some(frame),
+            Ok(Some(Err(err))) => {
+                self.errors += 1;
+                warn!(target: SESSION, "Error while receiving bytes: {err}. Received bytes will not be processed");
+                None
+            }
+            Ok(None) => {
+                debug!(target: SESSION, "Connection closed by {peer}");
+                return;
+            }
+            Err(_) => None,
+        };
+        if pending.as_ref().is_some_and(is_machine_hello) {
+            pending = None;
+            self.acknowledge_machine().await;
+        }
+
+        let mut welcome = if self.inner.settings.no_welcome {
+            String::new()
+        } else {
+            self.welcome()
+        };
+        if let Some(on_connect) = &self.inner.settings.on_connect {
+            if let Some(greeting) = on_connect(&self.context) {
+                // Part of the welcome frame, so that clients skipping the welcome skip the greeting too.
+                welcome.push_str(&ensure_newline(greeting));
+            }
+        }
+        if !self.machine {
+            if !welcome.is_empty() {
+                debug!(target: SESSION, "Welcoming {peer}");
+                self.send(Frame::Text(welcome.into_bytes().into())).await;
+                debug!(target: SESSION, "Finished welcoming {peer}");
+            }
+            self.prompt().await;
+        }
+
+        loop {
+            if self.outbox.is_closed() {
+                debug!(target: SESSION, "Connection to {peer} is closed. Closing the session.");
+                return;
+            }
+
+            let frame = match pending.take().or_else(|| self.interrupted.take()) {
+                Some(frame) => frame,
+                None => tokio::select! {
+                    _ = stop.cancelled() => {
+                        debug!(target: SESSION, "Stopping session for {peer}");
+                        // Requests in progress are still answered.
+                        while let Some(handled) = self.running.join_next().await {
+                            self.complete(handled).await;
+                        }
+                        // Texts queued before stopping, e.g. the lifecycle event, are still delivered.
+                        while let Ok(frame) = self.pushed.try_recv() {
+                            self.send(frame).await;
+                        }
+                        if self.registered.is_kicked() {
+                            let notice = Bytes::from_static(b"Session closed by the console\n");
+                            self.send(Frame::Text(notice)).await;
+                        }
+                        return;
+                    }
+                    _ = elapsed(self.last_received, self.inner.settings.idle_timeout) => {
+                        debug!(target: SESSION, "Session for {peer} is idle. Closing the session.");
+                        let notice = Bytes::from_static(b"Closing idle session\n");
+                        self.send(Frame::Text(notice)).await;
+                        return;
+                    }
+                    _ = elapsed(self.last_sent, self.keep_alive()) => {
+                        // A fresh prompt on its own line tells the operator the console is still there.
+                        self.send(Frame::Text(Bytes::from_static(b"\n"))).await;
+                        self.prompt().await;
+                        continue;
+                    }
+                    _ = elapsed(self.last_ping, self.heartbeat()) => {
+                        if !self.ping().await {
+                            return;
+                        }
+                        continue;
+                    }
+                    Some(frame) = self.pushed.recv() => {
+                        self.send(frame).await;
+                        continue;
+                    }
+                    Some(handled) = self.running.join_next() => {
+                        self.complete(handled).await;
+                        continue;
+                    }
+                    result = self.stream.next() => match result {
+                        Some(Ok(frame)) => {
+                            self.last_received = Instant::now();
+                            self.missed_pongs = 0;
+                            frame
+                        }
+                        Some(Err(err)) => {
+                            self.errors += 1;
+                            warn!(target: SESSION, "Error while receiving bytes: {err}. Received bytes will not be processed");
+                            continue;
+                        }
+                        None => {
+                            // Connection closed.
+                            debug!(target: SESSION, "Connection closed by {peer}");
+                            return;
+                        }
+                    }
+                },
+            };
+
+            if let Some(replies) = self.stream.decoder_mut().telnet_replies() {
+                // Negotiation is no content, it bypasses accounting and deltas.
+                if let Err(err) = self.outbox.push(Frame::Text(replies)).await {
+                    debug!(target: SESSION, "Failed to answer telnet negotiation of {peer}: {err:?}");
+                }
+            }
+            if let Some(oversized) = self.stream.decoder_mut().take_oversized() {
+                self.reject_oversized(oversized).await;
+            }
+            if is_wakeup(&frame) {
+                continue;
+            }
+
+            // A hello arriving after the welcome still marks a programmatic client.
+            if is_machine_hello(&frame) {
+                self.acknowledge_machine().await;
+                continue;
+            }
+            if let Some(control) = Control::from_frame(&frame) {
+                if !self.control(control).await {
+                    return;
+                }
+                continue;
+            }
+
+            self.registered.received(match &frame {
+                Frame::Typed(bytes) | Frame::Text(bytes) => bytes.len(),
+            });
+
+            if self.inner.settings.token.is_some() && !self.context.auth.authenticated {
+                if !self.authenticate(frame).await {
+                    return;
+                }
+                continue;
+            }
+
+            self.expire_elevation();
+            match frame {
+                Frame::Typed(bytes) => self.dispatch_typed(bytes).await,
+                Frame::Text(bytes) => {
+                    self.dispatch_text(bytes, &stop).await;
+                    self.prompt().await;
+                }
+            }
+        }
+    }
+
+    /// Marks the session as used by a programmatic client, which is spared frames meant for humans.
+    async fn acknowledge_machine(&mut self) {
+        debug!(target: SESSION, "{} is a programmatic client", self.peer);
+        self.machine = true;
+        self.send(Control::Offer(PROTOCOL_VERSIONS.to_vec()).to_frame())
+            .await;
+    }
+
+    /// Handles a control frame of a programmatic client, returns `false` if the session is to be closed.
+    async fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Select(version) => self.select_protocol(version).await,
+            Control::Ping => self.send(Control::Pong.to_frame()).await,
+            // Receiving any frame resets the missed heartbeats.
+            Control::Pong => true,
+            Control::Accept(names) => self.accept_compression(&names).await,
+            Control::Offer(_) | Control::Compress(_) => {
+                debug!(target: SESSION, "{} sent an unexpected {control:?}", self.peer);
+                true
+            }
+        }
+    }
+
+    /// Picks the first compressor of the console the client can decompress with, if any.
+    async fn accept_compression(&mut self, names: &[String]) -> bool {
+        let compressor = names.iter().find_map(|name| {
+            self.inner
+                .settings
+                .compressors
+                .iter()
+                .find(|compressor| compressor.name() == name)
+        });
+        let Some(compressor) = compressor.cloned() else {
+            return true;
+        };
+
+        debug!(target: SESSION, "Compressing typed frames to {} with {}", self.peer, compressor.name());
+        let sent = self
+            .send(Control::Compress(compressor.name().to_owned()).to_frame())
+            .await;
+        self.compressor = Some(compressor);
+        sent
+    }
+
+    /// Applies the protocol version the client picked, returns `false` if the session is to be closed.
+    async fn select_protocol(&mut self, version: u32) -> bool {
+        if !PROTOCOL_VERSIONS.contains(&version) {
+            self.errors += 1;
+            warn!(target: SESSION, "{} picked unsupported protocol version {version}. Closing the session.", self.peer);
+            let notice = format!(
+                "Unsupported protocol version {version}, supported: {PROTOCOL_VERSIONS:?}\n"
+            );
+            self.send(Frame::Text(notice.into_bytes().into())).await;
+            return false;
+        }
+
+        debug!(target: SESSION, "{} speaks protocol version {version}", self.peer);
+        self.context.protocol_version = Some(version);
+        true
+    }
+
+    /// Tells the client its message was dropped for exceeding [Builder::max_message_size](crate::Builder::max_message_size).
+    async fn reject_oversized(&mut self, oversized: Oversized) {
+        let max = self.inner.settings.max_message_size.unwrap_or_default();
+        self.errors += 1;
+        warn!(target: SESSION, "{} sent a message exceeding the limit of {max} bytes. Dropping the message.", self.peer);
+
+        let notice = format!("Message exceeds the limit of {max} bytes\n");
+        self.send(Frame::Text(notice.into_bytes().into())).await;
+        // Acknowledge the request, so that the sender does not wait forever for a reply.
+        if let Some(id) = oversized.id {
+            match bcs::to_bytes(&Reply {
+                id: Some(id),
+                bytes: None,
+                error: None,
+            }) {
+                Ok(reply) => {
+                    self.send(Frame::Typed(reply.into())).await;
+                }
+                Err(err) => warn!(target: DISPATCH, "Failed to serialize reply: {err}"),
+            }
+        }
+    }
+
+    /// Handles a frame received before the session authenticated,
+    /// returns `false` if the session must be closed.
+    async fn authenticate(&mut self, frame: Frame) -> bool {
+        let Some(expected) = &self.inner.settings.token else {
+            return true;
+        };
+
+        let bytes = match frame {
+            Frame::Text(bytes) => bytes,
+            Frame::Typed(bytes) => {
+                self.errors += 1;
+                warn!(target: SESSION, "Typed message from {} before authentication. Ignoring the message.", self.peer);
+                // Acknowledge the request, so that the sender does not wait forever for a reply.
+                if let Ok(Message { id: Some(id), .. }) =
+                    bcs::from_bytes::<Message<Services>>(bytes.as_ref())
+                {
+                    if let Ok(reply) = bcs::to_bytes(&Reply {
+                        id: Some(id),
+                        bytes: None,
+                        error: None,
+                    }) {
+                        self.send(Frame::Typed(reply.into())).await;
+                    }
+                }
+                return true;
+            }
+        };
+
+        let text = String::from_utf8_lossy(bytes.as_ref());
+        let (answer, keep_open) = match parse_auth(text.trim()) {
+            Some(token) if tokens_match(expected, token) => {
+                debug!(target: SESSION, "{} authenticated", self.peer);
+                self.context.auth.authenticated = true;
+                (AUTH_OK, true)
+            }
+            Some(_) => {
+                self.errors += 1;
+                warn!(target: SESSION, "{} failed to authenticate. Closing the session.", self.peer);
+                (AUTH_FAILED, false)
+            }
+            None => {
+                self.errors += 1;
+                (AUTH_REQUIRED, true)
+            }
+        };
+
+        self.send(Frame::Text(format!("{answer}\n").into_bytes().into()))
+            .await;
+
+        keep_open
+    }
+
+    /// Routes a strongly typed message to the subscription of its service.
+    async fn dispatch_typed(&mut self, bytes: Bytes) {
+        if let Some(batch) = Envelope::from_bytes(bytes.as_ref()) {
+            match batch {
+                Ok(batch) => self.dispatch_batch(batch).await,
+                Err(err) => {
+                    self.errors += 1;
+                    warn!(target: DISPATCH, "Received batch is not valid: {err}. Ignoring the frame.");
+                }
+            }
+            return;
+        }
+
+        let Message {
+            id,
+            service_id,
+            bytes,
+            version,
+        } = match bcs::from_bytes::<Message<Services>>(bytes.as_ref()) {
+            Ok(message) => message,
+            Err(err) => {
+                self.errors += 1;
+                warn!(target: DISPATCH, "Received typed frame is not a valid message: {err}. Ignoring the frame.");
+                return;
+            }
+        };
+
+        let span = self.dispatch_span(service_id.name());
+        self.deliver(id, service_id, bytes, version)
+            .instrument(span)
+            .await;
+    }
+
+    /// Hands a typed message to the subscription of `service_id` and replies to the sender.
+    async fn deliver(
+        &mut self,
+        id: Option<u64>,
+        service_id: Services,
+        bytes: Bytes,
+        version: Option<u32>,
+    ) {
+        self.commands += 1;
+        self.inner.stats.record_message();
+        message_debug!(self, "Received message for {}", service_id.name());
+        self.record(format!("{} (typed)", service_id.name()));
+
+        let subscriptions = self.inner.subscriptions();
+        let response = if !self.reachable(&service_id) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Service {} is not exposed to {}. Refusing the message.", service_id.name(), self.peer);
+            let notice = format!(
+                "Access denied from {}: {}",
+                self.peer.ip(),
+                service_id.name()
+            );
+            self.refuse(id, ReplyError::Refused(notice)).await;
+            return;
+        } else if !self.authorized(&service_id) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "{} is not authorized for service {}. Refusing the message.", self.context.auth.identity, service_id.name());
+            let notice = format!("Access denied to {}", service_id.name());
+            self.refuse(id, ReplyError::Refused(notice)).await;
+            return;
+        } else if self.inner.is_disabled(service_id.name()) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Service {} is disabled. Refusing the message.", service_id.name());
+            let notice = format!("Service {} is disabled", service_id.name());
+            self.decline(id, notice).await;
+            return;
+        } else if let Some(subscription) = subscriptions.route(&service_id, version) {
+            message_debug!(self, "Found subscription for service {}", service_id.name());
+
+            if subscription.is_mutating() && self.inner.mode() == Mode::ReadOnly {
+                warn!(target: DISPATCH, "Service {} changes state in read-only mode. Refusing the message.", service_id.name());
+                match id {
+                    Some(_) => {
+                        self.refuse(id, ReplyError::Refused(MAINTENANCE.to_owned()))
+                            .await
+                    }
+                    None => {
+                        self.send(text_frame(MAINTENANCE)).await;
+                    }
+                }
+                return;
+            }
+            let incoming = Incoming::Typed {
+                service: service_id.name().to_owned(),
+                payload: bytes.clone(),
+            };
+            if let Err(err) = self.before(&incoming).await {
+                self.errors += 1;
+                warn!(target: DISPATCH, "Message for service {} is refused: {err}", service_id.name());
+                match id {
+                    Some(_) => self.refuse(id, ReplyError::Refused(err.to_string())).await,
+                    None => {
+                        self.send(text_frame(&err.to_string())).await;
+                    }
+                }
+                return;
+            }
+            if self.inner.settings.concurrent_requests.is_some() {
+                let subscriptions = subscriptions.clone();
+                self.spawn_request(id, service_id, incoming, bytes, version, subscriptions)
+                    .await;
+                return;
+            }
+            let started = Instant::now();
+            let timeout = subscription
+                .handler_timeout()
+                .or(self.inner.settings.handler_timeout);
+            let result = supervise(timeout, subscription.handle_with(&self.context, bytes)).await;
+            match self
+                .handled(id.is_some(), &service_id, incoming, started, result)
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    self.refuse(id, error).await;
+                    return;
+                }
+            }
+        } else if self.inner.is_starting_up() && !subscriptions.is_registered(&service_id) {
+            debug!(target: DISPATCH, "Service {} is not registered yet. Asking to retry.", service_id.name());
+            self.decline(id, STARTING_UP.to_owned()).await;
+            return;
+        } else if let Some(version) = version.filter(|_| subscriptions.is_registered(&service_id)) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Service {} does not support version {version}. Refusing the message.", service_id.name());
+            let supported = subscriptions.versions(&service_id);
+            let notice = version_notice(&service_id, version, &supported);
+            self.decline(id, notice).await;
+            return;
+        } else {
+            self.errors += 1;
+            warn!(target: DISPATCH, "No subscription found for service {}. Ignoring the message.", service_id.name());
+            let error = ReplyError::UnknownService(service_id.name().to_owned());
+            self.refuse(id, error).await;
+            return;
+        };
+
+        self.reply(id, response).await;
+    }
+
+    /// Handles the messages of `batch` one after the other and replies with all their results.
+    async fn dispatch_batch(&mut self, batch: Envelope<Services>) {
+        let Envelope { id, messages } = batch;
+        debug!(target: DISPATCH, "Received a batch of {} messages", messages.len());
+
+        let mut results = Vec::with_capacity(messages.len());
+        for Message {
+            service_id,
+            bytes,
+            version,
+            ..
+        } in messages
+        {
+            let span = self.dispatch_span(service_id.name());
+            let result = self
+                .execute(service_id, bytes, version)
+                .instrument(span)
+                .await;
+            results.push(result);
+        }
+
+        match bcs::to_bytes::<batch::Results>(&results) {
+            Ok(results) => self.reply(Some(id), Some(results.into())).await,
+            Err(err) => warn!(target: DISPATCH, "Failed to serialize batch results: {err}"),
+        }
+    }
+
+    /// Hands a typed message of a batch to the subscription of `service_id`, returns its result
+    /// where [Session::deliver] would answer the sender.
+    async fn execute(
+        &mut self,
+        service_id: Services,
+        bytes: Bytes,
+        version: Option<u32>,
+    ) -> Result<Option<Bytes>, ReplyError> {
+        self.commands += 1;
+        self.inner.stats.record_message();
+        message_debug!(self, "Received batched message for {}", service_id.name());
+        self.record(format!("{} (batch)", service_id.name()));
+
+        let subscriptions = self.inner.subscriptions();
+        if !self.reachable(&service_id) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Service {} is not exposed to {}. Refusing the message.", service_id.name(), self.peer);
+            let notice = format!(
+                "Access denied from {}: {}",
+                self.peer.ip(),
+                service_id.name()
+            );
+            return Err(ReplyError::Refused(notice));
+        }
+        if !self.authorized(&service_id) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "{} is not authorized for service {}. Refusing the message.", self.context.auth.identity, service_id.name());
+            return Err(ReplyError::Refused(format!(
+                "Access denied to {}",
+                service_id.name()
+            )));
+        }
+        if self.inner.is_disabled(service_id.name()) {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Service {} is disabled. Ignoring the message.", service_id.name());
+            let notice = format!("Service {} is disabled", service_id.name());
+            return Err(ReplyError::Refused(notice));
+        }
+        let Some(subscription) = subscriptions.route(&service_id, version) else {
+            self.errors += 1;
+            if self.inner.is_starting_up() && !subscriptions.is_registered(&service_id) {
+                return Err(ReplyError::Refused(STARTING_UP.to_owned()));
+            }
+            if let Some(version) = version.filter(|_| subscriptions.is_registered(&service_id)) {
+                let supported = subscriptions.versions(&service_id);
+                let notice = version_notice(&service_id, version, &supported);
+                return Err(ReplyError::Refused(notice));
+            }
+            warn!(target: DISPATCH, "No subscription found for service {}. Ignoring the message.", service_id.name());
+            return Err(ReplyError::UnknownService(service_id.name().to_owned()));
+        };
+        if subscription.is_mutating() && self.inner.mode() == Mode::ReadOnly {
+            warn!(target: DISPATCH, "Service {} changes state in read-only mode. Refusing the message.", service_id.name());
+            return Err(ReplyError::Refused(MAINTENANCE.to_owned()));
+        }
+
+        let incoming = Incoming::Typed {
+            service: service_id.name().to_owned(),
+            payload: bytes.clone(),
+        };
+        if let Err(err) = self.before(&incoming).await {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Message for service {} is refused: {err}", service_id.name());
+            return Err(ReplyError::Refused(err.to_string()));
+        }
+        let started = Instant::now();
+        let timeout = subscription
+            .handler_timeout()
+            .or(self.inner.settings.handler_timeout);
+        let result = supervise(timeout, subscription.handle_with(&self.context, bytes)).await;
+        self.handled(true, &service_id, incoming, started, result)
+            .await
+    }
+
+    /// Handles a typed request in its own task, once fewer than
+    /// [Builder::concurrent_requests](crate::Builder::concurrent_requests) are in progress.
+    async fn spawn_request(
+        &mut self,
+        id: Option<u64>,
+        service_id: Services,
+        incoming: Incoming,
+        bytes: Bytes,
+        version: Option<u32>,
+        subscriptions: Arc<Subscriptions<Services>>,
+    ) {
+        let max = self
+            .inner
+            .settings
+            .concurrent_requests
+            .unwrap_or(usize::MAX);
+        while self.running.len() >= max {
+            if let Some(handled) = self.running.join_next().await {
+                self.complete(handled).await;
+            }
+        }
+
+        let context = self.context.clone();
+        let handler_timeout = self.inner.settings.handler_timeout;
+        let request = async move {
+            let subscription = subscriptions
+                .route(&service_id, version)
+                .expect("Subscription is resolved before spawning");
+            let started = Instant::now();
+            let timeout = subscription.handler_timeout().or(handler_timeout);
+            let result = supervise(timeout, subscription.handle_with(&context, bytes)).await;
+            Handled {
+                id,
+                service_id,
+                incoming,
+                started,
+                result,
+            }
+        };
+        self.running.spawn(request.instrument(Span::current()));
+    }
+
+    /// Answers a typed request handled in its own task.
+    async fn complete(&mut self, handled: Result<Handled<Services>, JoinError>) {
+        match handled {
+            Ok(Handled {
+                id,
+                service_id,
+                incoming,
+                started,
+                result,
+            }) => match self
+                .handled(id.is_some(), &service_id, incoming, started, result)
+                .await
+            {
+                Ok(response) => self.reply(id, response).await,
+                Err(error) => self.refuse(id, error).await,
+            },
+            Err(err) => {
+                self.errors += 1;
+                warn!(target: DISPATCH, "Task handling a typed request failed: {err}");
+            }
+        }
+    }
+
+    /// Accounts for the `result` of a subscription of `service_id`, returns the response to send,
+    /// or the error to reply with if the subscription failed. Senders of a `request` are always
+    /// told about failures, others only get the [Builder::error_reporting](crate::Builder::error_reporting) text.
+    async fn handled(
+        &mut self,
+        request: bool,
+        service_id: &Services,
+        incoming: Incoming,
+        started: Instant,
+        result: Result<Option<Bytes>, SubscriptionError>,
+    ) -> Result<Option<Bytes>, ReplyError> {
+        let outcome = Outcome {
+            elapsed: started.elapsed(),
+            answered: matches!(result, Ok(Some(_))),
+            error: result.as_ref().err().map(ToString::to_string),
+        };
+        self.after(&incoming, &outcome).await;
+        self.inner
+            .stats
+            .record(service_id.name(), outcome.elapsed, result.is_err());
+        match result {
+            Ok(response) => Ok(response),
+            Err(err) => {
+                self.errors += 1;
+                warn!(target: DISPATCH, "Service {} failed to handle message: {err}", service_id.name());
+                if let Some(error) = err.downcast_ref::<ConsoleError>() {
+                    return Err(ReplyError::Failed(error.clone()));
+                }
+                if let Some(timeout) = err.downcast_ref::<HandlerTimeout>() {
+                    return Err(ReplyError::TimedOut(timeout.0));
+                }
+                if request && err.is::<HandlerPanic>() {
+                    // The panic message may reveal internals, it is only logged.
+                    return Err(ReplyError::Panicked);
+                }
+                let report = self
+                    .inner
+                    .settings
+                    .error_reporting
+                    .report(service_id.name(), &err);
+                if request {
+                    // Which service failed is no news to the sender, the error itself may be.
+                    let message = report.unwrap_or_else(|| {
+                        format!("Service {} failed to handle the message", service_id.name())
+                    });
+                    return Err(ReplyError::Failed(ConsoleError::new(
+                        HANDLER_FAILED,
+                        message,
+                    )));
+                }
+                if let Some(report) = report {
+                    self.send(text_frame(&report)).await;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sends `response` to a typed message.
+    async fn reply(&mut self, id: Option<u64>, response: Option<Bytes>) {
+        // Requests carrying a correlation id are always acknowledged,
+        // so that the sender does not wait forever for a reply.
+        if response.is_some() || id.is_some() {
+            match bcs::to_bytes(&Reply {
+                id,
+                bytes: response,
+                error: None,
+            }) {
+                Ok(reply) => {
+                    self.send(Frame::Typed(reply.into())).await;
+                }
+                Err(err) => warn!(target: DISPATCH, "Failed to serialize reply: {err}"),
+            }
+        }
+    }
+
+    /// Tells the sender of a request why it was not handled, messages without a correlation id
+    /// are not answered.
+    async fn refuse(&mut self, id: Option<u64>, error: ReplyError) {
+        let Some(id) = id else {
+            return;
+        };
+        match bcs::to_bytes(&Reply {
+            id: Some(id),
+            bytes: None,
+            error: Some(error),
+        }) {
+            Ok(reply) => {
+                self.send(Frame::Typed(reply.into())).await;
+            }
+            Err(err) => warn!(target: DISPATCH, "Failed to serialize reply: {err}"),
+        }
+    }
+
+    /// Span of a message dispatched to `service`, see [Builder::span_fields](crate::Builder::span_fields).
+    fn dispatch_span(&self, service: &str) -> Span {
+        let span = info_span!(target: DISPATCH, "dispatch", service = field::Empty);
+        if self.inner.settings.span_fields.service {
+            span.record("service", service);
+        }
+        span
+    }
+
+    /// Refuses a request with `notice`, which messages without a correlation id get as text instead.
+    async fn decline(&mut self, id: Option<u64>, notice: String) {
+        match id {
+            Some(_) => self.refuse(id, ReplyError::Refused(notice)).await,
+            None => {
+                self.send(text_frame(&notice)).await;
+            }
+        }
+    }
+
+    /// Offers a free-form message to subscriptions until the _first_ success,
+    /// or to all of them under [WeakDispatch::All].
+    async fn dispatch_text(&mut self, bytes: Bytes, stop: &CancellationToken) {
+        // Message is not strongly typed and probably came from netcat or a similar client.
+        let text = String::from_utf8_lossy(bytes.as_ref()).trim().to_string();
+        if self.script {
+            if text.is_empty() || text.starts_with('#') {
+                return;
+            }
+            self.send(text_frame(&format!("> {text}"))).await;
+        }
+        self.commands += 1;
+        self.inner.stats.record_message();
+        message_debug!(
+            self,
+            "Received message is not typed. Treating it as text: {text}"
+        );
+        self.record(text.clone());
+
+        let report = match self.builtin(&text) {
+            Some(report) => Some(report),
+            None => self.selftest(&text).await,
+        };
+        if let Some(report) = report {
+            self.send(Frame::Text(ensure_newline(report).into_bytes().into()))
+                .await;
+            return;
+        }
+
+        let incoming = Incoming::Text(text.clone());
+        if let Err(err) = self.before(&incoming).await {
+            self.errors += 1;
+            warn!(target: DISPATCH, "Text message is refused: {err}");
+            self.send(text_frame(&err.to_string())).await;
+            return;
+        }
+        let started = Instant::now();
+        let (answered, error) = self.offer(&text, stop).await;
+        let outcome = Outcome {
+            elapsed: started.elapsed(),
+            answered,
+            error,
+        };
+        self.after(&incoming, &outcome).await;
+    }
+
+    /// Offers `text` to subscriptions according to [WeakDispatch], returns whether one answered
+    /// and the error of the first one failing.
+    async fn offer(&mut self, text: &str, stop: &CancellationToken) -> (bool, Option<String>) {
+        let subscriptions = self.inner.subscriptions();
+        let order = match self.inner.settings.weak_dispatch {
+            WeakDispatch::FirstSuccess => {
+                let mut order = subscriptions.entries().iter().collect::<Vec<_>>();
+                self.rng.shuffle(&mut order);
+                order
+            }
+            WeakDispatch::Ordered | WeakDispatch::All => subscriptions.entries().iter().collect(),
+            WeakDispatch::Prefix => {
+                let mut order = subscriptions
+                    .entries()
+                    .iter()
+                    .filter(|entry| service::addressed(entry.0.name(), text).is_some())
+                    .collect::<Vec<_>>();
+                // `network peers` goes to `network.peers` before `network`.
+                order.sort_by_key(|entry| std::cmp::Reverse(entry.0.name().len()));
+                order
+            }
+        };
+
+        // Services skipped because they are not exposed to the peer.
+        let mut denied = Vec::new();
+        // Whether mutating services were skipped in read-only mode.
+        let mut held = false;
+        // Report of the first subscription failing to handle the message.
+        let mut failure = None;
+        let mut error = None;
+        let mut answered = false;
+        for entry in order {
+            let (service_id, subscription) = entry.as_ref();
+            if !self.reachable(service_id) {
+                denied.push(service_id.name());
+                continue;
+            }
+            if !self.authorized(service_id) || self.inner.is_disabled(service_id.name()) {
+                continue;
+            }
+            if subscription.is_mutating() && self.inner.mode() == Mode::ReadOnly {
+                held = true;
+                continue;
+            }
+
+            // Handlers see their name spelled with dots however it was addressed.
+            let routed;
+            let text = match self.inner.settings.weak_dispatch {
+                WeakDispatch::Prefix => {
+                    routed = service::addressed(service_id.name(), text)
+                        .expect("Only addressed services are offered the message");
+                    routed.as_str()
+                }
+                _ => text,
+            };
+            message_debug!(
+                self,
+                "[{}] request to process text message: `{text}`",
+                service_id.name()
+            );
+
+            let span = self.dispatch_span(service_id.name());
+            let started = Instant::now();
+            let timeout = subscription
+                .handler_timeout()
+                .or(self.inner.settings.handler_timeout);
+            let result = supervise(
+                timeout,
+                subscription.weak_handle_stream(&self.context, text),
+            )
+            .instrument(span.clone())
+            .await;
+            match result {
+                Ok(None) => {
+                    continue;
+                }
+                Ok(Some(replies)) => {
+                    self.forward(replies, stop).instrument(span).await;
+                    message_debug!(self, "[{}] Message processed", service_id.name());
+                    self.inner
+                        .stats
+                        .record(service_id.name(), started.elapsed(), false);
+                    if self.inner.settings.weak_dispatch != WeakDispatch::All {
+                        return (true, error);
+                    }
+                    answered = true;
+                }
+                Err(err) => {
+                    self.errors += 1;
+                    self.inner
+                        .stats
+                        .record(service_id.name(), started.elapsed(), true);
+                    span.in_scope(|| warn!(target: DISPATCH, "Service {} failed to handle message: {err}", service_id.name()));
+                    error.get_or_insert_with(|| err.to_string());
+                    if failure.is_none() {
+                        failure = match err.downcast_ref::<HandlerTimeout>() {
+                            // Timeouts reveal nothing of the handler, the sender is always told.
+                            Some(timeout) => Some(format!(
+                                "Service {} timed out after {:?}",
+                                service_id.name(),
+                                timeout.0
+                            )),
+                            None => self
+                                .inner
+                                .settings
+                                .error_reporting
+                                .report(service_id.name(), &err),
+                        };
+                    }
+                    continue;
+                }
+            }
+        }
+
+        if !answered && failure.is_none() {
+            if let Some(fallback) = subscriptions.fallback() {
+                let timeout = fallback
+                    .handler_timeout()
+                    .or(self.inner.settings.handler_timeout);
+                match supervise(timeout, fallback.weak_handle_stream(&self.context, text)).await {
+                    Ok(Some(replies)) => {
+                        self.forward(replies, stop).await;
+                        return (true, error);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        self.errors += 1;
+                        warn!(target: DISPATCH, "Default subscription failed to handle message: {err}");
+                        failure = self.inner.settings.error_reporting.report("default", &err);
+                        error = Some(err.to_string());
+                    }
+                }
+            }
+        }
+
+        if let Some(report) = failure {
+            self.send(text_frame(&report)).await;
+        } else if answered {
+            // Every subscription had its say under WeakDispatch::All.
+        } else if held {
+            self.send(text_frame(MAINTENANCE)).await;
+        } else if !denied.is_empty() {
+            self.errors += 1;
+            denied.sort_unstable();
+            let denial = format!(
+                "Access denied from {}: {}\n",
+                self.peer.ip(),
+                denied.join(", ")
+            );
+            self.send(Frame::Text(denial.into_bytes().into())).await;
+        } else if self.inner.is_starting_up() {
+            // The subscription meant to answer may not be registered yet.
+            self.send(text_frame(STARTING_UP)).await;
+        } else if let Some(fallback) = &self.inner.settings.unknown_command {
+            let fallback = text_frame(fallback);
+            self.send(fallback).await;
+        }
+        (answered, error)
+    }
+
+    /// Sends every chunk of `replies` as it arrives, until the stream ends, the console stops
+    /// or the remote end sends anything, which interrupts the stream and is dispatched next.
+    async fn forward(&mut self, mut replies: ReplyStream, stop: &CancellationToken) {
+        // A response interrupted earlier also ends those still to be forwarded for the same message.
+        if self.interrupted.is_some() {
+            return;
+        }
+        loop {
+            let chunk = tokio::select! {
+                // Chunks already produced are sent first, so stopping never loses a complete reply.
+                biased;
+                chunk = replies.next() => match chunk {
+                    Some(chunk) => chunk,
+                    None => return,
+                },
+                _ = stop.cancelled() => return,
+                // Lines of a runbook wait for their turn instead.
+                frame = self.stream.next(), if !self.script => {
+                    match frame {
+                        Some(Ok(frame)) => {
+                            self.last_received = Instant::now();
+                            self.missed_pongs = 0;
+                            self.interrupted = Some(frame);
+                        }
+                        Some(Err(err)) => {
+                            self.errors += 1;
+                            warn!(target: SESSION, "Error while receiving bytes: {err}. Received bytes will not be processed");
+                        }
+                        None => {}
+                    }
+                    debug!(target: SESSION, "{} interrupted a streamed response", self.peer);
+                    return;
+                }
+            };
+
+            if !self.send(Frame::Text(chunk)).await {
+                return;
+            }
+        }
+    }
+
+    /// Runs the [Builder::layer](crate::Builder::layer) hooks before dispatching `incoming`, in order.
+    async fn before(&self, incoming: &Incoming) -> Result<(), SubscriptionError> {
+        for layer in &self.inner.settings.middleware {
+            layer.before(&self.context, incoming).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the [Builder::layer](crate::Builder::layer) hooks once `incoming` is handled, in reverse order.
+    async fn after(&self, incoming: &Incoming, outcome: &Outcome) {
+        for layer in self.inner.settings.middleware.iter().rev() {
+            layer.after(&self.context, incoming, outcome).await;
+        }
+    }
+
+    /// Consults the [Builder::restrict_service](crate::Builder::restrict_service) networks.
+    fn reachable(&self, service_id: &Services) -> bool {
+        self.inner.policy.reachable(self.peer.ip(), service_id)
+    }
+
+    /// Consults the [Builder::authorizer](crate::Builder::authorizer), if any.
+    fn authorized(&self, service_id: &Services) -> bool {
+        self.inner.policy.authorized(&self.context.auth, service_id)
+    }
+
+    /// Records a command in the history of the session identity, if history is enabled.
+    fn record(&self, command: String) {
+        if let Some(history) = &self.inner.settings.history {
+            history.record(
+                &self.context.auth.identity,
+                HistoryEntry {
+                    at: SystemTime::now(),
+                    command,
+                },
+            );
+        }
+    }
+
+    /// Answers built-in commands, returns `None` if `text` is not an enabled built-in command.
+    fn builtin(&mut self, text: &str) -> Option<String> {
+        self.audit(text)
+            .or_else(|| self.elevate(text))
+            .or_else(|| self.toggle_deltas(text))
+            .or_else(|| self.toggle_colors(text))
+            .or_else(|| self.subscribe_topic(text))
+            .or_else(|| self.stats(text))
+            .or_else(|| self.list_sessions(text))
+            .or_else(|| self.runtime(text))
+            .or_else(|| self.start_script(text))
+            .or_else(|| self.set_time_zone(text))
+            .or_else(|| self.help(text))
+            .or_else(|| self.list_services(text))
+    }
+
+    /// Answers [SERVICES_COMMAND] with the services the session may use,
+    /// see [Client::list_services](crate::Client::list_services).
+    fn list_services(&self, text: &str) -> Option<String> {
+        if text != SERVICES_COMMAND {
+            return None;
+        }
+
+        let subscriptions = self.inner.subscriptions();
+        let services = subscriptions
+            .services()
+            .into_iter()
+            .filter(|service_id| self.reachable(service_id) && self.authorized(service_id))
+            .map(|service_id| ServiceInfo {
+                name: service_id.name().to_owned(),
+                versions: subscriptions.versions(service_id),
+                description: subscriptions
+                    .resolve(service_id, None)
+                    .and_then(|subscription| subscription.description())
+                    .map(str::to_owned),
+            })
+            .collect::<Vec<_>>();
+        Some(introspection::render(&services))
+    }
+
+    /// Answers the `tz [zone]` command, if time zones are enabled and `text` is such a command,
+    /// by showing or setting the zone timestamps of built-in commands are rendered in.
+    fn set_time_zone(&mut self, text: &str) -> Option<String> {
+        if !self.inner.settings.time_zones {
+            return None;
+        }
+        let zone = argument(text, TZ_COMMAND)?;
+        if zone.is_empty() {
+            return Some(format!("Time zone is {}", self.time_zone.name()));
+        }
+
+        Some(match TimeZone::parse(zone) {
+            Ok(time_zone) => {
+                self.time_zone = time_zone;
+                format!("Time zone set to {zone}")
+            }
+            Err(err) => err,
+        })
+    }
+
+    /// Answers the `help` command, if enabled and `text` is that command,
+    /// with the services the session may use and the enabled built-in commands.
+    fn help(&self, text: &str) -> Option<String> {
+        if !self.inner.settings.help || text != "help" {
+            return None;
+        }
+
+        // Services without a namespace come first, then every namespace in order of appearance.
+        let mut namespaces: Vec<(Option<&str>, Vec<String>)> = vec![(None, Vec::new())];
+        let subscriptions = self.inner.subscriptions();
+        for entry in subscriptions.entries() {
+            let (service_id, subscription) = entry.as_ref();
+            if !self.reachable(service_id) || !self.authorized(service_id) {
+                continue;
+            }
+
+            let namespace = service_id.namespace();
+            let mut line = match namespace {
+                Some(namespace) => service_id.name()[namespace.len() + 1..].to_owned(),
+                None => service_id.name().to_owned(),
+            };
+            if let Some(description) = subscription.description() {
+                line.push_str(" - ");
+                line.push_str(description);
+            }
+            match namespaces.iter_mut().find(|(other, _)| *other == namespace) {
+                Some((_, lines)) => lines.push(line),
+                None => namespaces.push((namespace, vec![line])),
+            }
+        }
+
+        let mut help = "Services:".to_owned();
+        for (namespace, lines) in &namespaces {
+            let indent = match namespace {
+                Some(namespace) => {
+                    help.push_str(&format!("\n  {namespace}:"));
+                    "\n    "
+                }
+                None => "\n  ",
+            };
+            for line in lines {
+                help.push_str(indent);
+                help.push_str(line);
+            }
+        }
+
+        let settings = &self.inner.settings;
+        let builtins = [
+            (settings.history.is_some(), "audit <identity>"),
+            (settings.approver.is_some(), "elevate <reason>"),
+            (settings.delta_updates, "delta on|off"),
+            (settings.topics, "subscribe|unsubscribe <topic>"),
+            (settings.color_command, "color on|off"),
+            (settings.stats_command, "stats [service]"),
+            (settings.sessions_command, "sessions"),
+            (settings.runtime_command, RUNTIME_COMMAND),
+            (settings.script_command, SCRIPT_COMMAND),
+            (settings.time_zones, "tz [zone]"),
+            (settings.selftest.is_some(), "selftest"),
+            (true, "help"),
+        ];
+        help.push_str("\nBuilt-in commands:");
+        for (_, command) in builtins.iter().filter(|(enabled, _)| *enabled) {
+            help.push_str("\n  ");
+            help.push_str(command);
+        }
+
+        Some(help)
+    }
+
+    /// Answers the `audit <identity>` command, if history is enabled and `text` is such a command.
+    fn audit(&self, text: &str) -> Option<String> {
+        let history = self.inner.settings.history.as_ref()?;
+        let identity = argument(text, "audit")?;
+        if identity.is_empty() {
+            return Some("Usage: audit <identity>".to_owned());
+        }
+
+        let entries = history.recent(identity);
+        if entries.is_empty() {
+            return Some(format!("No commands recorded for {identity}"));
+        }
+
+        Some(
+            entries
+                .iter()
+                .map(|entry| format!("{} {}", self.time_zone.format(entry.at), entry.command))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    /// Answers the `elevate <reason>` command, if elevation is enabled and `text` is such a command.
+    fn elevate(&mut self, text: &str) -> Option<String> {
+        let approver = self.inner.settings.approver.as_ref()?;
+        let reason = argument(text, "elevate")?;
+        if reason.is_empty() {
+            return Some("Usage: elevate <reason>".to_owned());
+        }
+
+        match approver(&self.context.auth, reason) {
+            Some(duration) => {
+                info!(target: SESSION, "{} elevated for {duration:?}: {reason}", self.context.auth.identity);
+                self.context.auth.elevated_until = Some(Instant::now() + duration);
+                Some(format!("Elevated for {duration:?}"))
+            }
+            None => {
+                warn!(target: SESSION, "{} was denied elevation: {reason}", self.context.auth.identity);
+                Some("Elevation denied".to_owned())
+            }
+        }
+    }
+
+    /// Answers the `selftest` command, if enabled and `text` is that command.
+    async fn selftest(&self, text: &str) -> Option<String> {
+        let timeout = self.inner.settings.selftest?;
+        if text != "selftest" {
+            return None;
+        }
+
+        let subscriptions = self.inner.subscriptions();
+        let probes = subscriptions.entries().iter().map(|entry| async move {
+            let (service_id, subscription) = entry.as_ref();
+            let started = Instant::now();
+            let (healthy, outcome) = match time::timeout(timeout, subscription.health()).await {
+                Ok(Ok(())) => (true, format!("ok in {:?}", started.elapsed())),
+                Ok(Err(err)) => (false, format!("failed: {err}")),
+                Err(_) => (false, format!("no answer within {timeout:?}")),
+            };
+            if !healthy {
+                warn!(target: DISPATCH, "Service {} failed the self-test: {outcome}", service_id.name());
+            }
+            (healthy, format!("{}: {outcome}", service_id.name()))
+        });
+        let results = join_all(probes).await;
+
+        let healthy = results.iter().filter(|(healthy, _)| *healthy).count();
+        let mut report = format!("{healthy} of {} services healthy", results.len());
+        for (_, line) in results {
+            report.push('\n');
+            report.push_str(&line);
+        }
+        Some(report)
+    }
+
+    /// Answers the `subscribe <topic>` and `unsubscribe <topic>` commands,
+    /// if topics are enabled and `text` is such a command.
+    fn subscribe_topic(&mut self, text: &str) -> Option<String> {
+        if !self.inner.settings.topics {
+            return None;
+        }
+
+        let session_id = self.context.session_id();
+        if let Some(topic) = argument(text, SUBSCRIBE_COMMAND) {
+            if topic.is_empty() {
+                return Some(format!("Usage: {SUBSCRIBE_COMMAND} <topic>"));
+            }
+
+            let push = self.context.push_handle().frames.clone();
+            self.topics.insert(topic.to_owned());
+            return Some(if self.inner.topics.subscribe(topic, session_id, push) {
+                format!("Subscribed to {topic}")
+            } else {
+                format!("Already subscribed to {topic}")
+            });
+        }
+
+        let topic = argument(text, UNSUBSCRIBE_COMMAND)?;
+        if topic.is_empty() {
+            return Some(format!("Usage: {UNSUBSCRIBE_COMMAND} <topic>"));
+        }
+
+        self.topics.remove(topic);
+        Some(if self.inner.topics.unsubscribe(topic, session_id) {
+            format!("Unsubscribed from {topic}")
+        } else {
+            format!("Not subscribed to {topic}")
+        })
+    }
+
+    /// Answers the `stats [service]` command, if enabled and `text` is such a command.
+    fn stats(&self, text: &str) -> Option<String> {
+        if !self.inner.settings.stats_command {
+            return None;
+        }
+        let service = argument(text, "stats")?;
+
+        let lines = self
+            .inner
+            .stats
+            .snapshot()
+            .into_iter()
+            .filter(|stats| service.is_empty() || stats.service == service)
+            .map(|stats| stats.to_string())
+            .collect::<Vec<_>>();
+        let mut report = match (lines.is_empty(), service.is_empty()) {
+            (false, _) => lines.join("\n"),
+            (true, true) => "No calls recorded".to_owned(),
+            (true, false) => format!("No calls recorded for {service}"),
+        };
+
+        if service.is_empty() && !self.inner.settings.metrics_providers.is_empty() {
+            report.push_str("\nMetrics:");
+            for metric in self.inner.host_metrics() {
+                report.push_str(&format!("\n  {metric}"));
+            }
+        }
+        Some(report)
+    }
+
+    /// Answers the [RUNTIME_COMMAND], if enabled, see [Builder::runtime_command](crate::Builder::runtime_command).
+    fn runtime(&self, text: &str) -> Option<String> {
+        if !self.inner.settings.runtime_command || text != RUNTIME_COMMAND {
+            return None;
+        }
+
+        let sessions = self.inner.sessions.load(Ordering::Relaxed);
+        Some(runtime::report(self.inner.started.elapsed(), sessions))
+    }
+
+    /// Answers the [SCRIPT_COMMAND], if enabled, by running the rest of the input as a runbook,
+    /// see [Builder::script_command](crate::Builder::script_command).
+    fn start_script(&mut self, text: &str) -> Option<String> {
+        if !self.inner.settings.script_command || text != SCRIPT_COMMAND {
+            return None;
+        }
+
+        self.script = true;
+        Some("Running the script".to_owned())
+    }
+
+    /// Answers the `sessions` command, if enabled, with the open sessions, see [Console::sessions](crate::Console::sessions).
+    fn list_sessions(&self, text: &str) -> Option<String> {
+        if !self.inner.settings.sessions_command || text != "sessions" {
+            return None;
+        }
+
+        let lines = self
+            .inner
+            .registry
+            .list()
+            .into_iter()
+            .map(|session| {
+                let mut line = format!(
+                    "#{} {} since {}, {} bytes in, {} bytes out",
+                    session.session_id,
+                    session.peer,
+                    self.time_zone.format(session.connected_at),
+                    session.bytes_in,
+                    session.bytes_out
+                );
+                if session.session_id == self.context.session_id() {
+                    line.push_str(" (this session)");
+                }
+                line
+            })
+            .collect::<Vec<_>>();
+        Some(lines.join("\n"))
+    }
+
+    /// Answers the `delta on|off` commands, if delta updates are enabled and `text` is such a command.
+    fn toggle_deltas(&mut self, text: &str) -> Option<String> {
+        if !self.inner.settings.delta_updates {
+            return None;
+        }
+
+        self.deltas = match text {
+            DELTA_ON => true,
+            DELTA_OFF => false,
+            _ => return None,
+        };
+        // The answer is sent in full, becoming the base of the first delta.
+        self.last_text = None;
+
+        Some(
+            if self.deltas {
+                DELTA_ENABLED
+            } else {
+                DELTA_DISABLED
+            }
+            .to_owned(),
+        )
+    }
+
+    /// Answers the `color on|off` command, if enabled and `text` is such a command.
+    fn toggle_colors(&mut self, text: &str) -> Option<String> {
+        if !self.inner.settings.color_command {
+            return None;
+        }
+
+        self.colors = match argument(text, COLOR_COMMAND)? {
+            "on" => true,
+            "off" => false,
+            _ => return Some("Usage: color on|off".to_owned()),
+        };
+        Some(format!(
+            "Colors {}",
+            if self.colors { "enabled" } else { "disabled" }
+        ))
+    }
+
+    /// Encodes a text frame as a delta against the previous one, if shorter.
+    fn encode_delta(&mut self, bytes: Bytes) -> Bytes {
+        let text = String::from_utf8_lossy(bytes.as_ref()).into_owned();
+        let delta = self
+            .last_text
+            .as_deref()
+            .and_then(|previous| delta::diff(previous, &text));
+        self.last_text = Some(text);
+
+        delta.map_or(bytes, |delta| delta.into_bytes().into())
+    }
+
+    /// Drops elevated access once it expires.
+    fn expire_elevation(&mut self) {
+        if self.context.auth.elevated_until.is_some() && !self.context.auth.is_elevated() {
+            info!(target: SESSION, "Elevation of {} expired", self.context.auth.identity);
+            self.context.auth.elevated_until = None;
+        }
+    }
+
+    /// Welcome message of this session, evaluated as it opens, see [Builder::welcome](crate::Builder::welcome).
+    fn welcome(&self) -> String {
+        let settings = &self.inner.settings;
+        if let Some(welcome) = &settings.welcome_with {
+            return ensure_newline(welcome());
+        }
+        welcome::render(
+            &settings.welcome,
+            &welcome::Variables {
+                uptime: self.inner.started.elapsed(),
+                services: self.inner.subscriptions().entries().len(),
+            },
+        )
+    }
+
+    /// Sends the [Builder::prompt](crate::Builder::prompt), if any, to interactive sessions.
+    /// Programmatic clients and sessions receiving deltas are not sent any.
+    async fn prompt(&mut self) {
+        if self.machine || self.deltas || self.script {
+            return;
+        }
+        if let Some(prompt) = &self.inner.settings.prompt {
+            let prompt = Bytes::copy_from_slice(prompt.as_bytes());
+            self.send(Frame::Text(prompt)).await;
+        }
+    }
+
+    /// Interval of heartbeats, see [Builder::heartbeat](crate::Builder::heartbeat).
+    /// Only clients which negotiated a protocol version with heartbeats are sent any.
+    fn heartbeat(&self) -> Option<Duration> {
+        let (interval, _) = self.inner.settings.heartbeat?;
+        let version = self.context.protocol_version?;
+        (version >= HEARTBEAT_VERSION).then_some(interval)
+    }
+
+    /// Sends a heartbeat, returns `false` if the session is to be closed
+    /// for missing too many of them.
+    async fn ping(&mut self) -> bool {
+        let (_, max_missed) = self.inner.settings.heartbeat.unwrap_or_default();
+        if self.missed_pongs >= max_missed {
+            self.errors += 1;
+            warn!(target: SESSION, "{} missed {} heartbeats. Closing the session.", self.peer, self.missed_pongs);
+            self.outbox.abort();
+            return false;
+        }
+
+        self.missed_pongs += 1;
+        self.last_ping = Instant::now();
+        self.send(Control::Ping.to_frame()).await
+    }
+
+    /// Interval of keep-alives, see [Builder::keep_alive](crate::Builder::keep_alive).
+    /// Programmatic clients are not sent any.
+    fn keep_alive(&self) -> Option<Duration> {
+        self.inner
+            .settings
+            .keep_alive
+            .filter(|_| !self.machine && !self.script)
+    }
+
+    /// Sends a frame to the remote end of the session, returns `false` if it failed.
+    async fn send(&mut self, frame: Frame) -> bool {
+        // Programmatic clients get clean text, whatever subscriptions write.
+        let frame = match frame {
+            Frame::Text(bytes) if (self.machine || !self.colors) && bytes.contains(&ESC) => {
+                Frame::Text(strip_ansi_bytes(&bytes).into())
+            }
+            frame => frame,
+        };
+        let frame = match frame {
+            Frame::Text(bytes) if self.deltas => Frame::Text(self.encode_delta(bytes)),
+            frame => frame,
+        };
+        let frame = match &self.compressor {
+            Some(compressor) => compression::compress(frame, compressor.as_ref()),
+            None => frame,
+        };
+
+        match (&frame, self.chunk_size()) {
+            (Frame::Typed(bytes), Some(chunk_size)) if chunked::needs_split(&frame, chunk_size) => {
+                for chunk in chunked::split(bytes, chunk_size) {
+                    if !self.transmit(chunk).await {
+                        return false;
+                    }
+                }
+                true
+            }
+            _ => self.transmit(frame).await,
+        }
+    }
+
+    /// Size of chunks typed frames are split into, if the client reassembles them,
+    /// see [Builder::chunk_size](crate::Builder::chunk_size).
+    fn chunk_size(&self) -> Option<usize> {
+        let version = self.context.protocol_version?;
+        (version >= CHUNKED_VERSION).then(|| self.inner.settings.chunk_size.unwrap_or(CHUNK_SIZE))
+    }
+
+    /// Sends a frame as it is, returns `false` if it failed.
+    async fn transmit(&mut self, frame: Frame) -> bool {
+        #[cfg(feature = "fault-injection")]
+        let Some(frame) = (match &self.inner.settings.fault_injector {
+            Some(injector) => injector.apply(frame).await,
+            None => Some(frame),
+        }) else {
+            return true;
+        };
+
+        if !Control::is_control(&frame) {
+            self.registered.sent(match &frame {
+                Frame::Typed(bytes) | Frame::Text(bytes) => bytes.len(),
+            });
+        }
+        self.last_sent = Instant::now();
+
+        match self.outbox.push(frame).await {
+            Ok(()) => true,
+            Err(Rejected::Closed) => {
+                self.errors += 1;
+                debug!(target: SESSION, "Failed to send frame to {}: connection is closed", self.peer);
+                false
+            }
+            Err(Rejected::Overflow) => {
+                self.errors += 1;
+                warn!(target: SESSION, "{} reads too slowly, its write queue is full. Closing the session.", self.peer);
+                false
+            }
+        }
+    }
+
+    /// Logs and publishes the statistics of the closing session.
+    fn summarize(&self) {
+        let summary = SessionSummary {
+            peer: self.peer,
+            duration: self.started.elapsed(),
+            commands: self.commands,
+            bytes_in: self.registered.bytes_in(),
+            bytes_out: self.registered.bytes_out(),
+            errors: self.errors,
+        };
+
+        info!(target: SESSION, "Session closed: {summary}");
+        // Nobody may be listening, which is fine.
+        let _ = self.inner.events.send(ConsoleEvent::SessionClosed(summary));
+    }
+}
+
+/// Switches a session to running a piped runbook, see [Builder::script_command](crate::Builder::script_command).
+const SCRIPT_COMMAND: &str = "script";
+
+/// How long a session waits for [MACHINE_HELLO](crate::codec::MACHINE_HELLO) before sending the welcome message.
+const HELLO_GRACE: Duration = Duration::from_millis(50);
+
+/// Extracts the argument of `command` from `text`, returns `None` if `text` is not that command.
+fn argument<'a>(text: &'a str, command: &str) -> Option<&'a str> {
+    let argument = text.strip_prefix(command)?;
+    if !argument.is_empty() && !argument.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    Some(argument.trim())
+}
+
+/// Why `version` of `service_id` is not served, given the `supported` versions.
+fn version_notice<Services: ServiceId>(
+    service_id: &Services,
+    version: u32,
+    supported: &[u32],
+) -> String {
+    match supported {
+        [] => format!(
+            "Service {} is not versioned, version {version} is not supported",
+            service_id.name()
+        ),
+        _ => format!(
+            "Service {} does not support version {version}, supported versions: {}",
+            service_id.name(),
+            supported
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Runs `handler`, failing with [HandlerTimeout] if it does not complete within the optional `timeout`,
+/// and with [HandlerPanic] if it panics, which leaves the session unharmed.
+async fn supervise<T>(
+    timeout: Option<Duration>,
+    handler: impl Future<Output = Result<T, SubscriptionError>>,
+) -> Result<T, SubscriptionError> {
+    let handler = AssertUnwindSafe(handler).catch_unwind().map(|result| {
+        result.unwrap_or_else(|panic| {
+            let message = match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => panic
+                    .downcast_ref::<&str>()
+                    .map_or("unknown cause", |message| message)
+                    .to_owned(),
+            };
+            Err(HandlerPanic(message).into())
+        })
+    });
+    match timeout {
+        Some(timeout) => time::timeout(timeout, handler)
+            .await
+            .unwrap_or_else(|_| Err(HandlerTimeout(timeout).into())),
+        None => handler.await,
+    }
+}
+
+/// Completes once `timeout` has elapsed since `since`, never completes if there is no timeout.
+async fn elapsed(since: Instant, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => time::sleep_until((since + timeout).into()).await,
+        None => std::future::pending().await,
+    }
+}
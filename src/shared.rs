@@ -0,0 +1,187 @@
+//! A [Client] shared by concurrent tasks, see [SharedClient].
+
+use crate::client::{limit, Client, ClientConfig, ClientError};
+use crate::codec::{decode_payload, Frame};
+use crate::console::{Message, Reply, ReplyError};
+use crate::subscription::TypedSubscription;
+use crate::targets::CLIENT;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::ToSocketAddrs;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+/// Requests queued for the connection task before callers wait for room.
+const QUEUE_CAPACITY: usize = 64;
+
+/// Cheaply cloneable handle to a single connection to [Console](crate::Console), letting many tasks
+/// issue requests concurrently: a background task writes them and hands every reply to its
+/// caller by correlation id.
+///
+/// Free-form text cannot be told apart per caller, so it is not available over a shared client,
+/// and text or typed messages not answering a request are discarded.
+/// The background task ends, failing pending requests, when the connection fails
+/// or once all handles are dropped.
+#[derive(Clone)]
+pub struct SharedClient {
+    requests: mpsc::Sender<Request>,
+    next_id: Arc<AtomicU64>,
+    /// Limit on waiting for a reply, [ClientConfig::read_timeout] of the connection.
+    read_timeout: Option<Duration>,
+}
+
+/// Frame to write, with where to deliver the reply to if it is a request.
+struct Request {
+    frame: Frame,
+    reply: Option<(u64, Replied)>,
+}
+
+/// Delivers the reply to a request to its caller.
+type Replied = oneshot::Sender<Result<Option<Bytes>, ReplyError>>;
+
+impl SharedClient {
+    /// Connects to [Console](crate::Console) with `config`, see [Client::with_config].
+    pub async fn connect<A: ToSocketAddrs>(
+        address: A,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::new(Client::with_config(address, config).await?))
+    }
+
+    /// Shares `client`, which must have no request in flight, spawning its connection task.
+    pub fn new(mut client: Client) -> Self {
+        // The connection is read continuously, so the read timeout applies to every reply instead.
+        let read_timeout = client.config.read_timeout.take();
+        let (requests, queued) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(serve(client, queued));
+        Self {
+            requests,
+            next_id: Arc::new(AtomicU64::new(0)),
+            read_timeout,
+        }
+    }
+
+    /// Same as [Client::send].
+    pub async fn send<S: Serialize, M: Serialize>(
+        &self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<()> {
+        let frame = message_frame(Message::new(None, service_id, message)?)?;
+        self.queue(Request { frame, reply: None }).await
+    }
+
+    /// Same as [Client::send_recv], other tasks may send requests meanwhile.
+    pub async fn send_recv<S: Serialize, M: Serialize>(
+        &self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame = message_frame(Message::new(Some(id), service_id, message)?)?;
+        let (reply, replied) = oneshot::channel();
+        self.queue(Request {
+            frame,
+            reply: Some((id, reply)),
+        })
+        .await?;
+
+        let receive = async {
+            replied
+                .await
+                .map_err(|_| anyhow::anyhow!("Connection closed unexpectedly"))
+        };
+        let reply = limit(self.read_timeout, ClientError::ReadTimeout, receive).await?;
+        Ok(reply.map_err(ClientError::from)?)
+    }
+
+    /// Same as [Client::request].
+    pub async fn request<S: Serialize, M: Serialize, R: DeserializeOwned>(
+        &self,
+        service_id: S,
+        message: &M,
+    ) -> anyhow::Result<Option<R>> {
+        let Some(bytes) = self.send_recv(service_id, message).await? else {
+            return Ok(None);
+        };
+
+        let response =
+            decode_payload(bytes.as_ref()).map_err(|err| ClientError::UnexpectedResponse {
+                expected: std::any::type_name::<R>(),
+                reason: err.to_string(),
+            })?;
+        Ok(Some(response))
+    }
+
+    /// Same as [Client::call].
+    pub async fn call<T, S>(
+        &self,
+        service_id: S,
+        request: &T::Request,
+    ) -> anyhow::Result<Option<T::Response>>
+    where
+        T: TypedSubscription,
+        T::Request: Serialize,
+        T::Response: DeserializeOwned,
+        S: Serialize,
+    {
+        self.request(service_id, request).await
+    }
+
+    async fn queue(&self, request: Request) -> anyhow::Result<()> {
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection closed unexpectedly"))
+    }
+}
+
+/// Frame carrying `message`, built by the calling task rather than the connection task.
+fn message_frame<S: Serialize>(message: Message<S>) -> anyhow::Result<Frame> {
+    Ok(Frame::Typed(bcs::to_bytes(&message)?.into()))
+}
+
+/// Writes queued requests to the connection of `client` and delivers the replies,
+/// until the connection fails or no handle is left.
+async fn serve(mut client: Client, mut queued: mpsc::Receiver<Request>) {
+    let mut pending = HashMap::new();
+    loop {
+        tokio::select! {
+            request = queued.recv() => {
+                let Some(Request { frame, reply }) = request else {
+                    debug!(target: CLIENT, "Every shared client handle is dropped, closing the connection");
+                    return;
+                };
+                pending.extend(reply);
+                // Also flushes a heartbeat answer interrupted by the request arriving.
+                if let Err(err) = client.write_frame(frame).await {
+                    warn!(target: CLIENT, "Failed to send a request: {err}. Closing the connection.");
+                    return;
+                }
+            }
+            frame = client.read_frame() => match frame {
+                Ok(Frame::Typed(bytes)) => match bcs::from_bytes::<Reply>(bytes.as_ref()) {
+                    Ok(Reply { id: Some(id), bytes, error }) => match pending.remove(&id) {
+                        // The caller may have given up waiting.
+                        Some(reply) => {
+                            let _ = reply.send(error.map_or(Ok(bytes), Err));
+                        }
+                        None => debug!(target: CLIENT, "Discarding a reply to an unknown request {id}"),
+                    },
+                    Ok(_) => debug!(target: CLIENT, "Discarding a typed message not answering a request"),
+                    Err(err) => warn!(target: CLIENT, "Received typed frame is not a valid reply: {err}"),
+                },
+                Ok(Frame::Text(_)) => debug!(target: CLIENT, "Discarding text received by a shared client"),
+                Err(err) => {
+                    warn!(target: CLIENT, "Failed to receive a reply: {err}. Closing the connection.");
+                    return;
+                }
+            }
+        }
+    }
+}
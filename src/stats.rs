@@ -0,0 +1,263 @@
+use crate::metrics::Metric;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, the last bucket is unbounded.
+const BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+/// Usage of a service since the console started, see [Console::service_stats](crate::Console::service_stats).
+#[derive(Debug, Clone, Default)]
+pub struct ServiceStats {
+    pub service: String,
+    /// Messages handled by the subscription, including failed ones.
+    /// Free-form messages only count for the subscription answering them.
+    pub calls: u64,
+    /// Messages the subscription failed to handle.
+    pub errors: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// Distribution of handling times over fixed buckets from 1ms to 5s.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// Counts per bucket of [LatencyHistogram::bounds], plus one for slower calls.
+    counts: [u64; BUCKETS.len() + 1],
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    /// Upper bounds of the buckets, the last bucket holding slower calls is unbounded.
+    pub fn bounds() -> &'static [Duration] {
+        &BUCKETS
+    }
+
+    /// Number of calls per bucket, one more than [LatencyHistogram::bounds].
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count())
+            .ok()
+            .filter(|count| *count > 0)?;
+        Some(self.total / count)
+    }
+
+    /// Upper bound of the bucket holding the `quantile` (between 0 and 1) of calls,
+    /// `None` if there were no calls or they fall in the unbounded bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((count as f64 * quantile.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(self.counts) {
+            seen += bucket;
+            if seen >= rank {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+    }
+}
+
+impl Display for ServiceStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} calls, {} errors",
+            self.service, self.calls, self.errors
+        )?;
+        if let Some(mean) = self.latency.mean() {
+            write!(f, ", mean {mean:?}")?;
+        }
+        for (label, quantile) in [("p50", 0.5), ("p99", 0.99)] {
+            match self.latency.quantile(quantile) {
+                Some(bound) => write!(f, ", {label} <= {bound:?}")?,
+                None if self.latency.count() > 0 => {
+                    write!(f, ", {label} > {:?}", BUCKETS[BUCKETS.len() - 1])?
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage of the console since it started, see [Console::metrics](crate::Console::metrics).
+#[derive(Debug, Clone, Default)]
+pub struct ConsoleMetrics {
+    /// Sessions opened, over accepted or dialed connections and multiplexed channels.
+    pub accepted_connections: u64,
+    /// Connections refused for their network, their rate or the session limit.
+    pub rejected_connections: u64,
+    pub active_sessions: usize,
+    /// Typed and free-form messages received, built-in commands included.
+    pub messages: u64,
+    /// Messages subscriptions failed to handle.
+    pub handler_errors: u64,
+    /// Messages per service, see [Console::service_stats](crate::Console::service_stats).
+    pub services: Vec<ServiceStats>,
+}
+
+impl ConsoleMetrics {
+    /// Figures as [Metric]s named `tcp_console_*`, for exporters.
+    /// Those of services are labelled with the service name.
+    pub fn to_metrics(&self) -> Vec<Metric> {
+        let mut metrics = vec![
+            Metric::counter(
+                "tcp_console_accepted_connections",
+                self.accepted_connections as f64,
+            )
+            .with_help("Sessions opened"),
+            Metric::counter(
+                "tcp_console_rejected_connections",
+                self.rejected_connections as f64,
+            )
+            .with_help("Connections refused"),
+            Metric::gauge("tcp_console_active_sessions", self.active_sessions as f64)
+                .with_help("Sessions currently open"),
+            Metric::counter("tcp_console_messages", self.messages as f64)
+                .with_help("Messages received"),
+            Metric::counter("tcp_console_handler_errors", self.handler_errors as f64)
+                .with_help("Messages subscriptions failed to handle"),
+        ];
+        for service in &self.services {
+            metrics.push(
+                Metric::counter("tcp_console_service_calls", service.calls as f64)
+                    .with_help("Messages handled per service")
+                    .with_label("service", &service.service),
+            );
+            metrics.push(
+                Metric::counter("tcp_console_service_errors", service.errors as f64)
+                    .with_help("Messages services failed to handle")
+                    .with_label("service", &service.service),
+            );
+        }
+        metrics
+    }
+}
+
+/// Statistics of the console and of all services by name, updated by the accepting task and sessions.
+#[derive(Default)]
+pub(crate) struct Stats {
+    services: Mutex<HashMap<String, ServiceStats>>,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    messages: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn record_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn metrics(&self, active_sessions: usize) -> ConsoleMetrics {
+        let services = self.snapshot();
+        ConsoleMetrics {
+            accepted_connections: self.accepted.load(Ordering::Relaxed),
+            rejected_connections: self.rejected.load(Ordering::Relaxed),
+            active_sessions,
+            messages: self.messages.load(Ordering::Relaxed),
+            handler_errors: services.iter().map(|service| service.errors).sum(),
+            services,
+        }
+    }
+
+    /// Records a message handled by `service` in `latency`.
+    pub(crate) fn record(&self, service: &str, latency: Duration, failed: bool) {
+        let mut services = self.services.lock().expect("Stats lock is never poisoned");
+        let stats = services
+            .entry(service.to_owned())
+            .or_insert_with(|| ServiceStats {
+                service: service.to_owned(),
+                ..ServiceStats::default()
+            });
+
+        stats.calls += 1;
+        stats.errors += u64::from(failed);
+        stats.latency.record(latency);
+    }
+
+    /// Statistics of services called at least once, ordered by name.
+    pub(crate) fn snapshot(&self) -> Vec<ServiceStats> {
+        let mut snapshot = self
+            .services
+            .lock()
+            .expect("Stats lock is never poisoned")
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        snapshot.sort_unstable_by(|a, b| a.service.cmp(&b.service));
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyHistogram, ServiceStats};
+    use std::time::Duration;
+
+    #[test]
+    fn histogram_reports_bucket_bounds() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for millis in [0, 1, 3, 4, 240, 7_000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.counts(), [2, 2, 0, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(500)));
+        assert_eq!(histogram.quantile(0.99), None);
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(1_208)));
+
+        let stats = ServiceStats {
+            service: "exec".to_owned(),
+            calls: 6,
+            errors: 1,
+            latency: histogram,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "exec: 6 calls, 1 errors, mean 1.208s, p50 <= 5ms, p99 > 5s"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+
+/// Escape opening every ANSI control sequence.
+pub(crate) const ESC: u8 = 0x1B;
+
+/// Command turning colors on or off for the session.
+pub(crate) const COLOR_COMMAND: &str = "color";
+
+/// Text rendered with ANSI colors and attributes, e.g. `Styled::red("DOWN").bold()`.
+///
+/// Subscriptions write styled output once: the console strips the escapes for programmatic
+/// clients and for sessions which turned colors off, see [Builder::color_command](crate::Builder::color_command).
+#[derive(Debug, Clone)]
+pub struct Styled<T> {
+    content: T,
+    /// SGR parameters, e.g. `31` for red.
+    codes: Vec<u8>,
+}
+
+impl<T: Display> Styled<T> {
+    fn new(content: T, code: u8) -> Self {
+        Self {
+            content,
+            codes: vec![code],
+        }
+    }
+
+    pub fn red(content: T) -> Self {
+        Self::new(content, 31)
+    }
+
+    pub fn green(content: T) -> Self {
+        Self::new(content, 32)
+    }
+
+    pub fn yellow(content: T) -> Self {
+        Self::new(content, 33)
+    }
+
+    pub fn blue(content: T) -> Self {
+        Self::new(content, 34)
+    }
+
+    pub fn magenta(content: T) -> Self {
+        Self::new(content, 35)
+    }
+
+    pub fn cyan(content: T) -> Self {
+        Self::new(content, 36)
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.codes.push(1);
+        self
+    }
+
+    pub fn dim(mut self) -> Self {
+        self.codes.push(2);
+        self
+    }
+
+    pub fn underline(mut self) -> Self {
+        self.codes.push(4);
+        self
+    }
+}
+
+impl<T: Display> Display for Styled<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\x1b[")?;
+        for (index, code) in self.codes.iter().enumerate() {
+            if index > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{code}")?;
+        }
+        write!(f, "m{}\x1b[0m", self.content)
+    }
+}
+
+/// Removes ANSI escape sequences from `text`, leaving everything else untouched.
+pub fn strip_ansi(text: &str) -> String {
+    String::from_utf8(strip_ansi_bytes(text.as_bytes()))
+        .expect("Escape sequences are ASCII, removing them keeps UTF-8 valid")
+}
+
+/// Removes ANSI escape sequences: control sequences `ESC [ parameters final`
+/// and two-byte escapes `ESC x`.
+pub(crate) fn strip_ansi_bytes(text: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(text.len());
+    let mut bytes = text.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != ESC {
+            stripped.push(byte);
+            continue;
+        }
+
+        // A lone escape before a multibyte character leaves the character alone.
+        if bytes.next_if(u8::is_ascii) == Some(b'[') {
+            // Parameters and intermediates run up to a final byte between `@` and `~`.
+            for byte in bytes.by_ref() {
+                if (0x40..=0x7E).contains(&byte) {
+                    break;
+                }
+            }
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_ansi, Styled};
+
+    #[test]
+    fn styles_render_and_strip() {
+        let status = format!(
+            "db: {}, cache: {}",
+            Styled::red("DOWN").bold(),
+            Styled::green("UP")
+        );
+        assert_eq!(
+            status,
+            "db: \x1b[31;1mDOWN\x1b[0m, cache: \x1b[32mUP\x1b[0m"
+        );
+        assert_eq!(strip_ansi(&status), "db: DOWN, cache: UP");
+        assert_eq!(strip_ansi("\x1b[2Kdone\x1b7 ✓"), "done ✓");
+    }
+}
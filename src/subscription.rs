@@ -1,5 +1,17 @@
+use crate::codec::{decode_payload, encode_payload};
+use crate::console::Error;
+use crate::context::Context;
+use crate::ensure_newline;
+use crate::service::ServiceId;
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 
 #[async_trait]
 /// Trait describing how incoming messages on [Console] must be handled.
@@ -13,10 +25,485 @@ pub trait Subscription {
     ///
     /// Returns an optional [String], which, if provided, will be sent back to the message sender.
     async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError>;
+
+    /// Handles strongly-typed messages with access to the session [Context]:
+    /// who is calling, since when, and the dependencies shared by the host.
+    ///
+    /// This is what [Console](crate::Console) calls, by default it delegates to [Subscription::handle].
+    async fn handle_with(
+        &self,
+        context: &Context,
+        message: Bytes,
+    ) -> Result<Option<Bytes>, SubscriptionError> {
+        let _ = context;
+        self.handle(message).await
+    }
+
+    /// Handles free-form text messages with access to the session [Context].
+    ///
+    /// This is what [Console](crate::Console) calls, by default it delegates to [Subscription::weak_handle].
+    async fn weak_handle_with(
+        &self,
+        context: &Context,
+        message: &str,
+    ) -> Result<Option<String>, SubscriptionError> {
+        let _ = context;
+        self.weak_handle(message).await
+    }
+
+    /// Handles free-form text messages producing their response over time, e.g. log dumps,
+    /// every chunk of the returned stream is sent as is as soon as it is produced.
+    ///
+    /// This is what [Console](crate::Console) calls, by default it sends the response
+    /// of [Subscription::weak_handle_with] as a single chunk.
+    async fn weak_handle_stream(
+        &self,
+        context: &Context,
+        message: &str,
+    ) -> Result<Option<ReplyStream>, SubscriptionError> {
+        let reply = self.weak_handle_with(context, message).await?;
+        Ok(reply.map(|reply| {
+            let chunk = Bytes::from(ensure_newline(reply));
+            stream::once(async move { chunk }).boxed()
+        }))
+    }
+
+    /// One-line description listed by the `help` command, see [Builder::with_help](crate::Builder::with_help).
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// How long handling a message may take before the sender is told it timed out,
+    /// overriding [Builder::handler_timeout](crate::Builder::handler_timeout) for this subscription.
+    fn handler_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether messages to the subscription change state, so that they are refused
+    /// while the console is in [Mode::ReadOnly](crate::Mode::ReadOnly).
+    fn is_mutating(&self) -> bool {
+        false
+    }
+
+    /// Checks the configuration of the subscription, e.g. that the files it serves exist,
+    /// when the console is built or the subscription is registered at runtime,
+    /// so that misconfigured handlers fail at startup rather than at the first command.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Probes the subscription for the `selftest` command, see [Builder::selftest](crate::Builder::selftest).
+    ///
+    /// By default a registered subscription is considered healthy.
+    async fn health(&self) -> Result<(), SubscriptionError> {
+        Ok(())
+    }
+}
+
+/// [Subscription] written against the types of its messages instead of bytes: requests are decoded
+/// from BCS before [TypedSubscription::handle] and responses encoded after it. Every type implementing
+/// it is a [Subscription], messages which do not decode into [TypedSubscription::Request] fail
+/// with a [TypedError] like any other handler error.
+#[async_trait]
+pub trait TypedSubscription: Send + Sync {
+    type Request: DeserializeOwned + Send;
+    type Response: Serialize + Send;
+
+    /// Handles strongly-typed messages, returning an optional response sent back to the sender.
+    async fn handle(
+        &self,
+        request: Self::Request,
+    ) -> Result<Option<Self::Response>, SubscriptionError>;
+
+    /// Like [Subscription::handle_with], by default it delegates to [TypedSubscription::handle].
+    async fn handle_with(
+        &self,
+        context: &Context,
+        request: Self::Request,
+    ) -> Result<Option<Self::Response>, SubscriptionError> {
+        let _ = context;
+        TypedSubscription::handle(self, request).await
+    }
+
+    /// Like [Subscription::weak_handle], free-form messages are ignored by default.
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        let _ = message;
+        Ok(None)
+    }
+
+    /// Like [Subscription::description].
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// Like [Subscription::handler_timeout].
+    fn handler_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Like [Subscription::is_mutating].
+    fn is_mutating(&self) -> bool {
+        false
+    }
+
+    /// Like [Subscription::validate].
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Like [Subscription::health].
+    async fn health(&self) -> Result<(), SubscriptionError> {
+        Ok(())
+    }
+}
+
+/// Why a [TypedSubscription] could not handle a message.
+#[derive(Debug, Error)]
+pub enum TypedError {
+    #[error("Message is not a valid {expected}: {source}")]
+    Request {
+        expected: &'static str,
+        source: bcs::Error,
+    },
+    #[error("Response {kind} cannot be serialized: {source}")]
+    Response {
+        kind: &'static str,
+        source: bcs::Error,
+    },
+}
+
+/// Error a subscription returns, as its [SubscriptionError], for senders to react to programmatically:
+/// typed requests carrying a correlation id are answered with it, and [Client](crate::Client) surfaces it
+/// as [ClientError::Failed](crate::ClientError::Failed). Requests failing with other errors are answered
+/// with [HANDLER_FAILED], the error itself only reaches senders as text, see
+/// [Builder::error_reporting](crate::Builder::error_reporting).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Error)]
+#[error("{message} ({code})")]
+pub struct ConsoleError {
+    /// Stable identifier of the failure, e.g. `block_not_found`.
+    pub code: String,
+    /// Human-readable description.
+    pub message: String,
+    /// Anything else the sender may need, e.g. the block height that was asked for.
+    pub details: Option<String>,
+}
+
+impl ConsoleError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// [ConsoleError::code] requests are answered with when their subscription fails with another error.
+pub const HANDLER_FAILED: &str = "handler_failed";
+
+/// Error of a subscription still handling a message when its timeout elapses,
+/// see [Builder::handler_timeout](crate::Builder::handler_timeout).
+#[derive(Debug, Error)]
+#[error("Handler timed out after {0:?}")]
+pub struct HandlerTimeout(pub Duration);
+
+/// Error of a subscription panicking while handling a message, carrying the panic message.
+/// The panic is caught, so that the session goes on serving further messages.
+#[derive(Debug, Error)]
+#[error("Handler panicked: {0}")]
+pub struct HandlerPanic(pub String);
+
+#[async_trait]
+impl<T: TypedSubscription> Subscription for T {
+    async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        let request = decode::<T>(&message)?;
+        encode::<T>(TypedSubscription::handle(self, request).await?)
+    }
+
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        TypedSubscription::weak_handle(self, message).await
+    }
+
+    async fn handle_with(
+        &self,
+        context: &Context,
+        message: Bytes,
+    ) -> Result<Option<Bytes>, SubscriptionError> {
+        let request = decode::<T>(&message)?;
+        encode::<T>(TypedSubscription::handle_with(self, context, request).await?)
+    }
+
+    fn description(&self) -> Option<&str> {
+        TypedSubscription::description(self)
+    }
+
+    fn handler_timeout(&self) -> Option<Duration> {
+        TypedSubscription::handler_timeout(self)
+    }
+
+    fn is_mutating(&self) -> bool {
+        TypedSubscription::is_mutating(self)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        TypedSubscription::validate(self)
+    }
+
+    async fn health(&self) -> Result<(), SubscriptionError> {
+        TypedSubscription::health(self).await
+    }
+}
+
+fn decode<T: TypedSubscription>(message: &[u8]) -> Result<T::Request, TypedError> {
+    decode_payload(message).map_err(|source| TypedError::Request {
+        expected: std::any::type_name::<T::Request>(),
+        source,
+    })
+}
+
+fn encode<T: TypedSubscription>(
+    response: Option<T::Response>,
+) -> Result<Option<Bytes>, SubscriptionError> {
+    let Some(response) = response else {
+        return Ok(None);
+    };
+    let bytes = encode_payload(&response).map_err(|source| TypedError::Response {
+        kind: std::any::type_name::<T::Response>(),
+        source,
+    })?;
+    Ok(Some(bytes))
 }
 
 /// Convenience type to abstract away concrete implementations of [Subscription] errors.
 pub type SubscriptionError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Response produced over time by [Subscription::weak_handle_stream].
+pub type ReplyStream = BoxStream<'static, Bytes>;
+
 /// Convenience type to abstract away concrete implementations of [Subscription].
 pub(crate) type BoxedSubscription = Box<dyn Subscription + Send + Sync>;
+
+/// Registered subscriptions kept by descending priority, then in registration order.
+///
+/// Entries are shared, so that the registry can be cheaply copied on write
+/// while sessions keep dispatching to the copy they started with.
+pub(crate) struct Subscriptions<Services> {
+    entries: Vec<Arc<(Services, BoxedSubscription)>>,
+    /// Priority of each entry, see [Builder::subscribe_with_priority](crate::Builder::subscribe_with_priority).
+    priorities: Vec<i32>,
+    /// Subscriptions answering typed messages for a version of a service,
+    /// see [Builder::subscribe_version](crate::Builder::subscribe_version).
+    versions: Vec<Arc<(Services, u32, BoxedSubscription)>>,
+    /// Subscription receiving messages for services without one,
+    /// see [Builder::default_subscription](crate::Builder::default_subscription).
+    fallback: Option<Arc<BoxedSubscription>>,
+}
+
+impl<Services: PartialEq> Subscriptions<Services> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            priorities: Vec::new(),
+            versions: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    pub(crate) fn contains(&self, service_id: &Services) -> bool {
+        self.get(service_id).is_some()
+    }
+
+    /// Inserts a subscription after all those of the same or a higher priority,
+    /// the caller must ensure `service_id` is not registered yet.
+    pub(crate) fn push(
+        &mut self,
+        service_id: Services,
+        priority: i32,
+        subscription: BoxedSubscription,
+    ) {
+        let index = self.priorities.partition_point(|other| *other >= priority);
+        self.entries
+            .insert(index, Arc::new((service_id, subscription)));
+        self.priorities.insert(index, priority);
+    }
+
+    /// Registers a subscription for `version` of `service_id`,
+    /// the caller must ensure that version is not registered yet.
+    pub(crate) fn push_version(
+        &mut self,
+        service_id: Services,
+        version: u32,
+        subscription: BoxedSubscription,
+    ) {
+        self.versions
+            .push(Arc::new((service_id, version, subscription)));
+    }
+
+    pub(crate) fn contains_version(&self, service_id: &Services, version: u32) -> bool {
+        self.get_version(service_id, version).is_some()
+    }
+
+    /// Removes the subscription of `service_id` and all its versions, returns `false` if there were none.
+    pub(crate) fn remove(&mut self, service_id: &Services) -> bool {
+        let versions = self.versions.len();
+        self.versions.retain(|entry| &entry.0 != service_id);
+        let Some(index) = self.entries.iter().position(|entry| &entry.0 == service_id) else {
+            return self.versions.len() < versions;
+        };
+        self.entries.remove(index);
+        self.priorities.remove(index);
+        true
+    }
+
+    /// Swaps the subscription of `service_id` for `subscription`, keeping its priority,
+    /// the caller must ensure `service_id` is registered.
+    pub(crate) fn replace(&mut self, service_id: Services, subscription: BoxedSubscription) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.0 == service_id) {
+            *entry = Arc::new((service_id, subscription));
+        }
+    }
+
+    pub(crate) fn get(&self, service_id: &Services) -> Option<&BoxedSubscription> {
+        self.entries
+            .iter()
+            .find(|entry| &entry.0 == service_id)
+            .map(|entry| &entry.1)
+    }
+
+    pub(crate) fn get_version(
+        &self,
+        service_id: &Services,
+        version: u32,
+    ) -> Option<&BoxedSubscription> {
+        self.versions
+            .iter()
+            .find(|entry| &entry.0 == service_id && entry.1 == version)
+            .map(|entry| &entry.2)
+    }
+
+    /// Subscription answering typed messages for `version` of `service_id`. Messages without
+    /// a version go to the unversioned subscription, or else to the latest version.
+    pub(crate) fn resolve(
+        &self,
+        service_id: &Services,
+        version: Option<u32>,
+    ) -> Option<&BoxedSubscription> {
+        match version {
+            Some(version) => self.get_version(service_id, version),
+            None => self.get(service_id).or_else(|| {
+                let latest = self.versions(service_id).pop()?;
+                self.get_version(service_id, latest)
+            }),
+        }
+    }
+
+    /// Subscription a typed message is dispatched to: the one [Subscriptions::resolve] finds,
+    /// or the fallback if `service_id` has no subscription at all.
+    pub(crate) fn route(
+        &self,
+        service_id: &Services,
+        version: Option<u32>,
+    ) -> Option<&BoxedSubscription> {
+        self.resolve(service_id, version).or_else(|| {
+            if self.is_registered(service_id) {
+                return None;
+            }
+            self.fallback()
+        })
+    }
+
+    pub(crate) fn set_fallback(&mut self, subscription: BoxedSubscription) {
+        self.fallback = Some(Arc::new(subscription));
+    }
+
+    pub(crate) fn fallback(&self) -> Option<&BoxedSubscription> {
+        self.fallback.as_deref()
+    }
+
+    /// Whether `service_id` has a subscription, unversioned or for any version.
+    pub(crate) fn is_registered(&self, service_id: &Services) -> bool {
+        self.contains(service_id) || self.versions.iter().any(|entry| &entry.0 == service_id)
+    }
+
+    /// Versions registered for `service_id`, in ascending order.
+    pub(crate) fn versions(&self, service_id: &Services) -> Vec<u32> {
+        let mut versions = self
+            .versions
+            .iter()
+            .filter(|entry| &entry.0 == service_id)
+            .map(|entry| entry.1)
+            .collect::<Vec<_>>();
+        versions.sort_unstable();
+        versions
+    }
+
+    /// Services with a subscription, those of [Subscriptions::entries] first,
+    /// then those with versioned subscriptions only.
+    pub(crate) fn services(&self) -> Vec<&Services> {
+        let mut services = self
+            .entries
+            .iter()
+            .map(|entry| &entry.0)
+            .collect::<Vec<_>>();
+        for entry in &self.versions {
+            if !services.contains(&&entry.0) {
+                services.push(&entry.0);
+            }
+        }
+        services
+    }
+
+    /// Subscriptions by descending priority, then in registration order.
+    pub(crate) fn entries(&self) -> &[Arc<(Services, BoxedSubscription)>] {
+        &self.entries
+    }
+}
+
+impl<Services: ServiceId> Subscriptions<Services> {
+    /// Validates all subscriptions, reporting every failure at once.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let unversioned = self.entries.iter().map(|entry| {
+            let (service_id, subscription) = entry.as_ref();
+            (service_id.name().to_owned(), subscription)
+        });
+        let versioned = self.versions.iter().map(|entry| {
+            let (service_id, version, subscription) = entry.as_ref();
+            (format!("{}@{version}", service_id.name()), subscription)
+        });
+        let fallback = self
+            .fallback()
+            .map(|subscription| ("default".to_owned(), subscription));
+        let failures = unversioned
+            .chain(versioned)
+            .chain(fallback)
+            .filter_map(|(name, subscription)| {
+                subscription
+                    .validate()
+                    .err()
+                    .map(|reason| format!("{name}: {reason}"))
+            })
+            .collect::<Vec<_>>();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidSubscriptions(failures))
+        }
+    }
+}
+
+impl<Services> Clone for Subscriptions<Services> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            priorities: self.priorities.clone(),
+            versions: self.versions.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! Tracing targets used by the crate, e.g., `RUST_LOG=tcp_console::dispatch=warn,tcp_console::accept=debug`.
+
+/// Accepting and vetting incoming connections.
+pub const ACCEPT: &str = "tcp_console::accept";
+
+/// Session lifecycle: welcome, idling, closing.
+pub const SESSION: &str = "tcp_console::session";
+
+/// Routing messages to subscriptions.
+pub const DISPATCH: &str = "tcp_console::dispatch";
+
+/// Client side of the connection.
+pub const CLIENT: &str = "tcp_console::client";
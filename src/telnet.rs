@@ -0,0 +1,173 @@
+//! Telnet option negotiation, see [Builder::telnet](crate::Builder::telnet).
+//!
+//! Telnet clients interleave `IAC` sequences with the text typed by the operator.
+//! They are stripped before text is framed, and answered so that the client stays in line mode:
+//! it echoes and edits lines locally and sends them once complete.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// "Interpret as command", opening every sequence. Doubled, it stands for a literal 255 byte.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+/// Opens a subnegotiation, closed by `IAC SE`.
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// Option letting the client edit lines locally, see RFC 1184.
+const LINEMODE: u8 = 34;
+/// Subnegotiation of [LINEMODE] setting its mode.
+const LINEMODE_MODE: u8 = 1;
+/// Mode of [LINEMODE] in which the client edits lines before sending them.
+const MODE_EDIT: u8 = 1;
+
+/// Longest unterminated subnegotiation held back, the options negotiated fit in far less.
+const MAX_SUBNEGOTIATION: usize = 256;
+
+/// Negotiation state of a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Telnet {
+    linemode: bool,
+    /// Answers to send to the client.
+    replies: BytesMut,
+}
+
+impl Telnet {
+    /// Removes the sequences found in the text `src[..limit]`, returns the limit of the remaining text.
+    ///
+    /// An incomplete trailing sequence is held back until the rest arrives,
+    /// unless `complete` tells that nothing more will follow the text.
+    pub(crate) fn strip(&mut self, src: &mut BytesMut, mut limit: usize, complete: bool) -> usize {
+        let mut at = 0;
+        while at < limit {
+            match src[at] {
+                IAC => {}
+                // Telnet terminates lines with CR NUL or CR LF, the NUL is padding.
+                b'\r' if at + 1 < limit && src[at + 1] == 0 => {
+                    remove(src, at + 1, 1);
+                    limit -= 1;
+                    at += 1;
+                    continue;
+                }
+                _ => {
+                    at += 1;
+                    continue;
+                }
+            }
+
+            let Some(len) = sequence_len(&src[at..limit]) else {
+                if complete {
+                    // Garbage cut short, e.g. by a typed frame.
+                    remove(src, at, limit - at);
+                } else if limit - at > MAX_SUBNEGOTIATION {
+                    // A subnegotiation which never ends would hold back all text after it.
+                    remove(src, at, MAX_SUBNEGOTIATION);
+                    limit -= MAX_SUBNEGOTIATION;
+                    continue;
+                }
+                return at;
+            };
+
+            if src[at + 1] == IAC {
+                // An escaped literal 255 byte stays in the text.
+                remove(src, at, 1);
+                limit -= 1;
+                at += 1;
+                continue;
+            }
+
+            self.answer(&src[at + 1..at + len]);
+            remove(src, at, len);
+            limit -= len;
+        }
+        limit
+    }
+
+    pub(crate) fn has_replies(&self) -> bool {
+        !self.replies.is_empty()
+    }
+
+    /// Takes the answers to negotiations seen so far.
+    pub(crate) fn take_replies(&mut self) -> Option<Bytes> {
+        (!self.replies.is_empty()).then(|| self.replies.split().freeze())
+    }
+
+    /// Answers a sequence without its leading `IAC`.
+    fn answer(&mut self, sequence: &[u8]) {
+        match *sequence {
+            // The console performs no option, including echoing, which the client keeps doing.
+            [DO, option] => self.replies.put_slice(&[IAC, WONT, option]),
+            [WILL, LINEMODE] if !self.linemode => {
+                self.linemode = true;
+                self.replies.put_slice(&[IAC, DO, LINEMODE]);
+                self.replies
+                    .put_slice(&[IAC, SB, LINEMODE, LINEMODE_MODE, MODE_EDIT, IAC, SE]);
+            }
+            [WILL, LINEMODE] => {}
+            [WILL, option] => self.replies.put_slice(&[IAC, DONT, option]),
+            [WONT, LINEMODE] => self.linemode = false,
+            // Options the client stops or refuses are off already, subnegotiations
+            // and commands such as `NOP` need no answer.
+            _ => {}
+        }
+    }
+}
+
+/// Length of the sequence at the start of `src`, `None` if it is incomplete.
+fn sequence_len(src: &[u8]) -> Option<usize> {
+    match *src.get(1)? {
+        WILL..=DONT => src.get(2).map(|_| 3),
+        SB => src
+            .windows(2)
+            .skip(2)
+            .position(|window| window == [IAC, SE])
+            .map(|end| end + 4),
+        _ => Some(2),
+    }
+}
+
+/// Removes `len` bytes of `src` starting at `at`.
+fn remove(src: &mut BytesMut, at: usize, len: usize) {
+    src.copy_within(at + len.., at);
+    src.truncate(src.len() - len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Telnet, MAX_SUBNEGOTIATION};
+    use bytes::BytesMut;
+
+    #[test]
+    fn negotiations_are_stripped_and_answered() {
+        let mut telnet = Telnet::default();
+        // DO ECHO, WILL LINEMODE, a subnegotiation, an escaped 255, CR NUL and a cut sequence.
+        let mut src = BytesMut::from(
+            &b"\xFF\xFD\x01st\xFF\xFB\x22at\xFF\xFA\x22\x03\x01\xFF\xF0us\xFF\xFF\r\0\n\xFF\xFA"[..],
+        );
+
+        let len = src.len();
+        let limit = telnet.strip(&mut src, len, false);
+        assert_eq!(&src[..limit], b"status\xFF\r\n");
+        assert_eq!(&src[limit..], b"\xFF\xFA");
+        assert_eq!(
+            telnet.take_replies().as_deref(),
+            Some(&b"\xFF\xFC\x01\xFF\xFD\x22\xFF\xFA\x22\x01\x01\xFF\xF0"[..])
+        );
+        assert_eq!(telnet.take_replies(), None);
+    }
+
+    #[test]
+    fn unterminated_subnegotiations_are_dropped() {
+        let mut telnet = Telnet::default();
+        let mut src = BytesMut::from(&b"\xFF\xFA\x18"[..]);
+        src.extend_from_slice(&[0; MAX_SUBNEGOTIATION - 3]);
+        src.extend_from_slice(b"status\n");
+
+        let len = src.len();
+        let limit = telnet.strip(&mut src, len, false);
+        assert_eq!(&src[..limit], b"status\n");
+        assert_eq!(telnet.take_replies(), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! Helpers for testing subscriptions against a running [Console], e.g. from the tests of the host application.
+
+use crate::builder::{Builder, Subscribed};
+use crate::client::Client;
+use crate::console::Console;
+use crate::service::ServiceId;
+use crate::transcript::Mismatch;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::net::Ipv4Addr;
+
+/// Address of consoles spawned by [spawn_ephemeral]: a port of the loopback interface chosen by the OS.
+pub type Ephemeral = (Ipv4Addr, u16);
+
+/// Builds the console configured by `builder` on an ephemeral loopback port, replacing any
+/// bind address, spawns it and connects a [Client] to it. Tests running in parallel never
+/// compete for a port.
+pub async fn spawn_ephemeral<Services, A>(
+    builder: Builder<Services, A, Subscribed>,
+) -> anyhow::Result<(Console<Services, Ephemeral>, Client)>
+where
+    Services: ServiceId + DeserializeOwned + Send + Sync + 'static,
+{
+    let mut console = builder.bind_address((Ipv4Addr::LOCALHOST, 0)).build()?;
+    let address = console.spawn().await?;
+    let client = Client::new(address, None).await?;
+    Ok((console, client))
+}
+
+/// Sends the free-form `command` and asserts the console answers `expected`.
+pub async fn assert_reply(
+    client: &mut Client,
+    command: &str,
+    expected: &str,
+) -> anyhow::Result<()> {
+    client.weak_send(command).await?;
+    let reply = client.weak_read().await?;
+    assert_eq!(reply, expected, "Unexpected reply to `{command}`");
+    Ok(())
+}
+
+/// Sends a typed `message` to `service_id` and asserts the console answers `expected`,
+/// `None` standing for a request acknowledged without a response.
+pub async fn assert_response<S, M, R>(
+    client: &mut Client,
+    service_id: S,
+    message: &M,
+    expected: Option<R>,
+) -> anyhow::Result<()>
+where
+    S: Serialize,
+    M: Serialize,
+    R: DeserializeOwned + PartialEq + Debug,
+{
+    let response = client.request::<S, M, R>(service_id, message).await?;
+    assert_eq!(response, expected, "Unexpected response to a typed request");
+    Ok(())
+}
+
+/// Free-form commands replayed in order by a scripted client, each with the reply it should get,
+/// e.g. `Script::new().expect("status", "ok").send("reset").expect("status", "ok")`.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<(String, Option<String>)>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `command` and compares its reply with `reply`.
+    pub fn expect(mut self, command: &str, reply: &str) -> Self {
+        self.steps
+            .push((command.to_owned(), Some(reply.to_owned())));
+        self
+    }
+
+    /// Sends `command` and waits for its reply, whatever it is.
+    pub fn send(mut self, command: &str) -> Self {
+        self.steps.push((command.to_owned(), None));
+        self
+    }
+
+    /// Replays the commands over `client`, waiting for the reply to each before sending the next one.
+    /// Returns the replies differing from the expected ones, indexed by step, empty if all match.
+    pub async fn run(&self, client: &mut Client) -> anyhow::Result<Vec<Mismatch>> {
+        let mut mismatches = Vec::new();
+        for (index, (command, expected)) in self.steps.iter().enumerate() {
+            client.weak_send(command).await?;
+            let actual = client.weak_read().await?;
+            if let Some(expected) = expected.as_ref().filter(|expected| **expected != actual) {
+                mismatches.push(Mismatch {
+                    index,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_reply, assert_response, spawn_ephemeral, Script};
+    use crate::service::TestService;
+    use crate::{Mismatch, Subscription, SubscriptionError};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Counts `inc` commands, typed messages read the count.
+    #[derive(Default)]
+    struct Counter(AtomicU32);
+
+    #[async_trait]
+    impl Subscription for Counter {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(Some(bcs::to_bytes(&self.0.load(Ordering::Relaxed))?.into()))
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(
+                (message == "inc")
+                    .then(|| (self.0.fetch_add(1, Ordering::Relaxed) + 1).to_string()),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn scripts_replay_against_ephemeral_consoles() -> anyhow::Result<()> {
+        let builder = crate::Builder::new().subscribe(TestService::One, Counter::default())?;
+        let (console, mut client) = spawn_ephemeral(builder).await?;
+
+        assert_reply(&mut client, "inc", "1").await?;
+        let mismatches = Script::new()
+            .send("inc")
+            .expect("inc", "3")
+            .expect("inc", "5")
+            .run(&mut client)
+            .await?;
+        assert_eq!(
+            mismatches,
+            [Mismatch {
+                index: 2,
+                expected: "5".to_owned(),
+                actual: "4".to_owned(),
+            }]
+        );
+        assert_response(&mut client, TestService::One, &(), Some(4u32)).await?;
+
+        console.stop();
+
+        Ok(())
+    }
+}
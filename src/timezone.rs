@@ -0,0 +1,506 @@
+//! Time zones sessions render timestamps in, set with the `tz <zone>` command,
+//! see [Builder::time_zones](crate::Builder::time_zones).
+//!
+//! Zones are `UTC`, fixed offsets such as `+02:00`, or IANA names such as `Europe/Berlin`
+//! read from the system time zone database.
+
+use crate::history::{format_local, unix_secs};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Command setting the time zone of the session.
+pub(crate) const TZ_COMMAND: &str = "tz";
+
+/// Location of the system time zone database.
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Offset from UTC in effect over some period, with its abbreviation, e.g. `CEST`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LocalTime {
+    offset: i32,
+    abbreviation: String,
+}
+
+/// A time zone able to convert instants to local time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TimeZone {
+    name: String,
+    /// Instants from which `local[index]` applies, in ascending order.
+    transitions: Vec<(i64, usize)>,
+    /// Local times of the zone, the first applies before the first transition.
+    local: Vec<LocalTime>,
+    /// Applies after the last transition.
+    rule: Option<Rule>,
+}
+
+impl TimeZone {
+    pub(crate) fn utc() -> Self {
+        Self::fixed("UTC".to_owned(), 0)
+    }
+
+    /// Parses `UTC`, a fixed offset `±HH[:MM]` or the IANA name of a zone of the system database.
+    pub(crate) fn parse(name: &str) -> Result<Self, String> {
+        if name.eq_ignore_ascii_case("utc") {
+            return Ok(Self::utc());
+        }
+        if let Some(offset) = parse_fixed_offset(name) {
+            return Ok(Self::fixed(name.to_owned(), offset));
+        }
+
+        // Names are paths into the database, never let them escape it.
+        let valid = !name.is_empty()
+            && name
+                .split('/')
+                .all(|part| !part.is_empty() && !part.starts_with('.'))
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c));
+        if !valid {
+            return Err(format!("Unknown time zone {name}"));
+        }
+
+        let data = std::fs::read(Path::new(ZONEINFO).join(name))
+            .map_err(|_| format!("Unknown time zone {name}"))?;
+        Self::from_tzif(name, &data).ok_or_else(|| format!("Unreadable time zone {name}"))
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Formats `at` as `YYYY-MM-DD HH:MM:SS <abbreviation>` in local time.
+    pub(crate) fn format(&self, at: SystemTime) -> String {
+        let secs = unix_secs(at);
+        let local = self.local_time(secs);
+        format_local(secs + i64::from(local.offset), &local.abbreviation)
+    }
+
+    fn fixed(name: String, offset: i32) -> Self {
+        let abbreviation = if offset == 0 {
+            "UTC".to_owned()
+        } else {
+            name.clone()
+        };
+        Self {
+            name,
+            transitions: Vec::new(),
+            local: vec![LocalTime {
+                offset,
+                abbreviation,
+            }],
+            rule: None,
+        }
+    }
+
+    fn local_time(&self, secs: i64) -> LocalTime {
+        let after = self.transitions.partition_point(|(at, _)| *at <= secs);
+        match (after, &self.rule) {
+            (after, Some(rule)) if after == self.transitions.len() => rule.local_time(secs),
+            (0, _) => self.local[0].clone(),
+            (after, _) => self.local[self.transitions[after - 1].1].clone(),
+        }
+    }
+
+    /// Reads a TZif file, see RFC 8536, preferring the 64-bit data of version 2 and later.
+    fn from_tzif(name: &str, data: &[u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+        let mut header = reader.header()?;
+        let mut time_size = 4;
+        if header.version >= b'2' {
+            reader.skip(header.v1_len())?;
+            header = reader.header()?;
+            time_size = 8;
+        }
+
+        let times = (0..header.time_count)
+            .map(|_| reader.time(time_size))
+            .collect::<Option<Vec<_>>>()?;
+        let indices = reader.take(header.time_count)?.to_vec();
+        let types = (0..header.type_count)
+            .map(|_| {
+                let offset = i32::from_be_bytes(reader.take(4)?.try_into().ok()?);
+                let _dst = reader.take(1)?;
+                let abbreviation = reader.take(1)?[0] as usize;
+                Some((offset, abbreviation))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let abbreviations = reader.take(header.char_count)?;
+        reader.skip(header.leap_count * (time_size + 4) + header.std_count + header.ut_count)?;
+
+        let local = types
+            .iter()
+            .map(|(offset, start)| {
+                let rest = abbreviations.get(*start..)?;
+                let end = rest.iter().position(|c| *c == 0)?;
+                Some(LocalTime {
+                    offset: *offset,
+                    abbreviation: String::from_utf8_lossy(&rest[..end]).into_owned(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if local.is_empty() || indices.iter().any(|index| *index as usize >= local.len()) {
+            return None;
+        }
+
+        // The footer of version 2 files describes the time after the last transition.
+        let rule = if time_size == 8 {
+            let footer = String::from_utf8_lossy(reader.0);
+            Rule::parse(footer.trim())
+        } else {
+            None
+        };
+
+        Some(Self {
+            name: name.to_owned(),
+            transitions: times
+                .into_iter()
+                .zip(indices.into_iter().map(usize::from))
+                .collect(),
+            local,
+            rule,
+        })
+    }
+}
+
+struct Header {
+    version: u8,
+    ut_count: usize,
+    std_count: usize,
+    leap_count: usize,
+    time_count: usize,
+    type_count: usize,
+    char_count: usize,
+}
+
+impl Header {
+    /// Length of the version 1 data block following the header.
+    fn v1_len(&self) -> usize {
+        self.time_count * 5
+            + self.type_count * 6
+            + self.char_count
+            + self.leap_count * 8
+            + self.std_count
+            + self.ut_count
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?) as usize)
+    }
+
+    fn time(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.take(size)?;
+        Some(match size {
+            4 => i64::from(i32::from_be_bytes(bytes.try_into().ok()?)),
+            _ => i64::from_be_bytes(bytes.try_into().ok()?),
+        })
+    }
+
+    fn header(&mut self) -> Option<Header> {
+        if self.take(4)? != b"TZif" {
+            return None;
+        }
+        let version = self.take(1)?[0];
+        self.skip(15)?;
+        Some(Header {
+            version,
+            ut_count: self.count()?,
+            std_count: self.count()?,
+            leap_count: self.count()?,
+            time_count: self.count()?,
+            type_count: self.count()?,
+            char_count: self.count()?,
+        })
+    }
+}
+
+/// A POSIX `TZ` rule such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    standard: LocalTime,
+    /// Daylight saving time with the days it starts and ends, each with the local time of day.
+    daylight: Option<(LocalTime, Switch, Switch)>,
+}
+
+/// Day a rule switches on, with the local time of day in seconds.
+type Switch = (Day, i64);
+
+/// Day of a year a POSIX rule switches on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Day {
+    /// `Jn`: day 1 to 365, February 29 is never counted.
+    Julian(u32),
+    /// `n`: day 0 to 365, counting February 29 in leap years.
+    Ordinal(u32),
+    /// `Mm.w.d`: weekday `d` (0 is Sunday) of week `w` (5 is the last) of month `m`.
+    Weekday { month: u32, week: u32, weekday: u32 },
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Option<Self> {
+        let mut rest = rule;
+        let standard_name = take_name(&mut rest)?;
+        let standard_offset = -take_offset(&mut rest)?;
+        let standard = LocalTime {
+            offset: standard_offset,
+            abbreviation: standard_name,
+        };
+        if rest.is_empty() {
+            return Some(Self {
+                standard,
+                daylight: None,
+            });
+        }
+
+        let daylight_name = take_name(&mut rest)?;
+        let daylight_offset = match rest.starts_with(',') {
+            true => standard_offset + 3600,
+            false => -take_offset(&mut rest)?,
+        };
+        let (start, end) = rest.strip_prefix(',')?.split_once(',')?;
+
+        Some(Self {
+            standard,
+            daylight: Some((
+                LocalTime {
+                    offset: daylight_offset,
+                    abbreviation: daylight_name,
+                },
+                parse_switch(start)?,
+                parse_switch(end)?,
+            )),
+        })
+    }
+
+    fn local_time(&self, secs: i64) -> LocalTime {
+        let Some((daylight, start, end)) = &self.daylight else {
+            return self.standard.clone();
+        };
+
+        let (year, _, _) = crate::history::civil_from_days(secs.div_euclid(86_400));
+        // Daylight saving starts in standard time and ends in daylight saving time.
+        let starts = switch_instant(year, *start) - i64::from(self.standard.offset);
+        let ends = switch_instant(year, *end) - i64::from(daylight.offset);
+        let in_daylight = if starts < ends {
+            starts <= secs && secs < ends
+        } else {
+            // Southern hemisphere, daylight saving spans the new year.
+            !(ends <= secs && secs < starts)
+        };
+
+        match in_daylight {
+            true => daylight.clone(),
+            false => self.standard.clone(),
+        }
+    }
+}
+
+/// Takes an abbreviation, either alphabetic or quoted in angle brackets like `<+03>`.
+fn take_name(rest: &mut &str) -> Option<String> {
+    let (name, remainder) = match rest.strip_prefix('<') {
+        Some(quoted) => {
+            let (name, remainder) = quoted.split_once('>')?;
+            (name, remainder)
+        }
+        None => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            rest.split_at(end)
+        }
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    *rest = remainder;
+    Some(name.to_owned())
+}
+
+/// Takes a time `[±]hh[:mm[:ss]]` in seconds.
+fn take_offset(rest: &mut &str) -> Option<i32> {
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || ":+-".contains(c)))
+        .unwrap_or(rest.len());
+    let (time, remainder) = rest.split_at(end);
+    *rest = remainder;
+    parse_time(time).and_then(|secs| i32::try_from(secs).ok())
+}
+
+fn parse_time(time: &str) -> Option<i64> {
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, time.strip_prefix('+').unwrap_or(time)),
+    };
+
+    let mut secs = 0;
+    let mut parts = 0;
+    for (part, scale) in time.split(':').zip([3600, 60, 1]) {
+        secs += part.parse::<i64>().ok()? * scale;
+        parts += 1;
+    }
+    (parts > 0 && time.split(':').count() == parts).then_some(sign * secs)
+}
+
+/// Parses a switch `day[/time]`, the time defaulting to 02:00.
+fn parse_switch(switch: &str) -> Option<Switch> {
+    let (day, time) = match switch.split_once('/') {
+        Some((day, time)) => (day, parse_time(time)?),
+        None => (switch, 7200),
+    };
+
+    let day = if let Some(day) = day.strip_prefix('J') {
+        Day::Julian(day.parse().ok().filter(|day| (1..=365).contains(day))?)
+    } else if let Some(day) = day.strip_prefix('M') {
+        let mut fields = day.split('.').map(str::parse::<u32>);
+        let (Some(Ok(month)), Some(Ok(week)), Some(Ok(weekday)), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return None;
+        };
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return None;
+        }
+        Day::Weekday {
+            month,
+            week,
+            weekday,
+        }
+    } else {
+        Day::Ordinal(day.parse().ok().filter(|day| *day <= 365)?)
+    };
+    Some((day, time))
+}
+
+/// Local seconds since the epoch at which a rule switches in `year`.
+fn switch_instant(year: i64, (day, time): Switch) -> i64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let january_first = days_from_civil(year, 1, 1);
+    let days = match day {
+        Day::Julian(day) => january_first + i64::from(day) - 1 + i64::from(leap && day >= 60),
+        Day::Ordinal(day) => january_first + i64::from(day),
+        Day::Weekday {
+            month,
+            week,
+            weekday,
+        } => {
+            let first = days_from_civil(year, month, 1);
+            // 1970-01-01 was a Thursday.
+            let first_weekday = (first + 4).rem_euclid(7);
+            let mut day = first
+                + (i64::from(weekday) - first_weekday).rem_euclid(7)
+                + 7 * (i64::from(week) - 1);
+            let next_month = match month {
+                12 => days_from_civil(year + 1, 1, 1),
+                month => days_from_civil(year, month + 1, 1),
+            };
+            while day >= next_month {
+                day -= 7;
+            }
+            day
+        }
+    };
+    days * 86_400 + time
+}
+
+/// Converts a proleptic Gregorian date to days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parses `±HH[:MM]` or `±HHMM` into seconds east of UTC.
+fn parse_fixed_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+    if hours.is_empty() || hours.len() > 2 || !hours.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours = hours.parse::<i32>().ok().filter(|hours| *hours <= 14)?;
+    let minutes = minutes
+        .parse::<i32>()
+        .ok()
+        .filter(|minutes| *minutes < 60)?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rule, TimeZone};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn rules_switch_to_daylight_saving() {
+        let zone = TimeZone {
+            name: "Europe/Berlin".to_owned(),
+            transitions: Vec::new(),
+            local: Vec::new(),
+            rule: Rule::parse("CET-1CEST,M3.5.0,M10.5.0/3"),
+        };
+
+        // 2024-03-31 00:59:59 UTC, a second before clocks went forward.
+        let at = UNIX_EPOCH + Duration::from_secs(1_711_846_799);
+        assert_eq!(zone.format(at), "2024-03-31 01:59:59 CET");
+        assert_eq!(
+            zone.format(at + Duration::from_secs(1)),
+            "2024-03-31 03:00:00 CEST"
+        );
+        // 2024-10-27 01:00:00 UTC, when clocks went back.
+        let at = UNIX_EPOCH + Duration::from_secs(1_729_990_800);
+        assert_eq!(
+            zone.format(at - Duration::from_secs(1)),
+            "2024-10-27 02:59:59 CEST"
+        );
+        assert_eq!(zone.format(at), "2024-10-27 02:00:00 CET");
+
+        let sydney = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        // 2024-01-15 00:00:00 UTC falls in the southern summer.
+        assert_eq!(sydney.local_time(1_705_276_800).abbreviation, "AEDT");
+        assert_eq!(sydney.local_time(1_720_000_000).abbreviation, "AEST");
+    }
+
+    #[test]
+    fn fixed_offsets_and_names() {
+        let at = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(
+            TimeZone::parse("utc").unwrap().format(at),
+            "2000-02-29 12:34:56 UTC"
+        );
+        assert_eq!(
+            TimeZone::parse("-05:30").unwrap().format(at),
+            "2000-02-29 07:04:56 -05:30"
+        );
+        assert_eq!(
+            TimeZone::parse("+0200").unwrap().format(at),
+            "2000-02-29 14:34:56 +0200"
+        );
+        assert!(TimeZone::parse("+25:00").is_err());
+        assert!(TimeZone::parse("../../etc/passwd").is_err());
+        assert!(TimeZone::parse("Nowhere/Special").is_err());
+    }
+}
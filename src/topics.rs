@@ -0,0 +1,81 @@
+use crate::codec::Frame;
+use crate::targets::SESSION;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Command subscribing the session to a topic.
+pub(crate) const SUBSCRIBE_COMMAND: &str = "subscribe";
+/// Command unsubscribing the session from a topic.
+pub(crate) const UNSUBSCRIBE_COMMAND: &str = "unsubscribe";
+
+/// Reserved topic the console publishes its lifecycle events to: `draining` when
+/// [Console::shutdown](crate::Console::shutdown) starts, `stopping` on [Console::stop](crate::Console::stop).
+/// Host applications may publish their own events to it, e.g. before draining themselves.
+pub const LIFECYCLE_TOPIC: &str = "console.lifecycle";
+
+/// Push queues of the subscribed sessions by topic and session id.
+type Subscribers = HashMap<String, HashMap<u64, mpsc::Sender<Frame>>>;
+
+/// Sessions subscribed to each topic, see [Builder::topics](crate::Builder::topics).
+#[derive(Default)]
+pub(crate) struct Topics {
+    subscribers: Mutex<Subscribers>,
+}
+
+impl Topics {
+    /// Subscribes a session to `topic`, returns `false` if it already was.
+    pub(crate) fn subscribe(
+        &self,
+        topic: &str,
+        session_id: u64,
+        push: mpsc::Sender<Frame>,
+    ) -> bool {
+        self.lock()
+            .entry(topic.to_owned())
+            .or_default()
+            .insert(session_id, push)
+            .is_none()
+    }
+
+    /// Unsubscribes a session from `topic`, returns `false` if it was not subscribed.
+    pub(crate) fn unsubscribe(&self, topic: &str, session_id: u64) -> bool {
+        let mut subscribers = self.lock();
+        let Some(sessions) = subscribers.get_mut(topic) else {
+            return false;
+        };
+
+        let removed = sessions.remove(&session_id).is_some();
+        if sessions.is_empty() {
+            subscribers.remove(topic);
+        }
+        removed
+    }
+
+    /// Queues `frame` to every session subscribed to `topic`,
+    /// returns the number of sessions it was queued for.
+    pub(crate) fn publish(&self, topic: &str, frame: Frame) -> usize {
+        let subscribers = self.lock();
+        let Some(sessions) = subscribers.get(topic) else {
+            return 0;
+        };
+
+        sessions
+            .iter()
+            .filter(|(session_id, push)| match push.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(err) => {
+                    warn!(target: SESSION, "Failed to publish {topic} to session {session_id}: {err}");
+                    false
+                }
+            })
+            .count()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Subscribers> {
+        self.subscribers
+            .lock()
+            .expect("Topics lock is never poisoned")
+    }
+}
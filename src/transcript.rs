@@ -0,0 +1,490 @@
+use crate::targets::CLIENT;
+use crate::Client;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::time;
+use tracing::debug;
+
+/// A recorded free-form session: commands sent to [Console](crate::Console) and replies received,
+/// each with its offset from the start of the session.
+///
+/// The textual form has one entry per line: `<milliseconds> > <command>` for commands
+/// and `<milliseconds> < <reply>` for replies, with newlines in the text escaped as `\n`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    entries: Vec<Entry>,
+}
+
+/// A single recorded step of a [Transcript].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Offset from the start of the session.
+    pub at: Duration,
+    pub direction: Direction,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A command sent to [Console](crate::Console).
+    Sent,
+    /// A reply received from [Console](crate::Console).
+    Received,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+}
+
+impl Transcript {
+    /// Encrypts the transcript with `cipher` for storage, entry by entry, see [SealedEntries].
+    pub fn seal<C: TranscriptCipher + ?Sized>(
+        &self,
+        cipher: &C,
+    ) -> Result<Vec<u8>, TranscriptError> {
+        let mut sealed = Vec::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            sealed.extend(entry.seal(index + 1, cipher)?);
+        }
+        Ok(sealed)
+    }
+
+    /// Decrypts a transcript sealed with [Transcript::seal] or assembled from [Entry::seal].
+    pub fn unseal<C: TranscriptCipher + ?Sized>(
+        sealed: &[u8],
+        cipher: &C,
+    ) -> Result<Self, TranscriptError> {
+        let entries = SealedEntries::new(sealed, cipher).collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+}
+
+impl Entry {
+    /// Encrypts the entry with `cipher`, so that a journal can be appended to one entry at a time.
+    /// `sequence` is the position of the entry in the journal, starting at 1. It is sealed along
+    /// with the entry, so that entries dropped, reordered or replayed are detected by [SealedEntries].
+    pub fn seal<C: TranscriptCipher + ?Sized>(
+        &self,
+        sequence: usize,
+        cipher: &C,
+    ) -> Result<Vec<u8>, TranscriptError> {
+        let ciphertext = cipher
+            .encrypt(format!("{sequence} {self}").as_bytes())
+            .map_err(TranscriptError::Cipher)?;
+        let len = u32::try_from(ciphertext.len()).map_err(|_| TranscriptError::TooLong)?;
+
+        let mut sealed = Vec::with_capacity(4 + ciphertext.len());
+        sealed.extend(len.to_be_bytes());
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// Parses the textual form of an entry found at `line`.
+    fn parse(line: &str, index: usize) -> Result<Self, TranscriptError> {
+        let malformed = || TranscriptError::Malformed(index);
+
+        let (millis, rest) = line.split_once(' ').ok_or_else(malformed)?;
+        let millis = millis.parse::<u64>().map_err(|_| malformed())?;
+        let (direction, text) = match rest.split_at_checked(1) {
+            Some((">", text)) => (Direction::Sent, text),
+            Some(("<", text)) => (Direction::Received, text),
+            _ => return Err(malformed()),
+        };
+
+        Ok(Entry {
+            at: Duration::from_millis(millis),
+            direction,
+            text: unescape(text.strip_prefix(' ').unwrap_or(text)),
+        })
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        };
+        let text = self.text.replace('\\', "\\\\").replace('\n', "\\n");
+        write!(f, "{} {arrow} {text}", self.at.as_millis())
+    }
+}
+
+impl Display for Transcript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{entry}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Transcript {
+    type Err = TranscriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut transcript = Transcript::new();
+
+        for (index, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            transcript.push(Entry::parse(line, index + 1)?);
+        }
+
+        Ok(transcript)
+    }
+}
+
+/// Encrypts and decrypts transcripts at rest, as they may contain sensitive command arguments.
+///
+/// The host implements it with the scheme and key of its choice, e.g. AES-GCM with a fresh nonce
+/// prepended to every ciphertext, or age. Every entry is encrypted separately.
+pub trait TranscriptCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError>;
+
+    /// Fails if `ciphertext` was not produced by [TranscriptCipher::encrypt] with the same key.
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError>;
+}
+
+/// Convenience type to abstract away concrete implementations of [TranscriptCipher] errors.
+pub type CipherError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Decrypts the entries of a sealed transcript one at a time, e.g. to scan a large journal for audits.
+///
+/// A sealed transcript is a sequence of entries, each a big-endian `u32` length
+/// followed by the ciphertext of the position of the entry and its textual form.
+/// An entry found out of its position fails with [TranscriptError::OutOfSequence].
+pub struct SealedEntries<'a, C: ?Sized> {
+    sealed: &'a [u8],
+    cipher: &'a C,
+    index: usize,
+}
+
+impl<'a, C: TranscriptCipher + ?Sized> SealedEntries<'a, C> {
+    pub fn new(sealed: &'a [u8], cipher: &'a C) -> Self {
+        Self {
+            sealed,
+            cipher,
+            index: 0,
+        }
+    }
+}
+
+impl<C: TranscriptCipher + ?Sized> Iterator for SealedEntries<'_, C> {
+    type Item = Result<Entry, TranscriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sealed.is_empty() {
+            return None;
+        }
+        self.index += 1;
+
+        let entry = (|| {
+            let (len, rest) = self
+                .sealed
+                .split_first_chunk::<4>()
+                .ok_or(TranscriptError::Truncated(self.index))?;
+            let len = u32::from_be_bytes(*len) as usize;
+            let (ciphertext, rest) = rest
+                .split_at_checked(len)
+                .ok_or(TranscriptError::Truncated(self.index))?;
+            self.sealed = rest;
+
+            let plaintext = self
+                .cipher
+                .decrypt(ciphertext)
+                .map_err(TranscriptError::Cipher)?;
+            let line =
+                String::from_utf8(plaintext).map_err(|_| TranscriptError::Malformed(self.index))?;
+            let (sequence, line) = line
+                .split_once(' ')
+                .and_then(|(sequence, line)| Some((sequence.parse::<usize>().ok()?, line)))
+                .ok_or(TranscriptError::Malformed(self.index))?;
+            if sequence != self.index {
+                return Err(TranscriptError::OutOfSequence {
+                    expected: self.index,
+                    found: sequence,
+                });
+            }
+            Entry::parse(line, self.index)
+        })();
+
+        // Nothing sensible follows a truncated entry.
+        if matches!(entry, Err(TranscriptError::Truncated(_))) {
+            self.sealed = &[];
+        }
+        Some(entry)
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Records a [Transcript] with timing relative to its creation.
+pub struct Recorder {
+    started: Instant,
+    transcript: Transcript,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            transcript: Transcript::new(),
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction, text: &str) {
+        self.transcript.push(Entry {
+            at: self.started.elapsed(),
+            direction,
+            text: text.to_owned(),
+        });
+    }
+
+    pub fn finish(self) -> Transcript {
+        self.transcript
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A reply which differs from the recorded one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the transcript entry.
+    pub index: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Error)]
+pub enum TranscriptError {
+    #[error("Malformed transcript entry at line {0}")]
+    Malformed(usize),
+    #[error("Sealed transcript is truncated at entry {0}")]
+    Truncated(usize),
+    #[error("Sealed transcript has entry {found} in place of entry {expected}")]
+    OutOfSequence { expected: usize, found: usize },
+    #[error("Transcript entry is too long to seal")]
+    TooLong,
+    #[error("Transcript cipher failed: {0}")]
+    Cipher(CipherError),
+}
+
+impl Client {
+    /// Plays back `transcript` against [Console](crate::Console).
+    ///
+    /// Commands are sent with their original timing divided by `speed`,
+    /// e.g., `2.0` replays twice as fast and `f64::INFINITY` sends commands without any delay.
+    /// Every recorded reply is awaited and compared with the actual one,
+    /// the returned list of mismatches is empty if the console behaved exactly as recorded.
+    pub async fn replay(
+        &mut self,
+        transcript: &Transcript,
+        speed: f64,
+    ) -> anyhow::Result<Vec<Mismatch>> {
+        anyhow::ensure!(speed > 0.0, "Replay speed must be positive");
+
+        let started = time::Instant::now();
+        let mut mismatches = Vec::new();
+
+        for (index, entry) in transcript.entries().iter().enumerate() {
+            match entry.direction {
+                Direction::Sent => {
+                    time::sleep_until(started + entry.at.div_f64(speed)).await;
+                    debug!(target: CLIENT, "Replaying `{}`", entry.text);
+                    self.weak_send(&entry.text).await?;
+                }
+                Direction::Received => {
+                    let actual = self.weak_read().await?;
+                    if actual != entry.text.trim() {
+                        mismatches.push(Mismatch {
+                            index,
+                            expected: entry.text.clone(),
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::TestService;
+    use crate::transcript::{
+        CipherError, Direction, Entry, Recorder, SealedEntries, Transcript, TranscriptCipher,
+        TranscriptError,
+    };
+    use crate::{Subscription, SubscriptionError};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn replay_detects_divergence() -> anyhow::Result<()> {
+        let mut console = crate::Builder::new()
+            .bind_address((Ipv4Addr::LOCALHOST, 0))
+            .subscribe(TestService::One, Upper)?
+            .build()?;
+        let address = console.spawn().await?;
+
+        let mut recorder = Recorder::new();
+        recorder.record(Direction::Sent, "hello");
+        recorder.record(Direction::Received, "HELLO");
+        recorder.record(Direction::Sent, "bye");
+        recorder.record(Direction::Received, "multi\\line\nreply");
+
+        // Transcripts survive a round trip through their textual form.
+        let transcript = recorder.finish().to_string().parse::<Transcript>()?;
+
+        let mut client = crate::Client::new(address, None).await?;
+        let mismatches = client.replay(&transcript, f64::INFINITY).await?;
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 3);
+        assert_eq!(mismatches[0].expected, "multi\\line\nreply");
+        assert_eq!(mismatches[0].actual, "BYE");
+
+        console.stop();
+
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_transcripts_need_the_key() -> anyhow::Result<()> {
+        let mut transcript = Transcript::new();
+        for (millis, direction, text) in [
+            (0, Direction::Sent, "auth secret"),
+            (12, Direction::Received, "Authenticated\nWelcome"),
+        ] {
+            transcript.push(Entry {
+                at: Duration::from_millis(millis),
+                direction,
+                text: text.to_owned(),
+            });
+        }
+
+        let sealed = transcript.seal(&Xor(7))?;
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(Transcript::unseal(&sealed, &Xor(7))?, transcript);
+
+        // Entries can be read one by one, a wrong key fails every one of them.
+        let entries = SealedEntries::new(&sealed, &Xor(8)).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0], Err(TranscriptError::Cipher(_))));
+
+        let truncated = &sealed[..sealed.len() - 1];
+        assert!(matches!(
+            Transcript::unseal(truncated, &Xor(7)),
+            Err(TranscriptError::Truncated(2))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_entries_keep_their_order() -> anyhow::Result<()> {
+        let entries = ["status", "restart", "status"].map(|text| Entry {
+            at: Duration::ZERO,
+            direction: Direction::Sent,
+            text: text.to_owned(),
+        });
+        let sealed = entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| entry.seal(index + 1, &Xor(7)))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            Transcript::unseal(&sealed.concat(), &Xor(7))?.entries(),
+            entries
+        );
+
+        // Dropped, reordered and replayed entries are all out of sequence.
+        for tampered in [
+            [&sealed[0], &sealed[2]].map(Vec::as_slice).concat(),
+            [&sealed[1], &sealed[0]].map(Vec::as_slice).concat(),
+            [&sealed[0], &sealed[0]].map(Vec::as_slice).concat(),
+        ] {
+            assert!(matches!(
+                Transcript::unseal(&tampered, &Xor(7)),
+                Err(TranscriptError::OutOfSequence { .. })
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Toy cipher tagging ciphertexts with its key to detect a wrong one.
+    struct Xor(u8);
+
+    impl TranscriptCipher for Xor {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+            let mut ciphertext = vec![self.0];
+            ciphertext.extend(plaintext.iter().map(|byte| byte ^ self.0));
+            Ok(ciphertext)
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError> {
+            match ciphertext.split_first() {
+                Some((key, rest)) if *key == self.0 => {
+                    Ok(rest.iter().map(|byte| byte ^ self.0).collect())
+                }
+                _ => Err("Wrong key".into()),
+            }
+        }
+    }
+
+    struct Upper;
+
+    #[async_trait]
+    impl Subscription for Upper {
+        async fn handle(&self, _message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+            Ok(None)
+        }
+
+        async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+            Ok(Some(message.to_uppercase()))
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Placeholders of the welcome message, see [Builder::welcome](crate::Builder::welcome).
+
+use std::time::Duration;
+
+/// Values of the placeholders, taken when a session opens.
+pub(crate) struct Variables {
+    pub(crate) uptime: Duration,
+    pub(crate) services: usize,
+}
+
+/// Replaces `{hostname}`, `{version}`, `{uptime}` and `{services}` in `template`,
+/// leaving any other text in braces as is.
+pub(crate) fn render(template: &str, variables: &Variables) -> String {
+    if !template.contains('{') {
+        return template.to_owned();
+    }
+
+    let mut rendered = template.to_owned();
+    if rendered.contains("{hostname}") {
+        rendered = rendered.replace("{hostname}", &hostname());
+    }
+    rendered
+        .replace("{version}", env!("CARGO_PKG_VERSION"))
+        .replace("{uptime}", &format_uptime(variables.uptime))
+        .replace("{services}", &variables.services.to_string())
+}
+
+/// Name of the host, as known to the kernel.
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .into_iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Formats `uptime` as e.g. `2d 3h 4m 5s`, omitting leading zero units.
+pub(crate) fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let units = [
+        (secs / 86_400, "d"),
+        (secs % 86_400 / 3600, "h"),
+        (secs % 3600 / 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let first = units
+        .iter()
+        .position(|(value, _)| *value > 0)
+        .unwrap_or(units.len() - 1);
+    units[first..]
+        .iter()
+        .map(|(value, unit)| format!("{value}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Variables};
+    use std::time::Duration;
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let variables = Variables {
+            uptime: Duration::from_secs(90_061),
+            services: 3,
+        };
+        assert_eq!(
+            render(
+                "Up {uptime}, {services} services, v{version} {unknown}",
+                &variables
+            ),
+            format!(
+                "Up 1d 1h 1m 1s, 3 services, v{} {{unknown}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            render(
+                "{uptime}",
+                &Variables {
+                    uptime: Duration::ZERO,
+                    services: 0
+                }
+            ),
+            "0s"
+        );
+    }
+}
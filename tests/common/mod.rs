@@ -0,0 +1,79 @@
+//! Services and helpers shared by the integration tests.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tcp_console::{Builder, Client, NoAddress, Subscribed, Subscription, SubscriptionError};
+use tokio::time;
+
+tcp_console::console_services! {
+    pub enum Services {
+        Echo = "echo" => Echo,
+        Slow = "slow" => Slow,
+    }
+}
+
+/// Answers typed messages with their payload, and `echo <text>` with the text.
+pub struct Echo;
+
+#[async_trait]
+impl Subscription for Echo {
+    async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        Ok(Some(message))
+    }
+
+    async fn weak_handle(&self, message: &str) -> Result<Option<String>, SubscriptionError> {
+        Ok(message.strip_prefix("echo ").map(str::to_owned))
+    }
+}
+
+/// Number of typed messages [Slow] started handling, across all consoles of the test binary.
+static SLOW_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Answers typed messages carrying a number of milliseconds with `done` after that long.
+pub struct Slow;
+
+impl Slow {
+    /// Number of typed messages started handling so far, see [SLOW_STARTED].
+    pub fn started() -> usize {
+        SLOW_STARTED.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Subscription for Slow {
+    async fn handle(&self, message: Bytes) -> Result<Option<Bytes>, SubscriptionError> {
+        let millis: u64 = bcs::from_bytes(&message)?;
+        SLOW_STARTED.fetch_add(1, Ordering::SeqCst);
+        time::sleep(Duration::from_millis(millis)).await;
+        Ok(Some(bcs::to_bytes("done")?.into()))
+    }
+
+    async fn weak_handle(&self, _message: &str) -> Result<Option<String>, SubscriptionError> {
+        Ok(None)
+    }
+}
+
+/// Builder with all [Services] subscribed, to be spawned with [tcp_console::testing::spawn_ephemeral].
+pub fn builder() -> Builder<Services, NoAddress, Subscribed> {
+    Services::subscribe_all(Builder::new())
+}
+
+/// Connects `count` clients concurrently.
+pub async fn connect_many(address: SocketAddr, count: usize) -> anyhow::Result<Vec<Client>> {
+    let connects = (0..count).map(|_| Client::new(address, None));
+    futures_util::future::try_join_all(connects).await
+}
+
+/// Waits until `condition` holds, failing after five seconds.
+pub async fn eventually(condition: impl Fn() -> bool) -> anyhow::Result<()> {
+    time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
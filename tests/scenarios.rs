@@ -0,0 +1,110 @@
+//! Multi-client scenarios against a console listening on an ephemeral port.
+
+mod common;
+
+use common::{builder, connect_many, eventually, Services, Slow};
+use std::time::Duration;
+use tcp_console::testing::spawn_ephemeral;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn concurrent_clients_get_their_own_replies() -> anyhow::Result<()> {
+    let (console, client) = spawn_ephemeral(builder()).await?;
+    let address = console.local_addr().expect("Console is spawned");
+    let mut clients = connect_many(address, 15).await?;
+    clients.push(client);
+
+    let sessions = clients.into_iter().enumerate().map(|(index, mut client)| {
+        tokio::spawn(async move {
+            for round in 0..10 {
+                // Pipelined typed requests interleaved with free-form ones.
+                let first = client
+                    .send_request(Services::Echo, &format!("{index}/{round}/typed"))
+                    .await?;
+                let second = client.send_request(Services::Echo, &round).await?;
+                client
+                    .weak_send(&format!("echo {index}/{round}/weak"))
+                    .await?;
+
+                let reply = client.recv_response(second).await?.expect("Echo replies");
+                assert_eq!(bcs::from_bytes::<i32>(&reply)?, round);
+                let reply = client.recv_response(first).await?.expect("Echo replies");
+                assert_eq!(
+                    bcs::from_bytes::<String>(&reply)?,
+                    format!("{index}/{round}/typed")
+                );
+                assert_eq!(client.weak_read().await?, format!("{index}/{round}/weak"));
+            }
+            anyhow::Ok(())
+        })
+    });
+    for session in futures_util::future::join_all(sessions).await {
+        session??;
+    }
+
+    let metrics = console.metrics();
+    assert_eq!(metrics.accepted_connections, 16);
+    assert_eq!(metrics.messages, 16 * 10 * 3);
+    assert_eq!(metrics.handler_errors, 0);
+
+    console.stop();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn disconnect_storms_leave_the_console_usable() -> anyhow::Result<()> {
+    let (console, mut client) = spawn_ephemeral(builder().max_connections(64)).await?;
+    let address = console.local_addr().expect("Console is spawned");
+
+    for _ in 0..4 {
+        let mut streams = Vec::new();
+        for index in 0..32 {
+            let mut stream = TcpStream::connect(address).await?;
+            // Some leave mid-command, with a line or a typed header cut short.
+            match index % 3 {
+                0 => stream.write_all(b"echo unfinish").await?,
+                1 => stream.write_all(&[0xC0, 0xC1, 0, 0, 1, 0, 7]).await?,
+                _ => {}
+            }
+            streams.push(stream);
+        }
+        drop(streams);
+        eventually(|| console.session_count() == 1).await?;
+    }
+
+    client.weak_send("echo still here").await?;
+    assert_eq!(client.weak_read().await?, "still here");
+    assert_eq!(console.metrics().rejected_connections, 0);
+
+    console.stop();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shutdown_lets_in_flight_commands_finish() -> anyhow::Result<()> {
+    let (console, client) = spawn_ephemeral(builder()).await?;
+    let address = console.local_addr().expect("Console is spawned");
+    let mut clients = connect_many(address, 7).await?;
+    clients.push(client);
+
+    let started = Slow::started();
+    let mut requests = Vec::new();
+    for client in &mut clients {
+        requests.push(client.send_request(Services::Slow, &200u64).await?);
+    }
+    eventually(|| Slow::started() >= started + 8).await?;
+
+    console.shutdown(Duration::from_secs(5)).await?;
+    assert_eq!(console.session_count(), 0);
+
+    for (client, id) in clients.iter_mut().zip(requests) {
+        let reply = client.recv_response(id).await?.expect("Slow replies");
+        assert_eq!(bcs::from_bytes::<String>(&reply)?, "done");
+    }
+    assert!(tcp_console::Client::new(address, None).await.is_err());
+
+    Ok(())
+}